@@ -0,0 +1,296 @@
+// Command line options, parsed by hand to keep the dependencies down
+use crate::drawing::{import_location, layout_strip, Sequence, Session, View, SESSION_FILE};
+use crate::numbers::{self, positive, real};
+
+pub const USAGE: &str = "\
+Usage: newtons-fractal [options]
+
+Options:
+  --fractal F      mandelbrot, buddhabrot for a density plot of orbits, or
+                   lyapunov for the logistic map over a and b
+                   [default: mandelbrot]
+  --load PATH      Start from a saved session, which Ctrl+S and Ctrl+O then
+                   use too [default: session.txt]. Options after it change
+                   what was loaded.
+  --import LOC     Start at a location from a .kfr or .par file,
+                   or a location string copied with Ctrl+C
+  --center X,Y     Point in the middle of the window
+  --width W        Width of the view in the fractal's coordinates
+                   [default: whatever makes the height 1]
+  --zoom Z         Magnification, the same as --width 1/Z
+  --angle DEG      Turn the view counterclockwise [default: 0]
+  --size WxH       Window size in pixels [default: 600x600]
+  --max-iter N     Iteration limit [default: 256]
+  --formula F      mandelbrot, burning-ship, tricorn, julia:X,Y or custom:NAME
+                   for one from formulas.txt [default: mandelbrot]
+  --palette P      classic, fire, ice or gray [default: classic]
+  --mapping M      How the view is laid out: flat, exponential for a log-polar
+                   strip, inverse or sphere to see infinity [default: flat]
+  --sequence S     Order a and b are used in by lyapunov, like AABAB
+                   [default: AB]
+  --threads N      Number of render threads [default: twice the cores]
+  --output PATH    Render without showing a window, save it to PATH
+                   as a .ppm or .bmp, and exit
+  --strip PATH     Like --output, but render a log-polar strip to PATH as a
+                   .ppm, zooming from the whole set down to the view. It holds
+                   a zoom video for frames the size of --size.
+  --video STRIP    Make the frames of a zoom video from a --strip, the size
+                   of --size, and write them next to it as .ppm files
+  --frames N       Number of frames --video makes [default: 600]
+  -h, --help       Print this help
+";
+
+// Which op draws in the main window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fractal {
+    Mandelbrot,
+    Buddhabrot,
+    Lyapunov,
+}
+
+impl std::str::FromStr for Fractal {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "mandelbrot" => Ok(Self::Mandelbrot),
+            "buddhabrot" => Ok(Self::Buddhabrot),
+            "lyapunov" => Ok(Self::Lyapunov),
+            _ => Err(format!("Unknown fractal: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Options {
+    pub fractal: Fractal,
+    pub sequence: Sequence,
+    pub session: Session,
+    pub session_file: String,
+    pub threads: Option<usize>,
+    pub output: Option<String>,
+    // Whether output is a --strip, which is written a band of rows at a time
+    pub strip: bool,
+    // Whether the view was placed by a session, a location or any of
+    // --center, --width and --zoom, rather than left at the default
+    pub view_given: bool,
+}
+
+pub enum Command {
+    Run(Box<Options>),
+    // Frames from a strip, which needs no window
+    Video {
+        strip: String,
+        frames: usize,
+        width: usize,
+        height: usize,
+    },
+    Help,
+}
+
+pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Command, String> {
+    let mut options = Options {
+        fractal: Fractal::Mandelbrot,
+        sequence: Sequence::default(),
+        session: Session::default(),
+        session_file: SESSION_FILE.to_string(),
+        threads: None,
+        output: None,
+        strip: false,
+        view_given: false,
+    };
+    let mut width = None;
+    let mut zoom = None;
+    // Whether the view's width came from a session or a location
+    let mut framed = false;
+    let mut video = None;
+    let mut frames = 600;
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            return Ok(Command::Help);
+        }
+        // Values can be given as --name value or --name=value
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        if !name.starts_with("--") {
+            return Err(format!("Unexpected argument: {}", name));
+        }
+        let value = match value.or_else(|| args.next()) {
+            Some(value) => value,
+            None => return Err(format!("{} needs a value", name)),
+        };
+        let value = value.as_str();
+        let view = &mut options.session.view;
+        match name.as_str() {
+            "--fractal" => options.fractal = value.parse()?,
+            "--load" => {
+                options.session = Session::load(value)?;
+                options.session_file = value.to_string();
+                // A width from before doesn't count anymore
+                (width, zoom) = (None, None);
+                framed = true;
+            }
+            "--import" => {
+                let lower = value.to_lowercase();
+                let text = if lower.ends_with(".kfr") || lower.ends_with(".par") {
+                    std::fs::read_to_string(value).map_err(|e| format!("{}: {}", value, e))?
+                } else {
+                    value.to_string()
+                };
+                *view = import_location(&text, view.clone())
+                    .map_err(|e| format!("{}: {}", value, e))?;
+                (width, zoom) = (None, None);
+                framed = true;
+            }
+            "--center" => {
+                let (x, y) = value
+                    .split_once(',')
+                    .ok_or(format!("--center needs X,Y, got {}", value))?;
+                view.x = option(&name, x.parse())?;
+                view.y = option(&name, y.parse())?;
+                options.view_given = true;
+            }
+            "--width" => width = Some(option(&name, real(value).and_then(positive))?),
+            "--zoom" => zoom = Some(option(&name, real(value).and_then(positive))?),
+            "--angle" => view.angle = option(&name, real(value))?,
+            "--size" => {
+                let (w, h) = value
+                    .split_once('x')
+                    .ok_or(format!("--size needs WxH, got {}", value))?;
+                options.session.width = option(&name, numbers::parse(w).and_then(positive))?;
+                options.session.height = option(&name, numbers::parse(h).and_then(positive))?;
+            }
+            "--max-iter" => {
+                view.max_iter = option(&name, numbers::parse(value).and_then(positive))?
+            }
+            "--formula" => view.formula = value.parse()?,
+            "--palette" => view.palette = value.parse()?,
+            "--mapping" => options.session.mapping = value.parse()?,
+            "--sequence" => options.sequence = value.parse()?,
+            "--threads" => {
+                options.threads = Some(option(&name, numbers::parse(value).and_then(positive))?)
+            }
+            "--output" => {
+                let lower = value.to_lowercase();
+                if !lower.ends_with(".ppm") && !lower.ends_with(".bmp") {
+                    return Err(format!("--output must end in .ppm or .bmp, got {}", value));
+                }
+                options.output = Some(value.to_string());
+                options.strip = false;
+            }
+            "--strip" => {
+                if !value.to_lowercase().ends_with(".ppm") {
+                    return Err(format!("--strip must end in .ppm, got {}", value));
+                }
+                options.output = Some(value.to_string());
+                options.strip = true;
+            }
+            "--video" => video = Some(value.to_string()),
+            "--frames" => frames = option(&name, numbers::parse(value).and_then(positive))?,
+            _ => return Err(format!("Unknown option: {}", name)),
+        }
+    }
+    let view = &mut options.session.view;
+    match (width, zoom) {
+        (Some(_), Some(_)) => return Err("Only one of --width and --zoom can be given".to_string()),
+        (Some(width), None) => view.width = width,
+        (None, Some(zoom)) => view.width = View::default().width / zoom,
+        // Without anything to go by, the height of the view is 1
+        (None, None) if !framed => {
+            view.width = options.session.width as f64 / options.session.height as f64
+        }
+        (None, None) => (),
+    }
+    options.view_given |= framed || width.is_some() || zoom.is_some();
+    if let Some(strip) = video {
+        return Ok(Command::Video {
+            strip,
+            frames,
+            width: options.session.width as usize,
+            height: options.session.height as usize,
+        });
+    }
+    if options.strip {
+        layout_strip(&mut options.session)?;
+    }
+    Ok(Command::Run(Box::new(options)))
+}
+
+// Puts the option in front of what was wrong with its value
+fn option<T>(name: &str, value: Result<T, String>) -> Result<T, String> {
+    value.map_err(|e| format!("{}: {}", name, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(args: &[&str]) -> Result<Options, String> {
+        match parse(args.iter().map(|arg| arg.to_string()))? {
+            Command::Run(options) => Ok(*options),
+            _ => Err("Not a run".to_string()),
+        }
+    }
+
+    #[test]
+    fn the_default_view_is_one_high() {
+        let options = run(&["--size", "800x400"]).unwrap();
+        assert_eq!(options.session.view.width, 2.);
+        assert!(!options.view_given);
+    }
+
+    #[test]
+    fn width_and_zoom_are_applied_after_the_size() {
+        // The order on the command line doesn't matter
+        let a = run(&["--width", "0.5", "--size", "800x400"]).unwrap();
+        let b = run(&["--size=800x400", "--width=0.5"]).unwrap();
+        assert_eq!(a.session.view.width, 0.5);
+        assert_eq!(b.session.view.width, 0.5);
+        let zoomed = run(&["--zoom", "4"]).unwrap();
+        assert_eq!(zoomed.session.view.width, View::default().width / 4.);
+        assert!(zoomed.view_given);
+    }
+
+    #[test]
+    fn width_and_zoom_together_are_an_error() {
+        let e = run(&["--width", "2", "--zoom", "2"]).err().unwrap();
+        assert_eq!(e, "Only one of --width and --zoom can be given");
+    }
+
+    #[test]
+    fn center_places_the_view() {
+        let options = run(&["--center", "-0.75,0.1"]).unwrap();
+        let view = options.session.view;
+        assert_eq!(
+            (view.x.to_string(), view.y.to_string()),
+            ("-0.75".into(), "0.1".into())
+        );
+        assert!(options.view_given);
+    }
+
+    #[test]
+    fn bad_values_name_the_option() {
+        let e = |args: &[&str]| run(args).err().unwrap();
+        assert_eq!(e(&["--max-iter", "x"]), "--max-iter: Not a valid number: x");
+        assert_eq!(e(&["--width", "inf"]), "--width: Not a finite number: inf");
+        assert_eq!(
+            e(&["--width", "0"]),
+            "--width: Has to be more than 0, got 0"
+        );
+        assert_eq!(e(&["--center", "1"]), "--center needs X,Y, got 1");
+        assert_eq!(e(&["--size"]), "--size needs a value");
+        assert_eq!(
+            e(&["--output", "out.png"]),
+            "--output must end in .ppm or .bmp, got out.png"
+        );
+        assert_eq!(e(&["--colour", "red"]), "Unknown option: --colour");
+        assert_eq!(e(&["view"]), "Unexpected argument: view");
+    }
+
+    #[test]
+    fn help_wins_over_everything() {
+        let args = ["--max-iter", "5", "-h", "--bogus"].map(String::from);
+        assert!(matches!(parse(args.iter().cloned()), Ok(Command::Help)));
+    }
+}
@@ -1,10 +1,34 @@
+mod bindings;
+mod buddhabrot;
+mod complex;
+mod kernel;
+mod location;
+mod lyapunov;
 mod mandelbrot;
+mod mapping;
+mod real;
+mod script;
+mod session;
+mod settings;
+mod strip;
+mod symbolic;
+mod view;
+mod viewport;
+pub use buddhabrot::Buddhabrot;
+pub use location::import as import_location;
+pub use lyapunov::{Lyapunov, Sequence};
 pub use mandelbrot::Mandelbrot;
+pub use session::{Session, SESSION_FILE};
+pub use settings::{Settings, PANEL_WIDTH};
+pub use strip::{layout as layout_strip, render as render_strip, video as video_from_strip};
+pub use view::View;
 
 pub mod basic_render_op {
-    use crate::events::SdlEvent;
-    use crate::rendering::{Pixel, Pixels, RenderOp};
+    use super::bindings::Bindings;
+    use crate::events::{MainEvent, SdlEvent};
+    use crate::rendering::{CancelToken, Pixels, RenderOp, RenderStats, Tile};
     use crate::windows::Window;
+    use sdl2::event::{Event, WindowEvent};
     use sdl2::rect::Rect;
     use std::sync::{
         atomic::{AtomicBool, Ordering},
@@ -14,35 +38,78 @@ pub mod basic_render_op {
     pub trait BasicOpImp: Send + Sync {
         fn get_op(&self) -> &BasicOp;
         fn get_op_mut(&mut self) -> &mut BasicOp;
-        fn draw(&self, pixels: &mut [Pixel], ind: usize, slice: usize);
+        fn draw(&self, tile: &mut Tile, token: &CancelToken);
         fn modify_data(&mut self);
         fn handle_events(&mut self) -> bool;
-        fn ind_to_xy(ind: usize, pitch: usize) -> (usize, usize) {
-            let x = ind % pitch;
-            let y = ind / pitch;
-            (x, y)
+        fn is_complete(&self) -> bool;
+        fn pending_offset(&self) -> (i32, i32) {
+            (0, 0)
+        }
+        fn wants_idle(&self) -> bool {
+            false
+        }
+        // Keys of ops without bindings never change what they draw
+        fn bindings(&self) -> Option<&Bindings> {
+            None
+        }
+        // By default a key bound to something that changes the view, or
+        // any scroll, drag or resize of our window, or a new setting,
+        // makes the frame being rendered out of date
+        fn interrupted_by(&self, event: &SdlEvent) -> bool {
+            match event {
+                SdlEvent::User(MainEvent::Setting(_)) => true,
+                SdlEvent::Event(Event::MouseMotion {
+                    window_id,
+                    mousestate,
+                    ..
+                }) => mousestate.left() && *window_id == self.get_op().id(),
+                SdlEvent::Event(Event::KeyDown {
+                    window_id,
+                    scancode: Some(scancode),
+                    keymod,
+                    ..
+                }) => {
+                    let action = self
+                        .bindings()
+                        .and_then(|bindings| bindings.action(*scancode, *keymod));
+                    *window_id == self.get_op().id() && action.is_some_and(|a| a.changes_view())
+                }
+                SdlEvent::Event(Event::MouseWheel { window_id, .. })
+                | SdlEvent::Event(Event::MouseButtonUp { window_id, .. })
+                | SdlEvent::Event(Event::Window {
+                    win_event: WindowEvent::Resized(..),
+                    window_id,
+                    ..
+                }) => *window_id == self.get_op().id(),
+                _ => false,
+            }
         }
     }
 
     pub struct BasicOp {
-        pub window: Arc<Mutex<Window>>,
+        // Ops rendering straight to a file have no window
+        pub window: Option<Arc<Mutex<Window>>>,
         pub window_id: u32,
         pub rect: Rect,
         pub buffers: [Pixels; 2],
         pub buffer_ind: usize,
         pub event_list: Mutex<Vec<SdlEvent>>,
         pub open: AtomicBool,
+        pub stats: RenderStats,
     }
 
     impl BasicOp {
         pub fn init(
-            window: Arc<Mutex<Window>>,
+            window: Option<Arc<Mutex<Window>>>,
             width: usize,
             height: usize,
             x: isize,
             y: isize,
         ) -> Self {
-            let window_id = window.lock().unwrap().id();
+            let window_id = match &window {
+                Some(window) => window.lock().unwrap().id(),
+                None => 0,
+            };
             let rect = Rect::new(x as i32, y as i32, width as u32, height as u32);
             let buffer1 = Pixels::new(width, height).unwrap();
             let buffer2 = Pixels::new(width, height).unwrap();
@@ -57,15 +124,45 @@ pub mod basic_render_op {
                 buffer_ind,
                 event_list,
                 open: AtomicBool::new(true),
+                stats: RenderStats::default(),
             }
         }
         pub fn id(&self) -> u32 {
             self.window_id
         }
+        // Sends an event to main and every op. Without a window
+        // there are no events, so there's nobody to tell either.
+        pub fn send(&self, event: MainEvent) {
+            let window = match &self.window {
+                Some(window) => window.lock().unwrap(),
+                None => return,
+            };
+            let sent = window
+                .canvas()
+                .window()
+                .subsystem()
+                .sdl()
+                .event()
+                .and_then(|events| events.event_sender().push_custom_event(event));
+            if let Err(e) = sent {
+                println!("Couldn't send an event: {}", e);
+            }
+        }
+        // Size of the window, or the one given when there's no window
+        pub fn size(
+            window: Option<&Arc<Mutex<Window>>>,
+            width: u32,
+            height: u32,
+        ) -> (usize, usize) {
+            match window {
+                Some(window) => window.lock().unwrap().size(),
+                None => (width as usize, height as usize),
+            }
+        }
     }
 
     impl<T: BasicOpImp> RenderOp for T {
-        fn get_window(&self) -> Arc<Mutex<Window>> {
+        fn get_window(&self) -> Option<Arc<Mutex<Window>>> {
             self.get_op().window.clone()
         }
         fn get_rect(&self) -> &Rect {
@@ -83,8 +180,8 @@ pub mod basic_render_op {
             self.get_op_mut().buffer_ind += 1;
             self.get_op_mut().buffer_ind %= 2;
         }
-        fn draw(&self, pixels: &mut [Pixel], ind: usize, pitch: usize) {
-            self.draw(pixels, ind, pitch);
+        fn draw(&self, tile: &mut Tile, token: &CancelToken) {
+            self.draw(tile, token);
         }
         fn modify_data(&mut self) {
             self.modify_data();
@@ -92,6 +189,9 @@ pub mod basic_render_op {
         fn handle_events(&mut self) -> bool {
             self.handle_events()
         }
+        fn interrupted_by(&self, event: &SdlEvent) -> bool {
+            self.interrupted_by(event)
+        }
         fn push_event(&self, event: SdlEvent) {
             let mut list = self.get_op().event_list.lock().unwrap();
             list.push(event);
@@ -104,5 +204,20 @@ pub mod basic_render_op {
             let s = self.get_op();
             s.open.load(Ordering::Relaxed)
         }
+        fn get_stats(&self) -> &RenderStats {
+            &self.get_op().stats
+        }
+        fn set_stats(&mut self, stats: RenderStats) {
+            self.get_op_mut().stats = stats;
+        }
+        fn is_complete(&self) -> bool {
+            self.is_complete()
+        }
+        fn pending_offset(&self) -> (i32, i32) {
+            self.pending_offset()
+        }
+        fn wants_idle(&self) -> bool {
+            self.wants_idle()
+        }
     }
 }
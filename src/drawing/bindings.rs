@@ -0,0 +1,627 @@
+// Every key binding in one place. Input is looked up here, and the
+// help overlay is written from here, so the two can't disagree.
+//
+// The defaults can be changed in controls.txt, which looks like
+//
+//     # Lines outside of a section are for every op
+//     move_amount = 0.2
+//     bind Left = move_left
+//     bind Ctrl+W = none
+//     # and a section only changes the op with that name
+//     [mandelbrot]
+//     scale_coarse = 1.25
+//     bind Ctrl+0 = go_to_bookmark 1
+use crate::numbers;
+use sdl2::keyboard::{Mod, Scancode};
+use std::str::FromStr;
+
+const CONFIG_FILE: &str = "controls.txt";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    MoveUp,
+    MoveLeft,
+    MoveDown,
+    MoveRight,
+    ZoomIn,
+    ZoomOut,
+    ZoomInDouble,
+    ZoomOutDouble,
+    RotateLeft,
+    RotateRight,
+    ResetRotation,
+    MoreIterations,
+    FewerIterations,
+    NextSampling,
+    NextFormula,
+    NextPalette,
+    NextColoring,
+    NextMapping,
+    Back,
+    Forward,
+    RecallBookmark(usize),
+    SaveBookmark(usize),
+    SaveSession,
+    LoadSession,
+    CopyLocation,
+    PasteLocation,
+    ExportLocation,
+    ToggleHud,
+    ToggleStats,
+    ToggleHelp,
+    ShowSettings,
+}
+
+// Names of the actions without a bookmark slot, as used in the config file
+const ACTION_NAMES: [(&str, Action); 29] = [
+    ("move_up", Action::MoveUp),
+    ("move_left", Action::MoveLeft),
+    ("move_down", Action::MoveDown),
+    ("move_right", Action::MoveRight),
+    ("zoom_in", Action::ZoomIn),
+    ("zoom_out", Action::ZoomOut),
+    ("zoom_in_double", Action::ZoomInDouble),
+    ("zoom_out_double", Action::ZoomOutDouble),
+    ("rotate_left", Action::RotateLeft),
+    ("rotate_right", Action::RotateRight),
+    ("reset_rotation", Action::ResetRotation),
+    ("more_iterations", Action::MoreIterations),
+    ("fewer_iterations", Action::FewerIterations),
+    ("next_sampling", Action::NextSampling),
+    ("next_formula", Action::NextFormula),
+    ("next_palette", Action::NextPalette),
+    ("next_coloring", Action::NextColoring),
+    ("next_mapping", Action::NextMapping),
+    ("back", Action::Back),
+    ("forward", Action::Forward),
+    ("save_session", Action::SaveSession),
+    ("load_session", Action::LoadSession),
+    ("copy_location", Action::CopyLocation),
+    ("paste_location", Action::PasteLocation),
+    ("export_location", Action::ExportLocation),
+    ("toggle_hud", Action::ToggleHud),
+    ("toggle_stats", Action::ToggleStats),
+    ("toggle_help", Action::ToggleHelp),
+    ("show_settings", Action::ShowSettings),
+];
+
+impl Action {
+    // Whether doing it makes the frame being rendered out of date
+    pub fn changes_view(self) -> bool {
+        !matches!(
+            self,
+            Self::SaveBookmark(_)
+                | Self::SaveSession
+                | Self::CopyLocation
+                | Self::ExportLocation
+                | Self::ToggleHud
+                | Self::ToggleStats
+                | Self::ToggleHelp
+                | Self::ShowSettings
+        )
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Self::MoveUp => "Move up",
+            Self::MoveLeft => "Move left",
+            Self::MoveDown => "Move down",
+            Self::MoveRight => "Move right",
+            Self::ZoomIn => "Zoom in",
+            Self::ZoomOut => "Zoom out",
+            Self::ZoomInDouble => "Zoom in 2x",
+            Self::ZoomOutDouble => "Zoom out 2x",
+            Self::RotateLeft => "Turn counterclockwise",
+            Self::RotateRight => "Turn clockwise",
+            Self::ResetRotation => "Undo turning",
+            Self::MoreIterations => "Double max iterations",
+            Self::FewerIterations => "Halve max iterations",
+            Self::NextSampling => "Next sampling mode",
+            Self::NextFormula => "Next formula",
+            Self::NextPalette => "Next palette",
+            Self::NextColoring => "Next coloring",
+            Self::NextMapping => "Next mapping",
+            Self::Back => "Go back",
+            Self::Forward => "Go forward",
+            Self::RecallBookmark(_) => "Go to bookmark",
+            Self::SaveBookmark(_) => "Save bookmark",
+            Self::SaveSession => "Save the session",
+            Self::LoadSession => "Load the saved session",
+            Self::CopyLocation => "Copy the location",
+            Self::PasteLocation => "Go to a pasted location",
+            Self::ExportLocation => "Write .kfr and .par files",
+            Self::ToggleHud => "Show or hide the hud",
+            Self::ToggleStats => "Show or hide the render stats",
+            Self::ToggleHelp => "Show or hide this help",
+            Self::ShowSettings => "Open the settings window again",
+        }
+    }
+}
+
+// Bookmarks are written with their slot, as in "save_bookmark 3"
+impl FromStr for Action {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut parts = s.split_whitespace();
+        let name = parts.next().unwrap_or("");
+        let slot = parts.next();
+        let bookmark = |make: fn(usize) -> Action| {
+            let slot: usize = slot
+                .and_then(|slot| slot.parse().ok())
+                .filter(|slot| (1..=9).contains(slot))
+                .ok_or(format!("{} needs a slot from 1 to 9", name))?;
+            Ok(make(slot - 1))
+        };
+        match name {
+            "go_to_bookmark" => bookmark(Action::RecallBookmark),
+            "save_bookmark" => bookmark(Action::SaveBookmark),
+            _ => ACTION_NAMES
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|&(_, action)| action)
+                .ok_or(format!("Unknown action: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Modifier {
+    Plain,
+    Shift,
+    Ctrl,
+}
+
+impl Modifier {
+    // Only shift and ctrl count, so caps or num lock don't get in the way
+    fn from_mod(keymod: Mod) -> Self {
+        if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) {
+            Self::Ctrl
+        } else if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+            Self::Shift
+        } else {
+            Self::Plain
+        }
+    }
+}
+
+struct Binding {
+    scancode: Scancode,
+    modifier: Modifier,
+    action: Action,
+}
+
+use Action::*;
+use Modifier::*;
+const DEFAULTS: [(Scancode, Modifier, Action); 48] = [
+    (Scancode::W, Plain, MoveUp),
+    (Scancode::A, Plain, MoveLeft),
+    (Scancode::S, Plain, MoveDown),
+    (Scancode::D, Plain, MoveRight),
+    (Scancode::Q, Plain, ZoomIn),
+    (Scancode::E, Plain, ZoomOut),
+    (Scancode::Z, Plain, ZoomInDouble),
+    (Scancode::X, Plain, ZoomOutDouble),
+    (Scancode::Left, Plain, RotateLeft),
+    (Scancode::Right, Plain, RotateRight),
+    (Scancode::R, Plain, ResetRotation),
+    (Scancode::Up, Plain, MoreIterations),
+    (Scancode::Down, Plain, FewerIterations),
+    (Scancode::M, Plain, NextSampling),
+    (Scancode::F, Plain, NextFormula),
+    (Scancode::P, Plain, NextPalette),
+    (Scancode::C, Plain, NextColoring),
+    (Scancode::V, Plain, NextMapping),
+    (Scancode::Backspace, Plain, Back),
+    (Scancode::Backspace, Shift, Forward),
+    (Scancode::Num1, Plain, RecallBookmark(0)),
+    (Scancode::Num2, Plain, RecallBookmark(1)),
+    (Scancode::Num3, Plain, RecallBookmark(2)),
+    (Scancode::Num4, Plain, RecallBookmark(3)),
+    (Scancode::Num5, Plain, RecallBookmark(4)),
+    (Scancode::Num6, Plain, RecallBookmark(5)),
+    (Scancode::Num7, Plain, RecallBookmark(6)),
+    (Scancode::Num8, Plain, RecallBookmark(7)),
+    (Scancode::Num9, Plain, RecallBookmark(8)),
+    (Scancode::Num1, Ctrl, SaveBookmark(0)),
+    (Scancode::Num2, Ctrl, SaveBookmark(1)),
+    (Scancode::Num3, Ctrl, SaveBookmark(2)),
+    (Scancode::Num4, Ctrl, SaveBookmark(3)),
+    (Scancode::Num5, Ctrl, SaveBookmark(4)),
+    (Scancode::Num6, Ctrl, SaveBookmark(5)),
+    (Scancode::Num7, Ctrl, SaveBookmark(6)),
+    (Scancode::Num8, Ctrl, SaveBookmark(7)),
+    (Scancode::Num9, Ctrl, SaveBookmark(8)),
+    (Scancode::S, Ctrl, SaveSession),
+    (Scancode::O, Ctrl, LoadSession),
+    (Scancode::C, Ctrl, CopyLocation),
+    (Scancode::V, Ctrl, PasteLocation),
+    (Scancode::E, Ctrl, ExportLocation),
+    (Scancode::Tab, Plain, ToggleHud),
+    (Scancode::T, Plain, ToggleStats),
+    (Scancode::F1, Plain, ToggleHelp),
+    (Scancode::H, Plain, ToggleHelp),
+    (Scancode::F2, Plain, ShowSettings),
+];
+
+// Things the mouse does, which aren't looked up like keys
+const MOUSE_HELP: [(&str, &str); 4] = [
+    ("Drag", "Move the view"),
+    ("Ctrl+Drag", "Box zoom, dragged right to left zooms out"),
+    ("Right Drag", "Turn the view around its center"),
+    ("Wheel", "Zoom at the cursor"),
+];
+
+pub struct Bindings {
+    list: Vec<Binding>,
+    // Fraction of the window a move goes
+    pub move_amount: f64,
+    // How much one zoom in or out scales by
+    pub scale_coarse: f64,
+    // Degrees one turn goes
+    pub rotate_amount: f64,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        Bindings {
+            list: DEFAULTS
+                .iter()
+                .map(|&(scancode, modifier, action)| Binding {
+                    scancode,
+                    modifier,
+                    action,
+                })
+                .collect(),
+            move_amount: 0.1,
+            scale_coarse: 1.6,
+            rotate_amount: 15.,
+        }
+    }
+}
+
+impl Bindings {
+    // The defaults with the config file on top. Settings in the
+    // section named op win over the ones outside of any section.
+    pub fn load(op: &str) -> Result<Self, String> {
+        match std::fs::read_to_string(CONFIG_FILE) {
+            Ok(text) => Self::parse(&text, op).map_err(|e| format!("{}{}", CONFIG_FILE, e)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(format!("{}: {}", CONFIG_FILE, e)),
+        }
+    }
+
+    // Errors start with the line number, so load can put the file name in front
+    fn parse(text: &str, op: &str) -> Result<Self, String> {
+        let mut bindings = Self::default();
+        let mut global = vec![];
+        let mut ours = vec![];
+        let mut section = None;
+        for (line_num, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = Some(name.trim().to_string());
+                continue;
+            }
+            match &section {
+                None => global.push((line_num, line)),
+                Some(name) if name == op => ours.push((line_num, line)),
+                Some(_) => (),
+            }
+        }
+        for (line_num, line) in global.into_iter().chain(ours) {
+            bindings
+                .apply(line)
+                .map_err(|e| format!(":{}: {}", line_num + 1, e))?;
+        }
+        Ok(bindings)
+    }
+
+    fn apply(&mut self, line: &str) -> Result<(), String> {
+        let (name, value) = line
+            .split_once('=')
+            .ok_or("Expected a setting like name = value")?;
+        let (name, value) = (name.trim(), value.trim());
+        let number = || numbers::real(value);
+        match name {
+            "move_amount" => self.move_amount = number()?,
+            "scale_coarse" => {
+                // Anything else would never zoom, or flip the view over
+                let scale = number()?;
+                if scale <= 0. || scale == 1. {
+                    return Err(format!(
+                        "scale_coarse has to be above 0 and not 1, got {}",
+                        value
+                    ));
+                }
+                self.scale_coarse = scale;
+            }
+            "rotate_amount" => self.rotate_amount = number()?,
+            _ => {
+                let key = name
+                    .strip_prefix("bind ")
+                    .ok_or(format!("Unknown setting: {}", name))?;
+                let (scancode, modifier) = parse_key(key.trim())?;
+                // Whatever the key did before is replaced
+                self.list
+                    .retain(|b| b.scancode != scancode || b.modifier != modifier);
+                if value != "none" {
+                    self.list.push(Binding {
+                        scancode,
+                        modifier,
+                        action: value.parse()?,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn action(&self, scancode: Scancode, keymod: Mod) -> Option<Action> {
+        let modifier = Modifier::from_mod(keymod);
+        self.list
+            .iter()
+            .find(|b| b.scancode == scancode && b.modifier == modifier)
+            .map(|b| b.action)
+    }
+
+    // One line per action. Runs of bindings doing the same thing, like the
+    // bookmark keys, are joined into a single line.
+    pub fn help_lines(&self) -> Vec<String> {
+        let list = &self.list;
+        let mut entries: Vec<(String, &str)> = vec![];
+        let mut i = 0;
+        while i < list.len() {
+            let first = &list[i];
+            let description = first.action.description();
+            let mut last = first;
+            while i + 1 < list.len()
+                && list[i + 1].action.description() == description
+                && list[i + 1].modifier == first.modifier
+            {
+                i += 1;
+                last = &list[i];
+            }
+            let mut keys = key_name(first);
+            if !std::ptr::eq(first, last) {
+                // Keys that all mean the same are listed with a slash, and ones
+                // that each do their own part of it are given as a range
+                let separator = if first.action == last.action {
+                    "/"
+                } else {
+                    "-"
+                };
+                keys = format!("{}{}{}", keys, separator, scancode_name(last.scancode));
+            }
+            entries.push((keys, description));
+            i += 1;
+        }
+        for (keys, description) in MOUSE_HELP {
+            entries.push((keys.to_string(), description));
+        }
+        entries.push(("Escape".to_string(), "Quit"));
+        let width = entries
+            .iter()
+            .map(|(keys, _)| keys.len())
+            .max()
+            .unwrap_or(0);
+        entries
+            .iter()
+            .map(|(keys, description)| format!("{:<width$}  {}", keys, description, width = width))
+            .collect()
+    }
+}
+
+fn key_name(binding: &Binding) -> String {
+    let prefix = match binding.modifier {
+        Plain => "",
+        Shift => "Shift+",
+        Ctrl => "Ctrl+",
+    };
+    format!("{}{}", prefix, scancode_name(binding.scancode))
+}
+
+// Keys are named like in the help, as in "W", "Shift+Backspace" or "Ctrl+1"
+fn parse_key(key: &str) -> Result<(Scancode, Modifier), String> {
+    let (modifier, name) = if let Some(name) = key.strip_prefix("Shift+") {
+        (Shift, name)
+    } else if let Some(name) = key.strip_prefix("Ctrl+") {
+        (Ctrl, name)
+    } else {
+        (Plain, key)
+    };
+    let scancode = scancode_from_name(name).ok_or(format!("Unknown key: {}", name))?;
+    Ok((scancode, modifier))
+}
+
+// SDL's names for the keys. The tests run without SDL, so there the keys
+// go by their names in Rust, and only the ones the tests use are known.
+#[cfg(not(test))]
+fn scancode_name(scancode: Scancode) -> String {
+    scancode.name().to_string()
+}
+
+#[cfg(not(test))]
+fn scancode_from_name(name: &str) -> Option<Scancode> {
+    Scancode::from_name(name)
+}
+
+#[cfg(test)]
+fn scancode_name(scancode: Scancode) -> String {
+    format!("{:?}", scancode)
+}
+
+#[cfg(test)]
+fn scancode_from_name(name: &str) -> Option<Scancode> {
+    let unbound = [Scancode::Num0, Scancode::Y];
+    DEFAULTS
+        .iter()
+        .map(|&(scancode, _, _)| scancode)
+        .chain(unbound.iter().copied())
+        .find(|&scancode| scancode_name(scancode) == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_shift_and_ctrl_pick_the_binding() {
+        let bindings = Bindings::default();
+        let action = |keymod| bindings.action(Scancode::Backspace, keymod);
+        assert_eq!(action(Mod::NOMOD), Some(Back));
+        assert_eq!(action(Mod::CAPSMOD | Mod::NUMMOD), Some(Back));
+        assert_eq!(action(Mod::RSHIFTMOD), Some(Forward));
+        // Ctrl comes first when both are held
+        assert_eq!(
+            bindings.action(Scancode::Num3, Mod::LCTRLMOD | Mod::LSHIFTMOD),
+            Some(SaveBookmark(2))
+        );
+        assert_eq!(
+            bindings.action(Scancode::Num3, Mod::NOMOD),
+            Some(RecallBookmark(2))
+        );
+        assert_eq!(bindings.action(Scancode::Y, Mod::NOMOD), None);
+    }
+
+    #[test]
+    fn help_joins_keys_that_do_the_same() {
+        let lines = Bindings::default().help_lines();
+        let line = |description: &str| -> String {
+            let found = lines.iter().find(|line| line.ends_with(description));
+            found
+                .unwrap()
+                .split_whitespace()
+                .next()
+                .unwrap()
+                .to_string()
+        };
+        // Each its own slot, so a range
+        assert_eq!(line("Go to bookmark"), "Num1-Num9");
+        assert_eq!(line("Save bookmark"), "Ctrl+Num1-Num9");
+        // The very same action, so either one
+        assert_eq!(line("Show or hide this help"), "F1/H");
+        assert_eq!(line("Go forward"), "Shift+Backspace");
+        assert_eq!(line("Zoom at the cursor"), "Wheel");
+        assert_eq!(
+            lines.last().unwrap().split_whitespace().collect::<Vec<_>>(),
+            ["Escape", "Quit"]
+        );
+        // One line per bookmark action, not nine
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|line| line.contains("bookmark"))
+                .count(),
+            2
+        );
+        // Descriptions all start in the same column
+        let column = |description: &str| {
+            let line = lines.iter().find(|line| line.ends_with(description));
+            line.unwrap().len() - description.len()
+        };
+        assert_eq!(column("Quit"), column("Move up"));
+        assert_eq!(
+            column("Quit"),
+            column("Box zoom, dragged right to left zooms out")
+        );
+    }
+
+    #[test]
+    fn sections_win_over_the_lines_for_every_op() {
+        let text = "\
+            # for everyone
+            move_amount = 0.2
+            rotate_amount = 5
+            [buddhabrot]
+            move_amount = 0.4
+            [mandelbrot]
+            rotate_amount = 30
+            scale_coarse = 1.25
+        ";
+        let mandelbrot = Bindings::parse(text, "mandelbrot").unwrap();
+        assert_eq!(mandelbrot.move_amount, 0.2);
+        assert_eq!(mandelbrot.rotate_amount, 30.);
+        assert_eq!(mandelbrot.scale_coarse, 1.25);
+        let buddhabrot = Bindings::parse(text, "buddhabrot").unwrap();
+        assert_eq!(buddhabrot.move_amount, 0.4);
+        assert_eq!(buddhabrot.rotate_amount, 5.);
+        assert_eq!(buddhabrot.scale_coarse, Bindings::default().scale_coarse);
+        // Even when the section comes first in the file
+        let text = "[lyapunov]\nmove_amount = 0.3\n[mandelbrot]\nmove_amount = 0.5";
+        assert_eq!(Bindings::parse(text, "lyapunov").unwrap().move_amount, 0.3);
+    }
+
+    #[test]
+    fn keys_take_a_modifier() {
+        assert_eq!(parse_key("W"), Ok((Scancode::W, Plain)));
+        assert_eq!(
+            parse_key("Shift+Backspace"),
+            Ok((Scancode::Backspace, Shift))
+        );
+        assert_eq!(parse_key("Ctrl+Num0"), Ok((Scancode::Num0, Ctrl)));
+        assert_eq!(parse_key("Alt+W"), Err("Unknown key: Alt+W".to_string()));
+        assert_eq!(parse_key("Ctrl+"), Err("Unknown key: ".to_string()));
+    }
+
+    #[test]
+    fn binding_a_key_replaces_what_it_did() {
+        let text = "bind Q = move_left\nbind Ctrl+Num0 = go_to_bookmark 3\nbind Ctrl+S = none";
+        let bindings = Bindings::parse(text, "mandelbrot").unwrap();
+        let action = |scancode, keymod| bindings.action(scancode, keymod);
+        assert_eq!(action(Scancode::Q, Mod::NOMOD), Some(MoveLeft));
+        assert_eq!(
+            action(Scancode::Num0, Mod::LCTRLMOD),
+            Some(RecallBookmark(2))
+        );
+        assert_eq!(action(Scancode::S, Mod::LCTRLMOD), None);
+        // The same key with another modifier keeps its own binding
+        assert_eq!(action(Scancode::S, Mod::NOMOD), Some(MoveDown));
+        // The old key for the action still works too
+        assert_eq!(action(Scancode::A, Mod::NOMOD), Some(MoveLeft));
+        // Unbound keys are gone from the help
+        let help = bindings.help_lines();
+        assert!(!help.iter().any(|line| line.ends_with("Save the session")));
+    }
+
+    #[test]
+    fn amounts_have_to_be_usable() {
+        let e = |text: &str| Bindings::parse(text, "mandelbrot").err().unwrap();
+        assert_eq!(e("move_amount = inf"), ":1: Not a finite number: inf");
+        assert_eq!(e("\nrotate_amount = NaN"), ":2: Not a finite number: NaN");
+        assert_eq!(
+            e("scale_coarse = 1"),
+            ":1: scale_coarse has to be above 0 and not 1, got 1"
+        );
+        assert_eq!(
+            e("scale_coarse = -2"),
+            ":1: scale_coarse has to be above 0 and not 1, got -2"
+        );
+        // Zooming out on the key for zooming in is allowed
+        let bindings = Bindings::parse("scale_coarse = 0.5", "mandelbrot").unwrap();
+        assert_eq!(bindings.scale_coarse, 0.5);
+    }
+
+    #[test]
+    fn bad_lines_say_what_is_wrong() {
+        let e = |text: &str| Bindings::parse(text, "mandelbrot").err().unwrap();
+        assert_eq!(e("zoom = 2"), ":1: Unknown setting: zoom");
+        assert_eq!(
+            e("move_amount 2"),
+            ":1: Expected a setting like name = value"
+        );
+        assert_eq!(e("bind W = fly"), ":1: Unknown action: fly");
+        assert_eq!(
+            e("bind W = save_bookmark"),
+            ":1: save_bookmark needs a slot from 1 to 9"
+        );
+        assert_eq!(
+            e("bind W = save_bookmark 10"),
+            ":1: save_bookmark needs a slot from 1 to 9"
+        );
+        // Sections for other ops are skipped without a look
+        assert!(Bindings::parse("[lyapunov]\nzoom = 2", "mandelbrot").is_ok());
+    }
+}
@@ -0,0 +1,608 @@
+// Density plots of the orbits of the Mandelbrot set. Random points c are
+// iterated from 0, and every pixel an orbit passes through is counted.
+// Every frame adds more orbits on top of the last, so the picture keeps
+// getting smoother until ORBIT_LIMIT orbits have been counted.
+use super::bindings::Bindings;
+use super::complex::Complex;
+use super::real::Real;
+use super::session::Session;
+use super::view::View;
+use super::viewport::{Controls, Viewport, ViewportOp};
+use crate::drawing::basic_render_op::{BasicOp, BasicOpImp};
+use crate::rendering::{CancelToken, RenderOpReference, Tile};
+use crate::windows::Window;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+// Orbits every tile adds per frame
+const ORBITS_PER_TILE: usize = 4000;
+
+// Refining stops after this many orbits
+const ORBIT_LIMIT: u64 = 1 << 28;
+
+// Hits are counted in fractions of this, so orbits picked by
+// Metropolis-Hastings can count for less than a whole hit
+const WHOLE_HIT: u64 = 1 << 20;
+
+// Views narrower than this are sampled with Metropolis-Hastings, since
+// hardly any random points in the whole set have orbits passing through them
+const ZOOMED_WIDTH: f64 = 1.5;
+
+// Width of a view that fits the whole set
+const WHOLE_WIDTH: f64 = 3.;
+
+// Tries at finding an orbit through the view to start a chain from
+const SEED_TRIES: usize = 10_000;
+
+// Steps a new chain takes before its orbits are counted, so it has
+// moved on from the point it happened to start at
+const BURN_IN: usize = 1000;
+
+// How bright the average pixel is, higher is darker
+const EXPOSURE: f64 = 3.;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    // Orbits that escape, in gray
+    Buddhabrot,
+    // Orbits that never escape, in gray
+    AntiBuddhabrot,
+    // Orbits that escape, with red, green and blue each only counting
+    // the ones that escape within 1, 1/10 and 1/100 of max_iter
+    Nebulabrot,
+}
+
+impl Kind {
+    fn next(self) -> Self {
+        match self {
+            Self::Buddhabrot => Self::AntiBuddhabrot,
+            Self::AntiBuddhabrot => Self::Nebulabrot,
+            Self::Nebulabrot => Self::Buddhabrot,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Buddhabrot => "buddhabrot",
+            Self::AntiBuddhabrot => "anti-buddhabrot",
+            Self::Nebulabrot => "nebulabrot",
+        }
+    }
+}
+
+// Small and quick random numbers, good enough for picking points
+struct Rng(u64);
+
+impl Rng {
+    // SplitMix64
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Evenly spread in [0, 1)
+    fn float(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Anywhere the set could be, which is all within 2 of 0
+    fn point(&mut self) -> (f64, f64) {
+        (self.float() * 4. - 2., self.float() * 4. - 2.)
+    }
+}
+
+// An orbit, and how it ended
+struct Orbit {
+    points: Vec<(f64, f64)>,
+    escaped: bool,
+}
+
+// Where a tile's Metropolis-Hastings chain got to, so the next frame
+// carries on from there
+struct Chain {
+    c: (f64, f64),
+    hits: usize,
+}
+
+struct Data {
+    kind: Kind,
+    controls: Controls,
+    // Hits of every pixel, as red, green and blue next to each other
+    histogram: Vec<AtomicU64>,
+    // Everything added to each color, to know how bright the average pixel is
+    totals: [AtomicU64; 3],
+    orbits: AtomicU64,
+    // Metropolis-Hastings chains by the position of the tile they're for
+    chains: Mutex<HashMap<(usize, usize), Chain>>,
+    // Frames drawn since the view changed, so every tile of every
+    // frame gets its own random numbers
+    frame: u64,
+}
+
+impl Data {
+    fn new(kind: Kind, controls: Controls) -> Self {
+        let viewport = &controls.viewport;
+        let histogram = Self::histogram(
+            viewport.pixel_width as usize,
+            viewport.pixel_height as usize,
+        );
+        Data {
+            kind,
+            controls,
+            histogram,
+            totals: Default::default(),
+            orbits: AtomicU64::new(0),
+            chains: Default::default(),
+            frame: 0,
+        }
+    }
+
+    fn histogram(width: usize, height: usize) -> Vec<AtomicU64> {
+        (0..width * height * 3).map(|_| AtomicU64::new(0)).collect()
+    }
+
+    // Limits of the three colors, an orbit only counts for the
+    // ones it escaped within
+    fn bands(&self) -> [usize; 3] {
+        let max_iter = self.controls.max_iter;
+        match self.kind {
+            Kind::Nebulabrot => [max_iter, (max_iter / 10).max(1), (max_iter / 100).max(1)],
+            _ => [max_iter; 3],
+        }
+    }
+
+    // Iterates z^2 + c from 0 until it escapes or max_iter is hit
+    fn orbit(&self, (cx, cy): (f64, f64), orbit: &mut Orbit) {
+        orbit.points.clear();
+        orbit.escaped = false;
+        let c = Complex::new(cx, cy);
+        let mut z = Complex::ZERO;
+        for _ in 0..self.controls.max_iter {
+            z = z.square() + c;
+            if z.norm_sqr() > 4. {
+                orbit.escaped = true;
+                return;
+            }
+            orbit.points.push((z.re, z.im));
+        }
+    }
+
+    // Points in the main cardioid and the period 2 bulb never escape,
+    // so there's no need to iterate them to know that
+    fn in_bulbs((x, y): (f64, f64)) -> bool {
+        let q = (x - 0.25) * (x - 0.25) + y * y;
+        q * (q + x - 0.25) <= 0.25 * y * y || (x + 1.) * (x + 1.) + y * y <= 1. / 16.
+    }
+
+    // Whether the kind of plot counts the orbit at all
+    fn counts(&self, orbit: &Orbit) -> bool {
+        match self.kind {
+            Kind::AntiBuddhabrot => !orbit.escaped,
+            _ => orbit.escaped,
+        }
+    }
+
+    // Where a point lands in histogram, if it's on screen
+    fn pixel(&self, point: (f64, f64)) -> Option<usize> {
+        let viewport = &self.controls.viewport;
+        let (x, y) = viewport.pixel(point)?;
+        Some(y * viewport.pixel_width as usize + x)
+    }
+
+    // How many points of the orbit are on screen
+    fn hits(&self, orbit: &Orbit) -> usize {
+        if !self.counts(orbit) {
+            return 0;
+        }
+        orbit
+            .points
+            .iter()
+            .filter(|p| self.pixel(**p).is_some())
+            .count()
+    }
+
+    // Adds the orbit to every color it counts for
+    fn add(&self, orbit: &Orbit, weight: u64) {
+        if !self.counts(orbit) {
+            return;
+        }
+        let bands = self.bands();
+        let len = orbit.points.len();
+        let mut added = [0; 3];
+        for (i, &point) in orbit.points.iter().enumerate() {
+            let pixel = match self.pixel(point) {
+                Some(pixel) => pixel,
+                None => continue,
+            };
+            for (color, &band) in bands.iter().enumerate() {
+                // Escaping orbits only count for colors they escaped within,
+                // and ones that don't escape only up to the color's limit
+                let counted = if orbit.escaped { len < band } else { i < band };
+                if counted {
+                    self.histogram[pixel * 3 + color].fetch_add(weight, Ordering::Relaxed);
+                    added[color] += weight;
+                }
+            }
+        }
+        for (total, added) in self.totals.iter().zip(added) {
+            total.fetch_add(added, Ordering::Relaxed);
+        }
+    }
+}
+
+pub struct Buddhabrot {
+    data: Data,
+    op: BasicOp,
+}
+
+impl Buddhabrot {
+    pub fn init(
+        window: Option<Arc<Mutex<Window>>>,
+        start: &Session,
+        view_given: bool,
+    ) -> RenderOpReference {
+        let (w, h) = BasicOp::size(window.as_ref(), start.width, start.height);
+        let bindings = Bindings::load("buddhabrot").unwrap_or_else(|e| {
+            println!("Couldn't load controls: {}", e);
+            Bindings::default()
+        });
+        // Orbits go all over, so start on the whole set unless a view was asked for
+        let view = &start.view;
+        let (x, y, width) = if view_given {
+            (view.x.to_f64(), view.y.to_f64(), view.width)
+        } else {
+            (-0.5, 0., WHOLE_WIDTH)
+        };
+        let viewport = Viewport {
+            x,
+            y,
+            width,
+            pixel_width: w as u32,
+            pixel_height: h as u32,
+            y_up: false,
+        };
+        let controls = Controls::new(viewport, start.view.max_iter, bindings);
+        let data = Data::new(Kind::Buddhabrot, controls);
+        let op = BasicOp::init(window, w, h, 0, 0);
+        Arc::new(RwLock::new(Box::new(Buddhabrot { data, op })))
+    }
+
+    // Points spread evenly over the whole set
+    fn sample_uniform(&self, rng: &mut Rng, token: &CancelToken) {
+        let mut orbit = Orbit {
+            points: Vec::with_capacity(self.data.controls.max_iter),
+            escaped: false,
+        };
+        let skip_bulbs = self.data.kind != Kind::AntiBuddhabrot;
+        for _ in 0..ORBITS_PER_TILE {
+            if token.is_cancelled() {
+                return;
+            }
+            let c = rng.point();
+            if skip_bulbs && Data::in_bulbs(c) {
+                continue;
+            }
+            self.data.orbit(c, &mut orbit);
+            self.data.add(&orbit, WHOLE_HIT);
+        }
+        let orbits = &self.data.orbits;
+        orbits.fetch_add(ORBITS_PER_TILE as u64, Ordering::Relaxed);
+    }
+
+    // Metropolis-Hastings: points are picked more often the more of their
+    // orbit is on screen, mostly by moving a little from the last point. Each
+    // orbit then counts for 1 / hits, so the density comes out the same as
+    // with even sampling, only with far less time spent on orbits elsewhere.
+    // Every tile keeps one chain going from frame to frame.
+    fn sample_metropolis(&self, tile: (usize, usize), rng: &mut Rng, token: &CancelToken) {
+        let capacity = self.data.controls.max_iter;
+        let mut current = Orbit {
+            points: Vec::with_capacity(capacity),
+            escaped: false,
+        };
+        let mut proposed = Orbit {
+            points: Vec::with_capacity(capacity),
+            escaped: false,
+        };
+        let chain = self.data.chains.lock().unwrap().remove(&tile);
+        let (mut c, mut hits, burn_in) = match chain {
+            Some(chain) => {
+                self.data.orbit(chain.c, &mut current);
+                (chain.c, chain.hits, 0)
+            }
+            None => match self.seed(rng, &mut current) {
+                Some((c, hits)) => (c, hits, BURN_IN),
+                None => {
+                    // The tries count as orbits, so a view no orbit passes
+                    // through still stops refining at some point
+                    let orbits = &self.data.orbits;
+                    orbits.fetch_add(SEED_TRIES as u64, Ordering::Relaxed);
+                    return;
+                }
+            },
+        };
+        let width = self.data.controls.viewport.width;
+        for step in 0..burn_in + ORBITS_PER_TILE {
+            if token.is_cancelled() {
+                // Only this frame is lost, unless the chain was still burning in
+                if step >= burn_in {
+                    let chain = Chain { c, hits };
+                    self.data.chains.lock().unwrap().insert(tile, chain);
+                }
+                return;
+            }
+            // Sometimes jump anywhere, so the chain can't get stuck
+            let next = if rng.float() < 0.2 {
+                rng.point()
+            } else {
+                // Step sizes from a ten thousandth of the view to half of it
+                let distance = width * 0.5 * (1e-4f64.ln() * rng.float()).exp();
+                let angle = rng.float() * std::f64::consts::TAU;
+                (c.0 + distance * angle.cos(), c.1 + distance * angle.sin())
+            };
+            self.data.orbit(next, &mut proposed);
+            let next_hits = self.data.hits(&proposed);
+            if next_hits > 0 && rng.float() * (hits as f64) < next_hits as f64 {
+                std::mem::swap(&mut current, &mut proposed);
+                c = next;
+                hits = next_hits;
+            }
+            if step >= burn_in {
+                self.data.add(&current, WHOLE_HIT / hits as u64);
+            }
+        }
+        let chain = Chain { c, hits };
+        self.data.chains.lock().unwrap().insert(tile, chain);
+        let orbits = &self.data.orbits;
+        orbits.fetch_add(ORBITS_PER_TILE as u64, Ordering::Relaxed);
+    }
+
+    // Any point with an orbit through the view, to start a chain from,
+    // and how many of the orbit's points are on screen
+    fn seed(&self, rng: &mut Rng, orbit: &mut Orbit) -> Option<((f64, f64), usize)> {
+        for _ in 0..SEED_TRIES {
+            let c = rng.point();
+            self.data.orbit(c, orbit);
+            let hits = self.data.hits(orbit);
+            if hits > 0 {
+                return Some((c, hits));
+            }
+        }
+        None
+    }
+
+    // Colors the tile from the histogram, scaled by how bright the
+    // average pixel is so the picture doesn't fade in over time
+    fn paint(&self, tile: &mut Tile) {
+        let d = &self.data;
+        let viewport = &d.controls.viewport;
+        let pixels = viewport.pixel_width as f64 * viewport.pixel_height as f64;
+        let means = d
+            .totals
+            .iter()
+            .map(|total| total.load(Ordering::Relaxed) as f64 / pixels)
+            .collect::<Vec<_>>();
+        let (tile_x, tile_y) = tile.position();
+        let (width, height) = tile.dimensions();
+        for y in 0..height {
+            for x in 0..width {
+                let pixel = (tile_y + y) * viewport.pixel_width as usize + tile_x + x;
+                let mut rgb = [0; 3];
+                for (color, value) in rgb.iter_mut().enumerate() {
+                    if means[color] == 0. {
+                        continue;
+                    }
+                    let hits = d.histogram[pixel * 3 + color].load(Ordering::Relaxed) as f64;
+                    let brightness = 1. - (-hits / means[color] / EXPOSURE).exp();
+                    *value = (brightness * 255.) as u8;
+                }
+                tile[(x, y)] = (rgb[0], rgb[1], rgb[2], 255);
+            }
+        }
+    }
+
+    fn refined(&self) -> bool {
+        self.data.orbits.load(Ordering::Relaxed) >= ORBIT_LIMIT
+    }
+
+    // The view changed, so everything counted so far is in the wrong place
+    fn clear(&mut self) {
+        let d = &mut self.data;
+        let viewport = &d.controls.viewport;
+        d.histogram = Data::histogram(
+            viewport.pixel_width as usize,
+            viewport.pixel_height as usize,
+        );
+        d.totals = Default::default();
+        d.orbits = AtomicU64::new(0);
+        d.chains = Default::default();
+        d.frame = 0;
+    }
+}
+
+impl ViewportOp for Buddhabrot {
+    fn controls(&self) -> &Controls {
+        &self.data.controls
+    }
+    fn controls_mut(&mut self) -> &mut Controls {
+        &mut self.data.controls
+    }
+    fn reset(&mut self) {
+        self.clear();
+    }
+    fn next_formula(&mut self) {
+        self.data.kind = self.data.kind.next();
+        println!("Plot: {}", self.data.kind.name());
+        self.clear();
+    }
+    fn hud_lines(&self) -> Vec<String> {
+        let d = &self.data;
+        let viewport = &d.controls.viewport;
+        let sampling = if viewport.width < ZOOMED_WIDTH {
+            "metropolis-hastings"
+        } else {
+            "even"
+        };
+        vec![
+            format!("Center: {} {:+}i", viewport.x, viewport.y),
+            format!("Zoom: {:.3e}x", View::default().width / viewport.width),
+            format!("Max iter: {}", d.controls.max_iter),
+            format!("Plot: {}", d.kind.name()),
+            format!("Sampling: {}", sampling),
+            format!("Orbits: {}", d.orbits.load(Ordering::Relaxed)),
+        ]
+    }
+}
+
+impl BasicOpImp for Buddhabrot {
+    fn get_op(&self) -> &BasicOp {
+        &self.op
+    }
+    fn get_op_mut(&mut self) -> &mut BasicOp {
+        &mut self.op
+    }
+    fn bindings(&self) -> Option<&Bindings> {
+        Some(&self.data.controls.bindings)
+    }
+    // Orbits go anywhere, so every tile adds its share of orbits
+    // to the whole histogram and then draws its own part of it
+    fn draw(&self, tile: &mut Tile, token: &CancelToken) {
+        let (tile_x, tile_y) = tile.position();
+        let seed = (self.data.frame << 32) ^ ((tile_y as u64) << 16) ^ tile_x as u64;
+        let mut rng = Rng(seed);
+        if !self.refined() {
+            if self.data.controls.viewport.width < ZOOMED_WIDTH {
+                self.sample_metropolis((tile_x, tile_y), &mut rng, token);
+            } else {
+                self.sample_uniform(&mut rng, token);
+            }
+        }
+        self.paint(tile);
+    }
+    fn modify_data(&mut self) {
+        self.data.frame += 1;
+    }
+    fn is_complete(&self) -> bool {
+        self.refined()
+    }
+    fn handle_events(&mut self) -> bool {
+        self.handle_viewport_events()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 4 by 3 screen from -2 to 2, one unit to a pixel
+    fn data(kind: Kind, max_iter: usize) -> Data {
+        let viewport = Viewport {
+            x: 0.,
+            y: 0.,
+            width: 4.,
+            pixel_width: 4,
+            pixel_height: 3,
+            y_up: false,
+        };
+        Data::new(kind, Controls::new(viewport, max_iter, Bindings::default()))
+    }
+
+    fn orbit(points: Vec<(f64, f64)>, escaped: bool) -> Orbit {
+        Orbit { points, escaped }
+    }
+
+    fn colors(d: &Data, pixel: usize) -> [u64; 3] {
+        let hits = |color: usize| d.histogram[pixel * 3 + color].load(Ordering::Relaxed);
+        [hits(0), hits(1), hits(2)]
+    }
+
+    #[test]
+    fn bulbs_are_the_cardioid_and_the_period_two_disk() {
+        for &c in &[(0., 0.), (0.2, 0.3), (-0.7, 0.), (-1., 0.), (-1.2, 0.1)] {
+            assert!(Data::in_bulbs(c), "{:?} is in a bulb", c);
+        }
+        for &c in &[(0.3, 0.), (-0.75, 0.2), (-1.3, 0.), (-0.1, 0.9), (1., 1.)] {
+            assert!(!Data::in_bulbs(c), "{:?} isn't in a bulb", c);
+        }
+        // Whatever the shortcut skips really doesn't escape
+        let d = data(Kind::Buddhabrot, 1000);
+        let mut o = orbit(vec![], false);
+        for &c in &[(0.2, 0.3), (-1.2, 0.1), (-0.5, 0.5)] {
+            d.orbit(c, &mut o);
+            assert!(!o.escaped);
+        }
+    }
+
+    #[test]
+    fn orbits_stop_where_they_escape() {
+        let d = data(Kind::Buddhabrot, 100);
+        let mut o = orbit(vec![], false);
+        // 1, 2, then 5 is out
+        d.orbit((1., 0.), &mut o);
+        assert!(o.escaped);
+        assert_eq!(o.points, vec![(1., 0.), (2., 0.)]);
+        // i, i - 1, -i, i - 1, ...
+        d.orbit((0., 1.), &mut o);
+        assert!(!o.escaped);
+        assert_eq!(o.points.len(), 100);
+        assert_eq!(&o.points[..3], &[(0., 1.), (-1., 1.), (0., -1.)]);
+    }
+
+    #[test]
+    fn points_land_on_their_pixel_row_by_row() {
+        let d = data(Kind::Buddhabrot, 100);
+        assert_eq!(d.pixel((-1.5, -1.)), Some(0));
+        assert_eq!(d.pixel((1.5, -1.)), Some(3));
+        assert_eq!(d.pixel((-1.5, 0.5)), Some(8));
+        assert_eq!(d.pixel((0.5, 0.5)), Some(10));
+        assert_eq!(d.pixel((2.5, 0.)), None);
+        assert_eq!(d.pixel((0., -1.6)), None);
+        assert_eq!(d.histogram.len(), 4 * 3 * 3);
+
+        // Points off screen don't count as hits, or at all
+        let o = orbit(vec![(0.5, 0.5), (9., 9.), (0.5, 0.5), (-1.5, -1.)], true);
+        assert_eq!(d.hits(&o), 3);
+        d.add(&o, 5);
+        assert_eq!(colors(&d, 10), [10; 3]);
+        assert_eq!(colors(&d, 0), [5; 3]);
+        assert_eq!(colors(&d, 3), [0; 3]);
+        let totals = d.totals.iter().map(|t| t.load(Ordering::Relaxed));
+        assert_eq!(totals.collect::<Vec<_>>(), vec![15; 3]);
+    }
+
+    #[test]
+    fn nebulabrot_colors_count_orbits_that_escaped_within_their_band() {
+        let d = data(Kind::Nebulabrot, 1000);
+        assert_eq!(d.bands(), [1000, 100, 10]);
+        let quick = orbit(vec![(0.5, 0.5); 5], true);
+        let slower = orbit(vec![(-1.5, -1.); 50], true);
+        let slowest = orbit(vec![(1.5, -1.); 500], true);
+        let stuck = orbit(vec![(-1.5, 0.5); 1000], false);
+        for o in &[&quick, &slower, &slowest, &stuck] {
+            d.add(o, 1);
+        }
+        assert_eq!(colors(&d, 10), [5, 5, 5]);
+        assert_eq!(colors(&d, 0), [50, 50, 0]);
+        assert_eq!(colors(&d, 3), [500, 0, 0]);
+        // Orbits that don't escape aren't part of it
+        assert_eq!(colors(&d, 8), [0, 0, 0]);
+        assert_eq!(d.hits(&stuck), 0);
+
+        // Small max_iter still leaves every color something to count
+        assert_eq!(data(Kind::Nebulabrot, 5).bands(), [5, 1, 1]);
+    }
+
+    #[test]
+    fn anti_buddhabrot_counts_only_orbits_that_stay() {
+        let d = data(Kind::AntiBuddhabrot, 1000);
+        assert_eq!(d.bands(), [1000; 3]);
+        d.add(&orbit(vec![(0.5, 0.5); 5], true), 1);
+        d.add(&orbit(vec![(-1.5, -1.); 1000], false), 1);
+        assert_eq!(colors(&d, 10), [0; 3]);
+        assert_eq!(colors(&d, 0), [1000; 3]);
+    }
+}
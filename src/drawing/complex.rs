@@ -0,0 +1,179 @@
+// Complex numbers over any of the Real types. The formula language and
+// the precise escape loops use these, while the vectorized loops work on
+// the parts directly since they do several points at once.
+use super::real::Real;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Complex<T = f64> {
+    pub re: T,
+    pub im: T,
+}
+
+impl Complex<f64> {
+    pub const ZERO: Complex = Complex { re: 0., im: 0. };
+    pub const ONE: Complex = Complex { re: 1., im: 0. };
+    pub const I: Complex = Complex { re: 0., im: 1. };
+}
+
+impl<T: Real> Complex<T> {
+    pub fn new(re: T, im: T) -> Self {
+        Complex { re, im }
+    }
+
+    pub fn real(re: T) -> Self {
+        Complex { re, im: T::zero() }
+    }
+
+    pub fn norm_sqr(&self) -> T {
+        self.re.clone() * self.re.clone() + self.im.clone() * self.im.clone()
+    }
+
+    pub fn abs(&self) -> T {
+        self.norm_sqr().sqrt()
+    }
+
+    pub fn arg(&self) -> T {
+        self.im.atan2(&self.re)
+    }
+
+    pub fn conj(self) -> Self {
+        Complex::new(self.re, -self.im)
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.re.is_finite() && self.im.is_finite()
+    }
+
+    pub fn square(self) -> Self {
+        let (re, im) = (self.re, self.im);
+        let two = T::from_f64(2.);
+        Complex::new(
+            re.clone() * re.clone() - im.clone() * im.clone(),
+            two * re * im,
+        )
+    }
+
+    pub fn exp(self) -> Self {
+        let r = self.re.exp();
+        Complex::new(r.clone() * self.im.cos(), r * self.im.sin())
+    }
+
+    // The principal branch, with the angle in (-pi, pi]
+    pub fn ln(self) -> Self {
+        Complex::new(self.abs().ln(), self.arg())
+    }
+
+    pub fn sqrt(self) -> Self {
+        let r = self.abs();
+        let two = T::from_f64(2.);
+        let re = ((r.clone() + self.re.clone()) / two.clone()).sqrt();
+        let im = ((r - self.re) / two).sqrt();
+        Complex::new(re, if self.im < T::zero() { -im } else { im })
+    }
+
+    pub fn sin(self) -> Self {
+        Complex::new(
+            self.re.sin() * self.im.cosh(),
+            self.re.cos() * self.im.sinh(),
+        )
+    }
+
+    pub fn cos(self) -> Self {
+        Complex::new(
+            self.re.cos() * self.im.cosh(),
+            -(self.re.sin() * self.im.sinh()),
+        )
+    }
+
+    pub fn tan(self) -> Self {
+        self.clone().sin() / self.cos()
+    }
+
+    pub fn sinh(self) -> Self {
+        Complex::new(
+            self.re.sinh() * self.im.cos(),
+            self.re.cosh() * self.im.sin(),
+        )
+    }
+
+    pub fn cosh(self) -> Self {
+        Complex::new(
+            self.re.cosh() * self.im.cos(),
+            self.re.sinh() * self.im.sin(),
+        )
+    }
+
+    // Squaring over and over, which is exact for small powers
+    pub fn powi(self, n: i32) -> Self {
+        let one = Complex::real(T::one());
+        let mut base = if n < 0 { one.clone() / self } else { self };
+        let mut n = n.unsigned_abs();
+        let mut result = one;
+        while n > 0 {
+            if n & 1 == 1 {
+                result = result * base.clone();
+            }
+            base = base.square();
+            n >>= 1;
+        }
+        result
+    }
+
+    pub fn powf(self, exponent: T) -> Self {
+        self.powc(Complex::real(exponent))
+    }
+
+    pub fn powc(self, exponent: Self) -> Self {
+        let zero = Complex::real(T::zero());
+        if self == zero {
+            return if exponent == zero {
+                Complex::real(T::one())
+            } else {
+                zero
+            };
+        }
+        (exponent * self.ln()).exp()
+    }
+}
+
+impl<T: Real> Add for Complex<T> {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl<T: Real> Sub for Complex<T> {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl<T: Real> Mul for Complex<T> {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        let (a, b, c, d) = (self.re, self.im, other.re, other.im);
+        Complex::new(a.clone() * c.clone() - b.clone() * d.clone(), a * d + b * c)
+    }
+}
+
+impl<T: Real> Div for Complex<T> {
+    type Output = Self;
+    fn div(self, other: Self) -> Self {
+        let d = other.norm_sqr();
+        let (a, b, c, e) = (self.re, self.im, other.re, other.im);
+        Complex::new(
+            (a.clone() * c.clone() + b.clone() * e.clone()) / d.clone(),
+            (b * c - a * e) / d,
+        )
+    }
+}
+
+impl<T: Real> Neg for Complex<T> {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Complex::new(-self.re, -self.im)
+    }
+}
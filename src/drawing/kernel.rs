@@ -0,0 +1,332 @@
+// Escape time loop for several points at once. Points that escape early
+// are masked off and stop changing, while the rest keep iterating until
+// every lane is done or max_iter is hit.
+use super::complex::Complex;
+use super::real::{Big, Real};
+use super::view::Formula;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+pub const LANES: usize = 4;
+
+// Returns the iteration count and the final |z|^2 of every point
+pub fn escape(
+    x0: &[f64; LANES],
+    y0: &[f64; LANES],
+    formula: Formula,
+    max_iter: usize,
+    bailout: f64,
+) -> ([usize; LANES], [f64; LANES]) {
+    // Formulas from the file are interpreted one point at a time
+    if let Formula::Custom(script) = formula {
+        let mut stack = vec![];
+        let mut iterations = [0; LANES];
+        let mut mags = [0.; LANES];
+        for lane in 0..LANES {
+            let c = Complex::new(x0[lane], y0[lane]);
+            (iterations[lane], mags[lane]) = script.escape(c, max_iter, bailout, &mut stack);
+        }
+        return (iterations, mags);
+    }
+    // z starts at the point, and c is the point too unless it's a julia set
+    let (cx, cy) = match formula {
+        Formula::Julia(x, y) => ([x; LANES], [y; LANES]),
+        _ => (*x0, *y0),
+    };
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return unsafe { escape_avx2(x0, y0, &cx, &cy, formula, max_iter, bailout) };
+        }
+        // sse2 is part of x86_64, so it's always there. It only
+        // fits two lanes, so do both halves one after the other.
+        let (lo_iter, lo_mag) = unsafe {
+            escape_sse2(
+                &x0[..2],
+                &y0[..2],
+                &cx[..2],
+                &cy[..2],
+                formula,
+                max_iter,
+                bailout,
+            )
+        };
+        let (hi_iter, hi_mag) = unsafe {
+            escape_sse2(
+                &x0[2..],
+                &y0[2..],
+                &cx[2..],
+                &cy[2..],
+                formula,
+                max_iter,
+                bailout,
+            )
+        };
+        (
+            [lo_iter[0], lo_iter[1], hi_iter[0], hi_iter[1]],
+            [lo_mag[0], lo_mag[1], hi_mag[0], hi_mag[1]],
+        )
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        escape_scalar(x0, y0, &cx, &cy, formula, max_iter, bailout)
+    }
+}
+
+#[cfg(any(not(target_arch = "x86_64"), test))]
+fn escape_scalar(
+    x0: &[f64; LANES],
+    y0: &[f64; LANES],
+    cx: &[f64; LANES],
+    cy: &[f64; LANES],
+    formula: Formula,
+    max_iter: usize,
+    bailout: f64,
+) -> ([usize; LANES], [f64; LANES]) {
+    let mut iterations = [0; LANES];
+    let mut mags = [0.; LANES];
+    for lane in 0..LANES {
+        let z = Complex::new(x0[lane], y0[lane]);
+        let c = Complex::new(cx[lane], cy[lane]);
+        (iterations[lane], mags[lane]) = escape_point(z, c, formula, max_iter, bailout);
+    }
+    (iterations, mags)
+}
+
+// The same as escape, one point at a time in any precision. Points are
+// given as offsets from center, and only added up once they're in T, so
+// they stay apart even where f64 would round them all to the center.
+// The center itself has more digits than f64 for the same reason.
+pub fn escape_precise<T: Real>(
+    center: &(Big, Big),
+    offsets: &[(f64, f64)],
+    formula: Formula,
+    max_iter: usize,
+    bailout: f64,
+) -> Vec<(usize, f64)> {
+    let (x, y) = (T::from_big(&center.0), T::from_big(&center.1));
+    offsets
+        .iter()
+        .map(|&(dx, dy)| {
+            let z = Complex::new(x.clone() + T::from_f64(dx), y.clone() + T::from_f64(dy));
+            let c = match formula {
+                Formula::Julia(cx, cy) => Complex::new(T::from_f64(cx), T::from_f64(cy)),
+                _ => z.clone(),
+            };
+            escape_point(z, c, formula, max_iter, bailout)
+        })
+        .collect()
+}
+
+fn escape_point<T: Real>(
+    mut z: Complex<T>,
+    c: Complex<T>,
+    formula: Formula,
+    max_iter: usize,
+    bailout: f64,
+) -> (usize, f64) {
+    // Compared in T, since turning a Big into an f64 goes through a string
+    let limit = T::from_f64(bailout);
+    let mut iteration = 0;
+    let mut mag = z.norm_sqr();
+    while mag <= limit && iteration < max_iter {
+        z = match formula {
+            Formula::BurningShip => Complex::new(z.re.abs(), z.im.abs()),
+            Formula::Tricorn => z.conj(),
+            _ => z,
+        };
+        z = z.square() + c.clone();
+        mag = z.norm_sqr();
+        iteration += 1;
+    }
+    (iteration, mag.to_f64())
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2")]
+unsafe fn escape_avx2(
+    x0: &[f64; LANES],
+    y0: &[f64; LANES],
+    cx: &[f64; LANES],
+    cy: &[f64; LANES],
+    formula: Formula,
+    max_iter: usize,
+    bailout: f64,
+) -> ([usize; LANES], [f64; LANES]) {
+    let cx = _mm256_loadu_pd(cx.as_ptr());
+    let cy = _mm256_loadu_pd(cy.as_ptr());
+    let limit = _mm256_set1_pd(bailout);
+    let one = _mm256_set1_pd(1.);
+    let sign = _mm256_set1_pd(-0.);
+    let mut x = _mm256_loadu_pd(x0.as_ptr());
+    let mut y = _mm256_loadu_pd(y0.as_ptr());
+    let mut count = _mm256_setzero_pd();
+    // All bits set while the lane is still iterating
+    let mut active = _mm256_castsi256_pd(_mm256_set1_epi64x(-1));
+    for _ in 0..max_iter {
+        let xx = _mm256_mul_pd(x, x);
+        let yy = _mm256_mul_pd(y, y);
+        let mag = _mm256_add_pd(xx, yy);
+        active = _mm256_and_pd(active, _mm256_cmp_pd(mag, limit, _CMP_LE_OQ));
+        if _mm256_movemask_pd(active) == 0 {
+            break;
+        }
+        // Compute New Point, but only keep it for active lanes
+        let xy = _mm256_mul_pd(x, y);
+        let xy = match formula {
+            Formula::BurningShip => _mm256_andnot_pd(sign, xy),
+            Formula::Tricorn => _mm256_xor_pd(sign, xy),
+            _ => xy,
+        };
+        let new_x = _mm256_add_pd(_mm256_sub_pd(xx, yy), cx);
+        let new_y = _mm256_add_pd(_mm256_add_pd(xy, xy), cy);
+        x = _mm256_blendv_pd(x, new_x, active);
+        y = _mm256_blendv_pd(y, new_y, active);
+        count = _mm256_add_pd(count, _mm256_and_pd(active, one));
+    }
+    let mag = _mm256_add_pd(_mm256_mul_pd(x, x), _mm256_mul_pd(y, y));
+    let mut counts = [0.; LANES];
+    let mut mags = [0.; LANES];
+    _mm256_storeu_pd(counts.as_mut_ptr(), count);
+    _mm256_storeu_pd(mags.as_mut_ptr(), mag);
+    (counts.map(|c| c as usize), mags)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "sse2")]
+unsafe fn escape_sse2(
+    x0: &[f64],
+    y0: &[f64],
+    cx: &[f64],
+    cy: &[f64],
+    formula: Formula,
+    max_iter: usize,
+    bailout: f64,
+) -> ([usize; 2], [f64; 2]) {
+    let cx = _mm_loadu_pd(cx.as_ptr());
+    let cy = _mm_loadu_pd(cy.as_ptr());
+    let limit = _mm_set1_pd(bailout);
+    let one = _mm_set1_pd(1.);
+    let sign = _mm_set1_pd(-0.);
+    let mut x = _mm_loadu_pd(x0.as_ptr());
+    let mut y = _mm_loadu_pd(y0.as_ptr());
+    let mut count = _mm_setzero_pd();
+    // All bits set while the lane is still iterating
+    let mut active = _mm_castsi128_pd(_mm_set1_epi64x(-1));
+    for _ in 0..max_iter {
+        let xx = _mm_mul_pd(x, x);
+        let yy = _mm_mul_pd(y, y);
+        let mag = _mm_add_pd(xx, yy);
+        active = _mm_and_pd(active, _mm_cmple_pd(mag, limit));
+        if _mm_movemask_pd(active) == 0 {
+            break;
+        }
+        // Compute New Point, but only keep it for active lanes.
+        // There's no blend in sse2, so mask by hand.
+        let xy = _mm_mul_pd(x, y);
+        let xy = match formula {
+            Formula::BurningShip => _mm_andnot_pd(sign, xy),
+            Formula::Tricorn => _mm_xor_pd(sign, xy),
+            _ => xy,
+        };
+        let new_x = _mm_add_pd(_mm_sub_pd(xx, yy), cx);
+        let new_y = _mm_add_pd(_mm_add_pd(xy, xy), cy);
+        x = _mm_or_pd(_mm_and_pd(active, new_x), _mm_andnot_pd(active, x));
+        y = _mm_or_pd(_mm_and_pd(active, new_y), _mm_andnot_pd(active, y));
+        count = _mm_add_pd(count, _mm_and_pd(active, one));
+    }
+    let mag = _mm_add_pd(_mm_mul_pd(x, x), _mm_mul_pd(y, y));
+    let mut counts = [0.; 2];
+    let mut mags = [0.; 2];
+    _mm_storeu_pd(counts.as_mut_ptr(), count);
+    _mm_storeu_pd(mags.as_mut_ptr(), mag);
+    ([counts[0] as usize, counts[1] as usize], mags)
+}
+
+#[cfg(all(test, target_arch = "x86_64"))]
+mod tests {
+    use super::super::real::DoubleDouble;
+    use super::*;
+
+    const FORMULAS: [Formula; 4] = [
+        Formula::Mandelbrot,
+        Formula::BurningShip,
+        Formula::Tricorn,
+        Formula::Julia(-0.8, 0.156),
+    ];
+
+    // Every lane against the scalar loop, counts and magnitudes both
+    fn check(x0: [f64; LANES], y0: [f64; LANES]) {
+        for formula in FORMULAS {
+            let (cx, cy) = match formula {
+                Formula::Julia(x, y) => ([x; LANES], [y; LANES]),
+                _ => (x0, y0),
+            };
+            let expected = escape_scalar(&x0, &y0, &cx, &cy, formula, 200, 4.);
+            if is_x86_feature_detected!("avx2") {
+                let avx2 = unsafe { escape_avx2(&x0, &y0, &cx, &cy, formula, 200, 4.) };
+                assert_eq!(avx2, expected, "avx2 {:?}", formula);
+            }
+            for half in [0..2, 2..4] {
+                let (iterations, mags) = unsafe {
+                    escape_sse2(
+                        &x0[half.clone()],
+                        &y0[half.clone()],
+                        &cx[half.clone()],
+                        &cy[half.clone()],
+                        formula,
+                        200,
+                        4.,
+                    )
+                };
+                assert_eq!(iterations, expected.0[half.clone()], "sse2 {:?}", formula);
+                assert_eq!(mags, expected.1[half], "sse2 {:?}", formula);
+            }
+        }
+    }
+
+    #[test]
+    fn lanes_inside_the_set() {
+        check([0., -1., 0.25, -0.1], [0., 0., 0., 0.1]);
+    }
+
+    #[test]
+    fn lanes_escaping() {
+        check([2., 1., -2.5, 0.5], [2., 1., 0., 0.6]);
+    }
+
+    #[test]
+    fn lanes_escaping_at_different_times() {
+        // Both sse2 halves and the avx2 mask see live and done lanes
+        check([-0.5, 1.5, 0.3, 0.], [0.1, 0., 0.5, 0.]);
+    }
+
+    #[test]
+    fn wider_types_count_the_same_as_f64() {
+        let center = (Big::from_f64(-0.5), Big::from_f64(0.));
+        let offsets = [(0., 0.), (2., 1.), (0.8, 0.5), (1., 0.6)];
+        for formula in FORMULAS {
+            let expected: Vec<_> = offsets
+                .iter()
+                .map(|&(dx, dy)| {
+                    let z = Complex::new(-0.5 + dx, dy);
+                    let c = match formula {
+                        Formula::Julia(x, y) => Complex::new(x, y),
+                        _ => z,
+                    };
+                    escape_point(z, c, formula, 200, 4.).0
+                })
+                .collect();
+            let counts = |escaped: Vec<(usize, f64)>| -> Vec<usize> {
+                escaped
+                    .into_iter()
+                    .map(|(iteration, _)| iteration)
+                    .collect()
+            };
+            let wide = escape_precise::<DoubleDouble>(&center, &offsets, formula, 200, 4.);
+            assert_eq!(counts(wide), expected, "double-double {:?}", formula);
+            let big = escape_precise::<Big>(&center, &offsets, formula, 200, 4.);
+            assert_eq!(counts(big), expected, "bignum {:?}", formula);
+        }
+    }
+}
@@ -0,0 +1,561 @@
+// Views written the way other fractal programs write them, so spots can be
+// passed back and forth. Three kinds are understood:
+//
+// - Kalles Fraktaler .kfr files, where zoom 1 is a view 4 wide
+// - Fractint .par entries, with the formulas it doesn't have built in
+//   coming from the .frm file written next to them
+// - Short strings made for pasting in chat or a url, like "nf2.AAAA..."
+//
+// None of the others know about our palettes, so the current one is kept.
+// All of them can hold a rotation, which is only written when there is one.
+// Custom formulas are passed around by name, so whoever reads one back
+// needs the same formula in their formula file.
+use super::real::{Big, Real};
+use super::script;
+use super::view::{Formula, Palette, View};
+use crate::numbers::{self, finite, positive};
+use std::convert::TryInto;
+
+// Version prefix of the short strings. The first version had the center
+// as f64s, which isn't enough for deep zooms, so now it's written out as
+// text with every digit.
+const PREFIX: &str = "nf2.";
+const PREFIX_F64: &str = "nf1.";
+// Fractint's screens are 4 by 3, and center-mag gives the height
+const PAR_ASPECT: f64 = 4. / 3.;
+pub const FRM_FILE: &str = "newtons-fractal.frm";
+
+// Fractint has no burning ship or tricorn, so they come from this
+pub const FRM: &str = "\
+BurningShip { ; z = (|re z| + i|im z|)^2 + c
+  z = 0, c = pixel:
+  z = sqr(abs(z)) + c
+  |z| <= 4
+}
+
+Tricorn { ; z = conj(z)^2 + c
+  z = 0, c = pixel:
+  z = sqr(conj(z)) + c
+  |z| <= 4
+}
+";
+
+// Works out which kind the text is. The text can also be a whole file.
+pub fn import(text: &str, base: View) -> Result<View, String> {
+    let text = text.trim();
+    if let Some(code) = text.strip_prefix(PREFIX) {
+        decode(code, true)
+    } else if let Some(code) = text.strip_prefix(PREFIX_F64) {
+        decode(code, false)
+    } else if text.contains('{') {
+        from_par(text, base)
+    } else if text.contains("Re:") {
+        from_kfr(text, base)
+    } else {
+        Err("Not a location string, .kfr or .par".to_string())
+    }
+}
+
+pub fn encode(view: &View) -> String {
+    let mut bytes = vec![];
+    for coordinate in &[&view.x, &view.y] {
+        let text = coordinate.to_string();
+        bytes.extend_from_slice(&(text.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(text.as_bytes());
+    }
+    bytes.extend_from_slice(&view.width.to_le_bytes());
+    bytes.extend_from_slice(&(view.max_iter as u32).to_le_bytes());
+    let palette = PALETTES.iter().position(|&p| p == view.palette).unwrap();
+    bytes.push(palette as u8);
+    match view.formula {
+        Formula::Mandelbrot => bytes.push(0),
+        Formula::BurningShip => bytes.push(1),
+        Formula::Tricorn => bytes.push(2),
+        Formula::Julia(x, y) => {
+            bytes.push(3);
+            bytes.extend_from_slice(&x.to_le_bytes());
+            bytes.extend_from_slice(&y.to_le_bytes());
+        }
+        Formula::Custom(script) => {
+            bytes.push(4);
+            // Fits, the formula file turns down longer names
+            debug_assert!(script.name.len() <= script::MAX_NAME);
+            bytes.push(script.name.len() as u8);
+            bytes.extend_from_slice(script.name.as_bytes());
+        }
+    }
+    // Left off when it's 0, so strings from before rotation stay the same
+    if view.angle != 0. {
+        bytes.extend_from_slice(&view.angle.to_le_bytes());
+    }
+    format!("{}{}", PREFIX, base64(&bytes))
+}
+
+const PALETTES: [Palette; 4] = [Palette::Classic, Palette::Fire, Palette::Ice, Palette::Gray];
+
+fn decode(code: &str, wide: bool) -> Result<View, String> {
+    let bytes = unbase64(code)?;
+    let rest = &mut &bytes[..];
+    let (x, y) = if wide {
+        (big(rest)?, big(rest)?)
+    } else {
+        (Big::from_f64(real(rest)?), Big::from_f64(real(rest)?))
+    };
+    let width = real(rest)?;
+    let max_iter = u32::from_le_bytes(take(rest, 4)?.try_into().unwrap()) as usize;
+    let palette = *PALETTES
+        .get(take(rest, 1)?[0] as usize)
+        .ok_or("Unknown palette in location string")?;
+    let formula = match take(rest, 1)?[0] {
+        0 => Formula::Mandelbrot,
+        1 => Formula::BurningShip,
+        2 => Formula::Tricorn,
+        3 => Formula::Julia(real(rest)?, real(rest)?),
+        4 => {
+            let len = take(rest, 1)?[0] as usize;
+            let name = String::from_utf8_lossy(take(rest, len)?);
+            Formula::Custom(
+                script::find(&name)
+                    .ok_or(format!("No formula named {} in the formula file", name))?,
+            )
+        }
+        _ => return Err("Unknown formula in location string".to_string()),
+    };
+    let angle = if rest.is_empty() { 0. } else { real(rest)? };
+    if !rest.is_empty() {
+        return Err("Location string is too long".to_string());
+    }
+    if width <= 0. || max_iter == 0 {
+        return Err("Location string has an empty view".to_string());
+    }
+    Ok(View {
+        x,
+        y,
+        width,
+        max_iter,
+        formula,
+        palette,
+        angle,
+    })
+}
+
+// Splits the first n bytes off of rest
+fn take<'a>(rest: &mut &'a [u8], n: usize) -> Result<&'a [u8], String> {
+    if rest.len() < n {
+        return Err("Location string is cut short".to_string());
+    }
+    let (taken, left) = rest.split_at(n);
+    *rest = left;
+    Ok(taken)
+}
+
+fn real(rest: &mut &[u8]) -> Result<f64, String> {
+    finite(f64::from_le_bytes(take(rest, 8)?.try_into().unwrap()))
+}
+
+// A number as text, after its length
+fn big(rest: &mut &[u8]) -> Result<Big, String> {
+    let len = u16::from_le_bytes(take(rest, 2)?.try_into().unwrap()) as usize;
+    String::from_utf8_lossy(take(rest, len)?).parse()
+}
+
+pub fn to_kfr(view: &View) -> Result<String, String> {
+    let fractal_type = match view.formula {
+        Formula::Mandelbrot => 0,
+        Formula::BurningShip => 1,
+        Formula::Tricorn => 4,
+        Formula::Julia(..) => return Err("Kalles Fraktaler has no Julia sets".to_string()),
+        Formula::Custom(_) => return Err("Kalles Fraktaler can't use custom formulas".to_string()),
+    };
+    let mut kfr = format!(
+        "Re: {}\r\nIm: {}\r\nZoom: {:E}\r\nIterations: {}\r\nFractalType: {}\r\nPower: 2\r\n",
+        view.x,
+        view.y,
+        4. / view.width,
+        view.max_iter,
+        fractal_type
+    );
+    if view.angle != 0. {
+        kfr += &format!("RotateAngle: {}\r\n", view.angle);
+    }
+    Ok(kfr)
+}
+
+fn from_kfr(text: &str, base: View) -> Result<View, String> {
+    let mut view = base;
+    // Kalles Fraktaler is for deep zooms, so the center has far more digits than f64
+    let (mut re, mut im, mut zoom): (Option<Big>, Option<Big>, Option<f64>) = (None, None, None);
+    view.formula = Formula::Mandelbrot;
+    view.angle = 0.;
+    for line in text.lines() {
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name.trim(), value.trim()),
+            None => continue,
+        };
+        match name {
+            "Re" => re = Some(value.parse()?),
+            "Im" => im = Some(value.parse()?),
+            "Zoom" => zoom = Some(numbers::parse(value)?),
+            "Iterations" => view.max_iter = positive(numbers::parse(value)?)?,
+            "RotateAngle" => view.angle = finite(numbers::parse(value)?)?,
+            "FractalType" => {
+                view.formula = match value {
+                    "0" => Formula::Mandelbrot,
+                    "1" => Formula::BurningShip,
+                    "4" => Formula::Tricorn,
+                    _ => return Err(format!("Fractal type {} isn't supported", value)),
+                }
+            }
+            "Power" if value != "2" => {
+                return Err(format!("Only power 2 is supported, got {}", value))
+            }
+            // Colors and the like
+            _ => (),
+        }
+    }
+    let missing = |name: &str| format!(".kfr has no {}", name);
+    view.x = re.ok_or_else(|| missing("Re"))?;
+    view.y = im.ok_or_else(|| missing("Im"))?;
+    view.width = 4. / positive(finite(zoom.ok_or_else(|| missing("Zoom"))?)?)?;
+    Ok(view)
+}
+
+pub fn to_par(view: &View, name: &str) -> String {
+    let kind = match view.formula {
+        Formula::Mandelbrot => "type=mandel".to_string(),
+        Formula::Julia(x, y) => format!("type=julia params={}/{}", x, y),
+        Formula::BurningShip => formula_type("BurningShip"),
+        Formula::Tricorn => formula_type("Tricorn"),
+        // Fractint can't read our formula file, but this can
+        Formula::Custom(custom) => format!(
+            "type=formula formulafile={} formulaname={}",
+            script::FORMULA_FILE,
+            custom.name
+        ),
+    };
+    let mag = 2. * PAR_ASPECT / view.width;
+    // After the magnification come the stretch, which we don't have, and the rotation
+    let rotation = if view.angle != 0. {
+        format!("/1/{}", view.angle)
+    } else {
+        String::new()
+    };
+    format!(
+        "{} {{ ; from newtons-fractal\n  reset=2004 {}\n  center-mag={}/{}/{}{}\n  maxiter={} inside=0\n  }}\n",
+        name, kind, view.x, view.y, mag, rotation, view.max_iter
+    )
+}
+
+fn formula_type(name: &str) -> String {
+    format!("type=formula formulafile={} formulaname={}", FRM_FILE, name)
+}
+
+// Whether writing the par also needs the frm
+pub fn needs_frm(view: &View) -> bool {
+    matches!(view.formula, Formula::BurningShip | Formula::Tricorn)
+}
+
+// Only the first entry of the file is read
+fn from_par(text: &str, base: View) -> Result<View, String> {
+    let mut view = base;
+    let start = text.find('{').unwrap() + 1;
+    let end = text[start..]
+        .find('}')
+        .ok_or(".par entry has no closing }")?
+        + start;
+    let mut kind = "mandel".to_string();
+    let mut formula_name = None;
+    let mut params = None;
+    let mut place = None;
+    let mut angle = 0.;
+    for line in text[start..end].lines() {
+        // Everything after a semicolon is a comment
+        let line = line.split(';').next().unwrap();
+        for word in line.split_whitespace() {
+            let (name, value) = match word.split_once('=') {
+                Some(pair) => pair,
+                None => continue,
+            };
+            let values = || -> Result<Vec<f64>, String> {
+                value
+                    .split('/')
+                    .map(|v| numbers::parse(v).and_then(finite))
+                    .collect()
+            };
+            match name {
+                "type" => kind = value.to_lowercase(),
+                "formulaname" => formula_name = Some(value.to_lowercase()),
+                "params" => params = Some(values()?),
+                "maxiter" => view.max_iter = positive(numbers::parse(value)?)?,
+                "center-mag" => {
+                    let v = values()?;
+                    if v.len() < 3 {
+                        return Err(format!("center-mag needs x/y/mag, got {}", value));
+                    }
+                    // The center keeps all of its digits
+                    let mut center = value.split('/').map(|v| v.parse::<Big>());
+                    let (x, y) = (center.next().unwrap()?, center.next().unwrap()?);
+                    place = Some((x, y, 2. * PAR_ASPECT / positive(v[2])?));
+                    angle = v.get(4).copied().unwrap_or(0.);
+                }
+                "corners" => {
+                    let v = values()?;
+                    if v.len() < 4 {
+                        return Err(format!("corners needs 4 numbers, got {}", value));
+                    }
+                    let (x_min, x_max, y_min, y_max) = (v[0], v[1], v[2], v[3]);
+                    place = Some((
+                        Big::from_f64((x_min + x_max) / 2.),
+                        Big::from_f64((y_min + y_max) / 2.),
+                        positive(x_max - x_min)?,
+                    ));
+                    angle = 0.;
+                }
+                _ => (),
+            }
+        }
+    }
+    view.formula = match (kind.as_str(), formula_name.as_deref()) {
+        ("mandel", _) => Formula::Mandelbrot,
+        ("julia", _) => match params.as_deref() {
+            Some([x, y, ..]) => Formula::Julia(*x, *y),
+            _ => return Err("julia needs params=x/y".to_string()),
+        },
+        ("formula", Some("burningship")) => Formula::BurningShip,
+        ("formula", Some("tricorn")) => Formula::Tricorn,
+        ("formula", Some(name)) if script::find_any_case(name).is_some() => {
+            Formula::Custom(script::find_any_case(name).unwrap())
+        }
+        ("formula", name) => {
+            return Err(format!(
+                "Formula {} isn't supported",
+                name.unwrap_or("without a name")
+            ))
+        }
+        (kind, _) => return Err(format!("Fractal type {} isn't supported", kind)),
+    };
+    let (x, y, width) = place.ok_or(".par entry has no center-mag or corners")?;
+    view.x = x;
+    view.y = y;
+    view.width = width;
+    view.angle = angle;
+    Ok(view)
+}
+
+// Url safe base64 without padding
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+fn base64(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in bytes.chunks(3) {
+        let mut n = 0u32;
+        for (i, &b) in chunk.iter().enumerate() {
+            n |= (b as u32) << (16 - 8 * i);
+        }
+        for i in 0..=chunk.len() {
+            out.push(BASE64[((n >> (18 - 6 * i)) & 63) as usize] as char);
+        }
+    }
+    out
+}
+
+fn unbase64(code: &str) -> Result<Vec<u8>, String> {
+    let mut out = vec![];
+    let mut n = 0u32;
+    let mut bits = 0;
+    for c in code.bytes() {
+        let value = BASE64
+            .iter()
+            .position(|&b| b == c)
+            .ok_or(format!("{} isn't used in location strings", c as char))?;
+        n = (n << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((n >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deep() -> View {
+        View {
+            x: "-1.768778833000000000000000000000000000000123"
+                .parse()
+                .unwrap(),
+            y: "-0.001738996000000000000000000000000000000456"
+                .parse()
+                .unwrap(),
+            width: 4e-39,
+            max_iter: 20000,
+            angle: 12.5,
+            ..View::default()
+        }
+    }
+
+    // Widths go through a zoom or magnification, which can be off in the last bit
+    fn assert_same(view: &View, expected: &View) {
+        assert!((view.width / expected.width - 1.).abs() < 1e-12);
+        let view = View {
+            width: expected.width,
+            ..view.clone()
+        };
+        assert_eq!(&view, expected);
+    }
+
+    #[test]
+    fn strings_read_back_what_was_written() {
+        let custom = View {
+            formula: Formula::Custom(&script::library()[0]),
+            palette: Palette::Gray,
+            ..View::default()
+        };
+        let julia = View {
+            formula: Formula::Julia(-0.8, 0.156),
+            palette: Palette::Fire,
+            ..deep()
+        };
+        for view in &[View::default(), deep(), custom, julia] {
+            let text = encode(view);
+            assert!(text.starts_with(PREFIX));
+            assert_eq!(&import(&text, View::default()).unwrap(), view);
+        }
+    }
+
+    #[test]
+    fn old_strings_with_an_f64_center_still_read() {
+        let mut bytes = vec![];
+        for x in &[-0.75f64, 0.1, 0.5] {
+            bytes.extend_from_slice(&x.to_le_bytes());
+        }
+        bytes.extend_from_slice(&300u32.to_le_bytes());
+        bytes.extend_from_slice(&[1, 2]);
+        let view = import(&format!("{}{}", PREFIX_F64, base64(&bytes)), deep()).unwrap();
+        let expected = View {
+            x: Big::from_f64(-0.75),
+            y: Big::from_f64(0.1),
+            width: 0.5,
+            max_iter: 300,
+            formula: Formula::Tricorn,
+            palette: Palette::Fire,
+            angle: 0.,
+        };
+        assert_eq!(view, expected);
+    }
+
+    #[test]
+    fn bad_strings_say_what_is_wrong() {
+        let e = |s: &str| import(s, View::default()).err().unwrap();
+        assert_eq!(e("nf2.AB*C"), "* isn't used in location strings");
+        assert_eq!(e("nf9.AAAA"), "Not a location string, .kfr or .par");
+        assert_eq!(e("nf2.AA"), "Location string is cut short");
+        let long = encode(&View::default()) + &"A".repeat(16);
+        assert_eq!(e(&long), "Location string is too long");
+    }
+
+    #[test]
+    fn kfr_reads_back_what_was_written() {
+        for formula in &[Formula::Mandelbrot, Formula::BurningShip, Formula::Tricorn] {
+            let view = View {
+                formula: *formula,
+                ..deep()
+            };
+            let kfr = to_kfr(&view).unwrap();
+            assert_same(&import(&kfr, View::default()).unwrap(), &view);
+        }
+        let julia = View {
+            formula: Formula::Julia(0., 1.),
+            ..deep()
+        };
+        assert_eq!(
+            to_kfr(&julia).err().unwrap(),
+            "Kalles Fraktaler has no Julia sets"
+        );
+    }
+
+    #[test]
+    fn kfr_keeps_every_digit_of_the_center() {
+        let kfr = "Re: -0.7436438870371587048926434520530000000001\r\n\
+                   Im: 0.13182590420531197049\r\nZoom: 1E30\r\nIterations: 5000\r\n";
+        let view = import(kfr, View::default()).unwrap();
+        let re: Big = "-0.7436438870371587048926434520530000000001"
+            .parse()
+            .unwrap();
+        assert_eq!(view.x, re);
+        assert_ne!(view.x, Big::from_f64(re.to_f64()));
+        assert!((view.width / 4e-30 - 1.).abs() < 1e-12);
+        assert_eq!(view.max_iter, 5000);
+    }
+
+    #[test]
+    fn kfr_needs_a_place_and_a_fractal_it_knows() {
+        let e = |s: &str| import(s, View::default()).err().unwrap();
+        assert_eq!(e("Im: 0\nZoom: 1\nRe: "), "Not a valid number: ");
+        assert_eq!(e("Re: 0\nZoom: 1"), ".kfr has no Im");
+        assert_eq!(e("Im: 0\nRe: 0"), ".kfr has no Zoom");
+        assert_eq!(e("Re: 0\nIm: 0\nZoom: 1E400"), "Not a finite number: inf");
+        assert_eq!(
+            e("Re: 0\nIm: 0\nZoom: 1\nFractalType: 7"),
+            "Fractal type 7 isn't supported"
+        );
+        assert_eq!(
+            e("Re: 0\nIm: 0\nZoom: 1\nPower: 3"),
+            "Only power 2 is supported, got 3"
+        );
+    }
+
+    #[test]
+    fn par_reads_back_what_was_written() {
+        let custom = View {
+            formula: Formula::Custom(&script::library()[0]),
+            ..deep()
+        };
+        for formula in &[
+            Formula::Mandelbrot,
+            Formula::BurningShip,
+            Formula::Tricorn,
+            Formula::Julia(-0.8, 0.156),
+        ] {
+            let view = View {
+                formula: *formula,
+                ..deep()
+            };
+            let par = to_par(&view, "spot");
+            assert_eq!(needs_frm(&view), par.contains(FRM_FILE));
+            assert_same(&import(&par, View::default()).unwrap(), &view);
+        }
+        assert_same(
+            &import(&to_par(&custom, "spot"), View::default()).unwrap(),
+            &custom,
+        );
+    }
+
+    #[test]
+    fn par_corners_and_errors() {
+        let par = "spot { reset=2004 type=mandel corners=-2/1/-1/1 maxiter=64 }";
+        let view = import(par, deep()).unwrap();
+        assert_eq!((view.x.to_f64(), view.y.to_f64()), (-0.5, 0.));
+        assert_eq!((view.width, view.max_iter, view.angle), (3., 64, 0.));
+        let e = |s: &str| import(s, View::default()).err().unwrap();
+        assert_eq!(
+            e("a { type=mandel }"),
+            ".par entry has no center-mag or corners"
+        );
+        assert_eq!(
+            e("a { type=lambda center-mag=0/0/1 }"),
+            "Fractal type lambda isn't supported"
+        );
+        assert_eq!(
+            e("a { type=mandel center-mag=0/0 }"),
+            "center-mag needs x/y/mag, got 0/0"
+        );
+        assert_eq!(
+            e("a { type=mandel center-mag=0/0/1"),
+            ".par entry has no closing }"
+        );
+    }
+}
@@ -0,0 +1,349 @@
+// Lyapunov fractals. The logistic map x -> r x (1 - x) is iterated with r
+// switching between a and b in a repeating sequence, like AABAB, and every
+// point (a, b) is colored by how fast nearby starting values drift apart.
+// Negative exponents settle down to a cycle, and positive ones are chaotic.
+use super::bindings::Bindings;
+use super::real::Real;
+use super::session::Session;
+use super::viewport::{Controls, Viewport, ViewportOp};
+use crate::drawing::basic_render_op::{BasicOp, BasicOpImp};
+use crate::rendering::{CancelToken, Pixel, RenderOp, RenderOpReference, Tile};
+use crate::windows::Window;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+
+// Size of the squares in the first, roughest pass
+const COARSE_BLOCK: usize = 8;
+
+// Iterations thrown away before measuring, so x has settled first
+const WARMUP: usize = 200;
+
+// Where x starts, the critical point of the map
+const START: f64 = 0.5;
+
+// Sequences the next formula key goes through, the ones with the best known pictures
+const PRESETS: [&str; 5] = ["AB", "AABAB", "BBBBBBAAAAAA", "AABB", "ABBAB"];
+
+// The map only stays in [0, 1] for r up to 4
+const OFF_MAP: Pixel = (0, 0, 0, 255);
+
+// Whether a or b is used at every step, repeating
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sequence(Vec<bool>);
+
+impl Default for Sequence {
+    fn default() -> Self {
+        PRESETS[0].parse().unwrap()
+    }
+}
+
+impl fmt::Display for Sequence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for &b in &self.0 {
+            write!(f, "{}", if b { 'B' } else { 'A' })?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Sequence {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let steps = s
+            .chars()
+            .map(|c| match c.to_ascii_uppercase() {
+                'A' => Ok(false),
+                'B' => Ok(true),
+                _ => Err(format!("A sequence is only A's and B's, got {}", s)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if steps.is_empty() {
+            return Err("A sequence needs at least one A or B".to_string());
+        }
+        Ok(Sequence(steps))
+    }
+}
+
+impl Sequence {
+    // Average of ln |r (1 - 2x)| along the orbit, the rate at which
+    // neighboring values of x drift apart every step
+    fn exponent(&self, a: f64, b: f64, max_iter: usize) -> f64 {
+        let steps = &self.0;
+        let mut x = START;
+        let mut rates = steps
+            .iter()
+            .map(|&b_step| if b_step { b } else { a })
+            .cycle();
+        for r in rates.by_ref().take(WARMUP) {
+            x = r * x * (1. - x);
+        }
+        let mut sum = 0.;
+        for r in rates.take(max_iter) {
+            sum += (r * (1. - 2. * x)).abs().ln();
+            x = r * x * (1. - x);
+            // x landed right on the critical point, so it's as stable as it gets
+            if sum == f64::NEG_INFINITY {
+                break;
+            }
+        }
+        sum / max_iter as f64
+    }
+}
+
+struct Data {
+    sequence: Sequence,
+    controls: Controls,
+    // Size of the squares of the pass being rendered, 0 once they're all done
+    block: usize,
+}
+
+pub struct Lyapunov {
+    data: Data,
+    op: BasicOp,
+}
+
+impl Lyapunov {
+    pub fn init(
+        window: Option<Arc<Mutex<Window>>>,
+        start: &Session,
+        view_given: bool,
+        sequence: Sequence,
+    ) -> RenderOpReference {
+        let (w, h) = BasicOp::size(window.as_ref(), start.width, start.height);
+        let bindings = Bindings::load("lyapunov").unwrap_or_else(|e| {
+            println!("Couldn't load controls: {}", e);
+            Bindings::default()
+        });
+        // The interesting part is a and b between 2 and 4, so
+        // start there unless a view was asked for
+        let view = &start.view;
+        let (x, y, width) = if view_given {
+            (view.x.to_f64(), view.y.to_f64(), view.width)
+        } else {
+            (3., 3., 2.)
+        };
+        // Like a graph, b goes up the screen
+        let viewport = Viewport {
+            x,
+            y,
+            width,
+            pixel_width: w as u32,
+            pixel_height: h as u32,
+            y_up: true,
+        };
+        let data = Data {
+            sequence,
+            controls: Controls::new(viewport, start.view.max_iter, bindings),
+            block: COARSE_BLOCK,
+        };
+        let op = BasicOp::init(window, w, h, 0, 0);
+        Arc::new(RwLock::new(Box::new(Lyapunov { data, op })))
+    }
+
+    fn exponent(&self, a: f64, b: f64) -> f64 {
+        self.data
+            .sequence
+            .exponent(a, b, self.data.controls.max_iter)
+    }
+
+    // Stable points go from black at 0 to yellow, and chaotic ones to blue
+    fn color(exponent: f64) -> Pixel {
+        if exponent < 0. {
+            let t = 1. - exponent.exp();
+            ((255. * t) as u8, (210. * t) as u8, (40. * t) as u8, 255)
+        } else {
+            let t = 1. - (-exponent).exp();
+            ((30. * t) as u8, (60. * t) as u8, (255. * t) as u8, 255)
+        }
+    }
+
+    // Where the corner of a pixel is on the (a, b) plane
+    fn plane(&self, pixel_x: f64, pixel_y: f64) -> (f64, f64) {
+        self.data.controls.viewport.plane(pixel_x, pixel_y)
+    }
+
+    fn pixel(&self, pixel_x: usize, pixel_y: usize) -> Pixel {
+        let (a, b) = self.plane(pixel_x as f64, pixel_y as f64);
+        if !(0. ..=4.).contains(&a) || !(0. ..=4.).contains(&b) {
+            return OFF_MAP;
+        }
+        Self::color(self.exponent(a, b))
+    }
+
+    // Whether the corner of a square was the corner of one in the last,
+    // twice as coarse pass too
+    fn copied(block: usize, pixel_x: usize, pixel_y: usize) -> bool {
+        block < COARSE_BLOCK
+            && pixel_x.is_multiple_of(block * 2)
+            && pixel_y.is_multiple_of(block * 2)
+    }
+
+    // The color the last pass gave a pixel
+    fn previous(&self, pixel_x: usize, pixel_y: usize) -> Pixel {
+        let present: &[Pixel] = self.get_present_buffer().into();
+        present[pixel_y * self.data.controls.viewport.pixel_width as usize + pixel_x]
+    }
+}
+
+impl ViewportOp for Lyapunov {
+    fn controls(&self) -> &Controls {
+        &self.data.controls
+    }
+    fn controls_mut(&mut self) -> &mut Controls {
+        &mut self.data.controls
+    }
+    // Back to the roughest pass
+    fn reset(&mut self) {
+        self.data.block = COARSE_BLOCK;
+    }
+    // The next preset after the current sequence, or the first one
+    // if it's not a preset at all
+    fn next_formula(&mut self) {
+        let current = self.data.sequence.to_string();
+        let index = PRESETS.iter().position(|&p| p == current);
+        let next = index.map_or(0, |i| (i + 1) % PRESETS.len());
+        self.data.sequence = PRESETS[next].parse().unwrap();
+        println!("Sequence: {}", self.data.sequence);
+        self.reset();
+    }
+    fn hud_lines(&self) -> Vec<String> {
+        let d = &self.data;
+        let viewport = &d.controls.viewport;
+        let mut lines = vec![
+            format!("Sequence: {}", d.sequence),
+            format!("Center: a {} b {}", viewport.x, viewport.y),
+            format!("Width: {:.3e}", viewport.width),
+            format!("Iterations: {}", d.controls.max_iter),
+        ];
+        let (mouse_x, mouse_y) = d.controls.mouse;
+        let (a, b) = self.plane(mouse_x as f64, mouse_y as f64);
+        if (0. ..=4.).contains(&a) && (0. ..=4.).contains(&b) {
+            lines.push(format!("Cursor: a {:.6} b {:.6}", a, b));
+            lines.push(format!("Exponent: {:.4}", self.exponent(a, b)));
+        } else {
+            lines.push("Cursor: off the map".to_string());
+        }
+        lines
+    }
+}
+
+impl BasicOpImp for Lyapunov {
+    fn get_op(&self) -> &BasicOp {
+        &self.op
+    }
+    fn get_op_mut(&mut self) -> &mut BasicOp {
+        &mut self.op
+    }
+    fn bindings(&self) -> Option<&Bindings> {
+        Some(&self.data.controls.bindings)
+    }
+    // Every square of the pass is filled with the color of its top left
+    // corner. Corners that were also corners in the last pass are copied
+    // from it instead of being computed again.
+    fn draw(&self, tile: &mut Tile, token: &CancelToken) {
+        let block = self.data.block.max(1);
+        let (tile_x, tile_y) = tile.position();
+        let (width, height) = tile.dimensions();
+        for y in (tile_y - tile_y % block..tile_y + height).step_by(block) {
+            if token.is_cancelled() {
+                return;
+            }
+            for x in (tile_x - tile_x % block..tile_x + width).step_by(block) {
+                let color = if Self::copied(block, x, y) {
+                    self.previous(x, y)
+                } else {
+                    self.pixel(x, y)
+                };
+                for py in y.max(tile_y)..(y + block).min(tile_y + height) {
+                    for px in x.max(tile_x)..(x + block).min(tile_x + width) {
+                        tile[(px - tile_x, py - tile_y)] = color;
+                    }
+                }
+            }
+        }
+    }
+    fn modify_data(&mut self) {
+        // Move on to the next, finer pass
+        self.data.block /= 2;
+    }
+    fn is_complete(&self) -> bool {
+        self.data.block == 0
+    }
+    fn handle_events(&mut self) -> bool {
+        self.handle_viewport_events()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sequences_are_written_the_way_they_are_read() {
+        for &text in PRESETS.iter() {
+            let sequence: Sequence = text.parse().unwrap();
+            assert_eq!(sequence.to_string(), text);
+        }
+        let sequence: Sequence = "aAbBa".parse().unwrap();
+        assert_eq!(sequence, Sequence(vec![false, false, true, true, false]));
+        assert_eq!(sequence.to_string(), "AABBA");
+        assert_eq!(Sequence::default().to_string(), PRESETS[0]);
+    }
+
+    #[test]
+    fn sequences_are_only_as_and_bs() {
+        assert_eq!(
+            "".parse::<Sequence>(),
+            Err("A sequence needs at least one A or B".to_string())
+        );
+        for &text in &["ABC", "A B", "AB1", "ÄB"] {
+            assert_eq!(
+                text.parse::<Sequence>(),
+                Err(format!("A sequence is only A's and B's, got {}", text))
+            );
+        }
+    }
+
+    #[test]
+    fn exponents_tell_chaos_from_cycles() {
+        let ab = Sequence::default();
+        // The logistic map at r = 4 is fully chaotic, with exponent ln 2.
+        // Right at 4 the critical point goes to 1 and then sits on the
+        // fixed point 0, which drifts apart even faster at ln 4.
+        let chaos = ab.exponent(3.9999, 3.9999, 100_000);
+        assert!((chaos - 2f64.ln()).abs() < 0.02, "exponent {}", chaos);
+        let edge = ab.exponent(4., 4., 1000);
+        assert!((edge - 4f64.ln()).abs() < 1e-9, "exponent {}", edge);
+        // At 3.2 it settles on a cycle of two
+        let cycle = ab.exponent(3.2, 3.2, 10_000);
+        assert!(cycle < 0., "exponent {}", cycle);
+        // Only the rates in the sequence count, in its order
+        let aabab: Sequence = "AABAB".parse().unwrap();
+        let swapped: Sequence = "BBABA".parse().unwrap();
+        assert_eq!(
+            aabab.exponent(3.4, 3.9, 1000),
+            swapped.exponent(3.9, 3.4, 1000)
+        );
+        assert!(aabab.exponent(2.5, 2.5, 1000) < 0.);
+    }
+
+    #[test]
+    fn passes_only_compute_corners_the_last_one_didnt() {
+        // Nothing to copy from before the first pass
+        assert!(!Lyapunov::copied(COARSE_BLOCK, 0, 0));
+        assert!(!Lyapunov::copied(COARSE_BLOCK, 16, 8));
+        for &block in &[4, 2, 1] {
+            assert!(Lyapunov::copied(block, 0, 0));
+            assert!(Lyapunov::copied(block, block * 2, block * 6));
+            assert!(!Lyapunov::copied(block, block, 0));
+            assert!(!Lyapunov::copied(block, 0, block * 3));
+        }
+        // Every pass after the first computes three of every four corners
+        let corners = (0..16)
+            .step_by(4)
+            .flat_map(|y| (0..16).step_by(4).map(move |x| (x, y)));
+        let copied = corners.filter(|&(x, y)| Lyapunov::copied(4, x, y)).count();
+        assert_eq!(copied, 4);
+    }
+}
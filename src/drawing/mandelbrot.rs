@@ -1,9 +1,120 @@
+use super::bindings::{Action, Bindings};
+use super::kernel::{self, LANES};
+use super::location;
+use super::mapping::Mapping;
+use super::real::{Big, DoubleDouble, Real};
+use super::session::Session;
+use super::view::{self, Bookmarks, Coloring, Formula, Palette, View};
 use crate::drawing::basic_render_op::{BasicOp, BasicOpImp};
-use crate::events::{MainEvent, SdlEvent};
-use crate::rendering::{Pixel, Pixels, RenderOpReference};
+use crate::events::{MainEvent, SdlEvent, Setting};
+use crate::rendering::{
+    CancelToken, FrameStatus, Pixel, Pixels, RenderOp, RenderOpReference, Tile,
+};
 use crate::windows::Window;
 use sdl2::rect::Rect;
+use sdl2::video::WindowPos;
 use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+// How long the mouse has to rest during a drag before the view is rendered
+const DRAG_PAUSE: Duration = Duration::from_millis(150);
+
+// How quickly a wheel zoom glides to where the wheel turned it to.
+// After this long about two thirds of the way is done.
+const WHEEL_GLIDE: Duration = Duration::from_millis(80);
+
+// Scale of one wheel tick
+const SCALE_WHEEL: f64 = 1.25;
+
+// Boxes thinner than this are taken as a click, not a zoom
+const MIN_BOX: i32 = 4;
+
+// Color of pixels the mapping doesn't put any point on. It counts as
+// inside, so the rect logic fills around them like around the set.
+const OFF_MAP: (Pixel, bool) = ((0, 0, 0, 255), true);
+
+// How many views back the history goes
+const HISTORY_LIMIT: usize = 1000;
+
+// Digits a bignum center keeps past the size of a pixel
+const SPARE_DIGITS: u64 = 20;
+
+// Size of the blocks in the first, coarsest preview pass.
+// Every pass after it halves the size until it reaches 1.
+const COARSE_BLOCK: usize = 8;
+
+// How many samples are taken for every pixel
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Sampling {
+    // One sample at the pixel's corner, the old behaviour
+    Single,
+    // An n by n evenly spaced grid inside of the pixel
+    Grid(usize),
+    // An n by n grid where every sample is moved randomly
+    // inside of its own cell
+    Jittered(usize),
+    // Single samples everywhere, then an n by n grid only for
+    // pixels that differ from their neighbors by more than threshold
+    Adaptive { size: usize, threshold: u8 },
+}
+
+impl Sampling {
+    fn next(self) -> Self {
+        match self {
+            Self::Single => Self::Grid(2),
+            Self::Grid(2) => Self::Grid(4),
+            Self::Grid(_) => Self::Jittered(4),
+            Self::Jittered(_) => Self::Adaptive {
+                size: 4,
+                threshold: 12,
+            },
+            Self::Adaptive { .. } => Self::Single,
+        }
+    }
+}
+
+// What the escape loop counts with. Deep zooms need more than f64 to tell
+// pixels apart, but the wider types are much slower, so they're only used
+// once a pixel gets too small next to the coordinates around it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Precision {
+    Double,
+    DoubleDouble,
+    // With this many digits
+    Big(u64),
+}
+
+impl Precision {
+    fn name(self) -> String {
+        match self {
+            Self::Double => "double".to_string(),
+            Self::DoubleDouble => "double-double".to_string(),
+            Self::Big(digits) => format!("bignum, {} digits", digits),
+        }
+    }
+}
+
+// Where the full resolution pass can take already computed pixels from
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Reuse {
+    Nothing,
+    // The last preview pass sampled every pixel with even coordinates
+    Preview,
+    // The view moved since the last full frame. The pixel at (x, y) used to be
+    // at (x * scale + offset_x, y * scale + offset_y) in the present buffer.
+    Moved {
+        scale: f64,
+        offset_x: f64,
+        offset_y: f64,
+    },
+}
+
+// A mouse drag that's moving the view
+struct Drag {
+    // How far the mouse moved since the view was last updated
+    pending: (i32, i32),
+    moved_at: Instant,
+}
 
 struct Data {
     x_ratio: f64,
@@ -11,11 +122,55 @@ struct Data {
     y_ratio: f64,
     y_offset: f64,
     max_iter: usize,
+    formula: Formula,
+    palette: Palette,
+    coloring: Coloring,
+    mapping: Mapping,
+    // Points further from 0 than this have escaped
+    bailout: f64,
+    // Shift of the palette as a fraction of its length, and how
+    // many palette entries one iteration goes through
+    palette_offset: f64,
+    palette_scale: f64,
+    sampling: Sampling,
+    // Views that were shown, and which of them is the current one
+    history: Vec<View>,
+    history_pos: usize,
+    bookmarks: Bookmarks,
+    bindings: Bindings,
+    // Where the session is saved to and loaded from
+    session_file: String,
+    // Block size of the next pass, 0 once the full resolution pass is done
+    block: usize,
+    // Time spent on the passes of the current view so far
+    render_time: Duration,
+    hud: bool,
+    // Whether the hud shows the worker stats of the last frame
+    stats: bool,
+    help: bool,
+    reuse: Reuse,
+    // Last known position of the mouse in the window
+    mouse: (i32, i32),
+    drag: Option<Drag>,
+    // Where the box being dragged out for a box zoom started
+    select: Option<(i32, i32)>,
+    // How far the view was dragged since the presented frame was drawn
+    unpresented: (i32, i32),
+    // Direction of the mouse from the center while the right button turns the view
+    twist: Option<f64>,
+    // Wheel ticks not zoomed by yet, and when the zoom last caught up
+    wheel: f64,
+    wheel_at: Instant,
     //
     window_width: f64,
     window_height: f64,
     window_x: f64,
     window_y: f64,
+    // The center to more digits than window_x and window_y, which are
+    // this rounded to f64. Zooms past what f64 can tell apart draw around it.
+    center: (Big, Big),
+    // Degrees the view is turned counterclockwise around its center
+    angle: f64,
     width: u32,
     height: u32,
 }
@@ -26,26 +181,24 @@ pub struct Mandelbrot {
 }
 
 impl Mandelbrot {
-    pub fn init(window: Arc<Mutex<Window>>) -> RenderOpReference {
-        let (w, h) = window.lock().unwrap().size();
-        let data = Self::init_data(w as u32, h as u32);
+    pub fn init(
+        window: Option<Arc<Mutex<Window>>>,
+        start: &Session,
+        session_file: String,
+    ) -> RenderOpReference {
+        let (w, h) = BasicOp::size(window.as_ref(), start.width, start.height);
+        let data = Self::init_data(w as u32, h as u32, start, session_file);
         let op = BasicOp::init(window, w, h, 0, 0);
         Arc::new(RwLock::new(Box::new(Mandelbrot { data, op })))
     }
 
-    fn init_data(width: u32, height: u32) -> Data {
+    fn init_data(width: u32, height: u32, session: &Session, session_file: String) -> Data {
+        let start = &session.view;
         // Basic numbers
-        let window_height = 1.;
-        let window_width = window_height * (width as f64 / height as f64);
-        // 0.001643721971153 − 0.822467633298876i
-        // -0.761574 - 0.0847596i
-        // -e/7 - e/20i
-        // -0.10715079727776 - 0.91210278793461i
-        // -1.74790375491685 + 0.00194820459426i
-        // -0.52303294558693 + 0.52633610977926i
-        //
-        let window_x = 0.001643721971153;
-        let window_y = -0.822467633298876;
+        let window_width = start.width;
+        let window_height = window_width * (height as f64 / width as f64);
+        let window_x = start.x.to_f64();
+        let window_y = start.y.to_f64();
         // Calculate useful numbers from those
         let (x_ratio, x_offset, y_ratio, y_offset) = Self::get_mandelbrot_vals(
             window_width,
@@ -55,18 +208,54 @@ impl Mandelbrot {
             window_y,
             height,
         );
-        let max_iter = 1 << 8;
+        let sampling = Sampling::Single;
+        let bindings = Bindings::load("mandelbrot").unwrap_or_else(|e| {
+            println!("Couldn't load controls: {}", e);
+            Bindings::default()
+        });
+        let bookmarks = view::load_bookmarks().unwrap_or_else(|e| {
+            println!("Couldn't load bookmarks: {}", e);
+            Bookmarks::default()
+        });
         Data {
             x_ratio,
             x_offset,
             y_ratio,
             y_offset,
-            max_iter,
+            max_iter: start.max_iter,
+            formula: start.formula,
+            palette: start.palette,
+            coloring: session.coloring,
+            mapping: session.mapping,
+            bailout: session.bailout,
+            palette_offset: session.palette_offset,
+            palette_scale: session.palette_scale,
+            sampling,
+            history: vec![start.clone()],
+            history_pos: 0,
+            bookmarks,
+            bindings,
+            session_file,
+            block: COARSE_BLOCK,
+            render_time: Duration::ZERO,
+            hud: true,
+            stats: false,
+            help: false,
+            reuse: Reuse::Nothing,
+            mouse: (width as i32 / 2, height as i32 / 2),
+            drag: None,
+            select: None,
+            unpresented: (0, 0),
+            twist: None,
+            wheel: 0.,
+            wheel_at: Instant::now(),
             //
             window_width,
             window_height,
             window_x,
             window_y,
+            center: (start.x.clone(), start.y.clone()),
+            angle: start.angle,
             width,
             height,
         }
@@ -100,40 +289,425 @@ impl Mandelbrot {
         )
     }
 
-    fn draw_iter(&self, pixel_x: usize, pixel_y: usize) -> (Pixel, bool) {
+    // Colors a batch of pixels with the current sampling
+    fn draw_iters(&self, pixels: &[(usize, usize)]) -> Vec<(Pixel, bool)> {
+        match self.data.sampling {
+            Sampling::Single | Sampling::Adaptive { .. } => {
+                self.super_sample(pixels, 1, false, true)
+            }
+            Sampling::Grid(size) => self.super_sample(pixels, size, false, false),
+            Sampling::Jittered(size) => self.super_sample(pixels, size, true, false),
+        }
+    }
+
+    // Every pixel becomes the average of a size by size grid of samples,
+    // and all the samples of the batch go through the kernel together.
+    fn super_sample(
+        &self,
+        pixels: &[(usize, usize)],
+        size: usize,
+        jitter: bool,
+        reuse: bool,
+    ) -> Vec<(Pixel, bool)> {
+        let mut colors = vec![((0, 0, 0, 255), true); pixels.len()];
+        // None for pixels taken from the present buffer
+        let mut sums = vec![None; pixels.len()];
+        let mut points = Vec::with_capacity(pixels.len() * size * size);
+        let mut owners = Vec::with_capacity(pixels.len() * size * size);
+        for (i, &(pixel_x, pixel_y)) in pixels.iter().enumerate() {
+            if let Some(color) = self.reused(pixel_x, pixel_y).filter(|_| reuse) {
+                colors[i] = color;
+                continue;
+            }
+            sums[i] = Some((0, 0, 0));
+            Self::sample_points(pixel_x, pixel_y, size, jitter, &mut points);
+            owners.resize(points.len(), i);
+        }
+        for (&i, ((r, g, b, _), inside)) in owners.iter().zip(self.sample(&points)) {
+            if let Some(sum) = &mut sums[i] {
+                sum.0 += r as usize;
+                sum.1 += g as usize;
+                sum.2 += b as usize;
+            }
+            colors[i].1 &= inside;
+        }
+        let count = size * size;
+        for (color, sum) in colors.iter_mut().zip(sums) {
+            if let Some(sum) = sum {
+                color.0 = (
+                    (sum.0 / count) as u8,
+                    (sum.1 / count) as u8,
+                    (sum.2 / count) as u8,
+                    255,
+                );
+            }
+        }
+        colors
+    }
+
+    // Whether zooming by the scale maps whole pixels onto whole pixels,
+    // either way around
+    fn whole_factor(scale: f64) -> bool {
+        let factor = if scale < 1. { 1. / scale } else { scale };
+        (factor - factor.round()).abs() < 1e-3
+    }
+
+    // Adds the size by size samples of a pixel to points. Offsets are
+    // centered around the pixel, so that the single sample position
+    // stays the middle of the grid.
+    fn sample_points(
+        pixel_x: usize,
+        pixel_y: usize,
+        size: usize,
+        jitter: bool,
+        points: &mut Vec<(f64, f64)>,
+    ) {
+        for sy in 0..size {
+            for sx in 0..size {
+                let (jx, jy) = if jitter {
+                    Self::jitter(pixel_x, pixel_y, sy * size + sx)
+                } else {
+                    (0.5, 0.5)
+                };
+                let x = pixel_x as f64 + (sx as f64 + jx) / size as f64 - 0.5;
+                let y = pixel_y as f64 + (sy as f64 + jy) / size as f64 - 0.5;
+                points.push((x, y));
+            }
+        }
+    }
+
+    // Looks for the pixel in the present buffer, for when the full resolution
+    // pass doesn't have to compute it again.
+    fn reused(&self, pixel_x: usize, pixel_y: usize) -> Option<(Pixel, bool)> {
+        if self.data.block != 1 {
+            return None;
+        }
+        match self.data.reuse {
+            Reuse::Nothing => None,
+            Reuse::Preview => {
+                if (pixel_x | pixel_y) & 1 != 0 {
+                    return None;
+                }
+                Some(self.previous(pixel_x, pixel_y))
+            }
+            Reuse::Moved {
+                scale,
+                offset_x,
+                offset_y,
+            } => {
+                // Only pixels that land exactly on an old pixel can be used
+                let old = |pixel: usize, offset: f64, max: u32| {
+                    let old = pixel as f64 * scale + offset;
+                    let rounded = old.round();
+                    if (old - rounded).abs() < 1e-3 && rounded >= 0. && rounded < max as f64 {
+                        Some(rounded as usize)
+                    } else {
+                        None
+                    }
+                };
+                let old_x = old(pixel_x, offset_x, self.data.width)?;
+                let old_y = old(pixel_y, offset_y, self.data.height)?;
+                Some(self.previous(old_x, old_y))
+            }
+        }
+    }
+
+    // The part of the tile, in tile coordinates, where every pixel can be
+    // copied from the present buffer. Only pans and integer zoom outs have
+    // one, other zooms just reuse scattered pixels through draw_iter.
+    fn covered(&self, tile: &Tile) -> Option<(usize, usize, usize, usize)> {
+        let (scale, offset_x, offset_y) = match self.data.reuse {
+            Reuse::Moved {
+                scale,
+                offset_x,
+                offset_y,
+            } if self.data.block == 1 => (scale, offset_x, offset_y),
+            _ => return None,
+        };
+        let is_int = |v: f64| (v - v.round()).abs() < 1e-6;
+        if scale < 1. || !is_int(scale) || !is_int(offset_x) || !is_int(offset_y) {
+            return None;
+        }
+        let scale = scale.round() as isize;
+        // First and last pixel, along one axis, that land inside the old frame
+        let range = |start: usize, len: usize, offset: f64, max: u32| {
+            let offset = offset.round() as isize;
+            let first = (-offset).div_euclid(scale) + ((-offset).rem_euclid(scale) != 0) as isize;
+            let last = (max as isize - 1 - offset).div_euclid(scale);
+            let first = first.max(start as isize);
+            let last = last.min((start + len) as isize - 1);
+            if first > last {
+                None
+            } else {
+                Some(((first as usize) - start, (last - first + 1) as usize))
+            }
+        };
+        let (tile_x, tile_y) = tile.position();
+        let (width, height) = tile.dimensions();
+        let (x, w) = range(tile_x, width, offset_x, self.data.width)?;
+        let (y, h) = range(tile_y, height, offset_y, self.data.height)?;
+        Some((x, y, w, h))
+    }
+
+    fn copy_covered(
+        &self,
+        tile: &mut Tile,
+        (x_pos, y_pos, width, height): (usize, usize, usize, usize),
+    ) {
+        let (tile_x, tile_y) = tile.position();
+        for y in y_pos..y_pos + height {
+            for x in x_pos..x_pos + width {
+                let (color, _) = self.reused(tile_x + x, tile_y + y).unwrap();
+                tile[(x, y)] = color;
+            }
+        }
+    }
+
+    // Marks every pixel of a width by height tile that differs by more than
+    // threshold from a neighbor. color gives the pixels of the tile, and
+    // whatever is known about the ones around it. Pixels filled by
+    // fill_rect are flat, so they never get marked.
+    fn edges(
+        width: usize,
+        height: usize,
+        threshold: u8,
+        color: impl Fn(isize, isize) -> Option<Pixel>,
+    ) -> Vec<bool> {
+        let differs = |a: Pixel, b: Pixel| {
+            let dif = |c1: u8, c2: u8| (c1 as i16 - c2 as i16).abs() > threshold as i16;
+            dif(a.0, b.0) || dif(a.1, b.1) || dif(a.2, b.2)
+        };
+        let inside =
+            |x: isize, y: isize| x >= 0 && y >= 0 && x < width as isize && y < height as isize;
+        let mut marked = vec![false; width * height];
+        for y in 0..height as isize {
+            for x in 0..width as isize {
+                let here = color(x, y).unwrap();
+                // Pairs inside of the tile are looked at once, from their
+                // left or top pixel, and pairs across the edge from inside
+                for (nx, ny) in [(x + 1, y), (x, y + 1), (x - 1, y), (x, y - 1)] {
+                    if (nx < x || ny < y) && inside(nx, ny) {
+                        continue;
+                    }
+                    let there = match color(nx, ny) {
+                        Some(there) => there,
+                        None => continue,
+                    };
+                    if differs(here, there) {
+                        marked[y as usize * width + x as usize] = true;
+                        if inside(nx, ny) {
+                            marked[ny as usize * width + nx as usize] = true;
+                        }
+                    }
+                }
+            }
+        }
+        marked
+    }
+
+    // The color the last pass gave a pixel, if there's one to go by
+    fn last_pass(&self, pixel_x: isize, pixel_y: isize) -> Option<Pixel> {
+        let d = &self.data;
+        if pixel_x < 0 || pixel_y < 0 || pixel_x >= d.width as isize || pixel_y >= d.height as isize
+        {
+            return None;
+        }
+        let (pixel_x, pixel_y) = (pixel_x as usize, pixel_y as usize);
+        match d.reuse {
+            Reuse::Nothing => None,
+            // The preview gave every 2x2 square the color of its top left pixel
+            Reuse::Preview => Some(self.previous(pixel_x & !1, pixel_y & !1).0),
+            Reuse::Moved { .. } => self.reused(pixel_x, pixel_y).map(|(color, _)| color),
+        }
+    }
+
+    fn previous(&self, pixel_x: usize, pixel_y: usize) -> (Pixel, bool) {
+        let present: &[Pixel] = self.get_present_buffer().into();
+        let color = present[pixel_y * self.data.width as usize + pixel_x];
+        (color, color.0 == 0 && color.1 == 0 && color.2 == 0)
+    }
+
+    // Draws a preview where every block x block square has the color of
+    // its top left pixel. Squares that were also the corner of a square in
+    // the last pass are copied from it instead of being computed again.
+    fn draw_blocks(&self, tile: &mut Tile, block: usize, token: &CancelToken) {
+        let (tile_x, tile_y) = tile.position();
+        let (width, height) = tile.dimensions();
+        // Top left corners of every square touching the tile
+        let mut corners = vec![];
+        for y in (tile_y - tile_y % block..tile_y + height).step_by(block) {
+            for x in (tile_x - tile_x % block..tile_x + width).step_by(block) {
+                corners.push((x, y));
+            }
+        }
+        if token.is_cancelled() {
+            return;
+        }
+        let copied = |(x, y): (usize, usize)| {
+            block < COARSE_BLOCK && x % (block * 2) == 0 && y % (block * 2) == 0
+        };
+        let computed: Vec<_> = corners
+            .iter()
+            .filter(|corner| !copied(**corner))
+            .map(|&(x, y)| (x as f64, y as f64))
+            .collect();
+        let mut samples = self.sample(&computed).into_iter();
+        for &(corner_x, corner_y) in &corners {
+            let color = if copied((corner_x, corner_y)) {
+                self.previous(corner_x, corner_y).0
+            } else {
+                samples.next().unwrap().0
+            };
+            // Only the part of the square inside of the tile
+            for y in corner_y.max(tile_y)..(corner_y + block).min(tile_y + height) {
+                for x in corner_x.max(tile_x)..(corner_x + block).min(tile_x + width) {
+                    tile[(x - tile_x, y - tile_y)] = color;
+                }
+            }
+        }
+    }
+
+    // Cheap hash of the pixel and sample index, so the jitter is random
+    // looking but stays the same between frames.
+    fn jitter(pixel_x: usize, pixel_y: usize, sample: usize) -> (f64, f64) {
+        let mut h = (pixel_x as u64)
+            .wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            .wrapping_add((pixel_y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F))
+            .wrapping_add(sample as u64);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xFF51_AFD7_ED55_8CCD);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xC4CE_B9FE_1A85_EC53);
+        h ^= h >> 33;
+        let jx = (h & 0xFFFF_FFFF) as f64 / (1u64 << 32) as f64;
+        let jy = (h >> 32) as f64 / (1u64 << 32) as f64;
+        (jx, jy)
+    }
+
+    // Runs the escape time loop on points given in pixel coordinates,
+    // LANES points at a time
+    fn sample(&self, points: &[(f64, f64)]) -> Vec<(Pixel, bool)> {
         let Data {
-            x_ratio,
-            x_offset,
-            y_ratio,
-            y_offset,
             max_iter,
+            formula,
+            bailout,
             ..
         } = self.data;
-        // (((2 * x) / width) - 1) * (wind_wid / 2) =  (wind_wid / width) * x - wind_wid / 2 - x_off;
-        let x0 = x_ratio * pixel_x as f64 - x_offset;
-        let y0 = y_ratio * pixel_y as f64 - y_offset;
-        let mut x_coord = x0;
-        let mut y_coord = y0;
-        let mut iteration = 0;
-        let it_mod;
+        let precision = self.precision();
+        if precision != Precision::Double {
+            let offsets: Vec<_> = points
+                .iter()
+                .map(|&(pixel_x, pixel_y)| self.offset(pixel_x, pixel_y))
+                .collect();
+            let on_map: Vec<(f64, f64)> = offsets.iter().flatten().copied().collect();
+            let bailout = bailout * bailout;
+            let escaped = match precision {
+                Precision::DoubleDouble => kernel::escape_precise::<DoubleDouble>(
+                    &self.data.center,
+                    &on_map,
+                    formula,
+                    max_iter,
+                    bailout,
+                ),
+                Precision::Big(digits) => kernel::escape_precise::<Big>(
+                    &(
+                        self.data.center.0.clone().with_digits(digits),
+                        self.data.center.1.clone().with_digits(digits),
+                    ),
+                    &on_map,
+                    formula,
+                    max_iter,
+                    bailout,
+                ),
+                Precision::Double => unreachable!(),
+            };
+            let mut escaped = escaped.into_iter();
+            return offsets
+                .iter()
+                .map(|offset| match offset {
+                    Some(_) => {
+                        let (iteration, mag) = escaped.next().unwrap();
+                        self.color(iteration, mag)
+                    }
+                    None => OFF_MAP,
+                })
+                .collect();
+        }
+        let mut colors = Vec::with_capacity(points.len());
+        for chunk in points.chunks(LANES) {
+            let mut x0 = [0.; LANES];
+            let mut y0 = [0.; LANES];
+            let mut off_map = [false; LANES];
+            for (lane, &(pixel_x, pixel_y)) in chunk.iter().enumerate() {
+                match self.plane(pixel_x, pixel_y) {
+                    Some(point) => (x0[lane], y0[lane]) = point,
+                    None => off_map[lane] = true,
+                }
+            }
+            let (iterations, mags) = kernel::escape(&x0, &y0, formula, max_iter, bailout * bailout);
+            for lane in 0..chunk.len() {
+                colors.push(if off_map[lane] {
+                    OFF_MAP
+                } else {
+                    self.color(iterations[lane], mags[lane])
+                });
+            }
+        }
+        colors
+    }
 
-        while x_coord * x_coord + y_coord * y_coord <= (1 << 4) as f64 && iteration < max_iter {
-            // Compute New Point
-            let x_temp = x_coord * x_coord - y_coord * y_coord + x0;
-            y_coord = 2. * x_coord * y_coord + y0;
-            x_coord = x_temp;
-            iteration += 1;
+    fn precision(&self) -> Precision {
+        let d = &self.data;
+        // Formulas from the file only run on f64
+        if let Formula::Custom(_) = d.formula {
+            return Precision::Double;
         }
+        let pixel = self.relative_pixel();
+        if pixel > 1e-13 {
+            Precision::Double
+        } else if pixel > 1e-28 {
+            Precision::DoubleDouble
+        } else {
+            Precision::Big(Self::big_digits(pixel))
+        }
+    }
+
+    // How big a pixel is next to the numbers it's added to
+    fn relative_pixel(&self) -> f64 {
+        let d = &self.data;
+        let scale = d.window_x.abs().max(d.window_y.abs()).max(d.window_width);
+        let step = d.x_ratio.min(d.y_ratio);
+        d.mapping
+            .smallest_step(step, d.window_width, d.window_height)
+            / scale
+    }
+
+    // Digits a Big needs to tell pixels of this relative size apart, with
+    // SPARE_DIGITS more for the rounding errors piling up over the orbit
+    // and so the center can take the next zoom before it's rounded again
+    fn big_digits(pixel: f64) -> u64 {
+        (-pixel.log10()).ceil().max(0.) as u64 + SPARE_DIGITS
+    }
+
+    // Picks the color from the escape time and the final |z|^2
+    fn color(&self, mut iteration: usize, mag: f64) -> (Pixel, bool) {
+        let max_iter = self.data.max_iter;
+        let it_mod;
         // Used to avoid floating point issues with points inside the set.
         if iteration < max_iter {
             // sqrt of inner term removed using log simplification rules.
-            let log_zn = (x_coord * x_coord + y_coord * y_coord).ln() / 2.;
+            let log_zn = mag.ln() / 2.;
             let nu = (log_zn / std::f64::consts::LN_2).ln() / std::f64::consts::LN_2;
             // Rearranging the potential function.
             // Dividing log_zn by log(2) instead of log(N = 1<<8)
             // because we want the entire palette to range from the
             // center to radius 2, NOT our bailout radius.
-            let it = iteration as f64 + 1. - nu;
+            let it = match self.data.coloring {
+                Coloring::Smooth => iteration as f64 + 1. - nu,
+                Coloring::Banded => iteration as f64,
+            };
+            let palette_len = self.data.palette.colors().len() as f64;
+            let it = it * self.data.palette_scale + self.data.palette_offset * palette_len;
             iteration = it as usize;
             it_mod = it % 1.;
         } else {
@@ -141,136 +715,106 @@ impl Mandelbrot {
         }
         // Color choosing
         let (r, g, b) = {
-            static PALETTE: [(u8, u8, u8); 16] = [
-                (66, 30, 15),
-                (25, 7, 26),
-                (9, 1, 47),
-                (4, 4, 73),
-                (0, 7, 100),
-                (12, 44, 138),
-                (24, 82, 177),
-                (57, 125, 209),
-                (134, 181, 229),
-                (211, 236, 248),
-                (241, 233, 191),
-                (248, 201, 95),
-                (255, 170, 0),
-                (204, 128, 0),
-                (153, 87, 0),
-                (106, 52, 3),
-            ];
-            // static PALETTE: [(u8, u8, u8); 2] = [(255, 255, 255), (255, 255, 255)];
-            let color1 = PALETTE[iteration % PALETTE.len()];
-            let color2 = PALETTE[(iteration + 1) % PALETTE.len()];
+            let palette = self.data.palette.colors();
+            let color1 = palette[iteration % palette.len()];
+            let color2 = palette[(iteration + 1) % palette.len()];
             // let (dr, dg, db) = (0., 0., 0.);
             Self::lin_inter(color1, color2, it_mod as f32)
         };
         ((r, g, b, 255), false)
     }
 
+    // Calculates the border of the rect, and returns whether
+    // it had any inside points, and any outside points
     fn draw_rect(
         &self,
-        ind: usize,
-        i: usize,
-        pixels: &mut [Pixel],
+        tile: &mut Tile,
         (x_pos, y_pos, width, height): (usize, usize, usize, usize),
-        pitch: usize,
-    ) -> (usize, bool, bool) {
+    ) -> (bool, bool) {
+        let (tile_x, tile_y) = tile.position();
         let mut outside = false;
         let mut inside = false;
-        let mut i = i + y_pos * pitch + x_pos;
-        let mut y = ind / pitch + 2;
-        for x in 0..width {
-            let (color, black) = self.draw_iter(x + x_pos, y + y_pos);
-            inside |= black;
-            outside |= !black;
-            pixels[i + x] = color;
+        let mut border = Vec::with_capacity(2 * (width + height));
+        // Top and bottom lines
+        for x in x_pos..x_pos + width {
+            border.push((x, y_pos));
+            if height > 1 {
+                border.push((x, y_pos + height - 1));
+            }
         }
-        i += pitch;
-        // Calc side lines
-        for _ in 1..height {
-            let (color, black) = self.draw_iter(x_pos, y + y_pos);
-            inside |= black;
-            outside |= !black;
-            pixels[i] = color;
-            let (color, black) = self.draw_iter(width - 1 + x_pos, y + y_pos);
-            inside |= black;
-            outside |= !black;
-            pixels[i + width - 1] = color;
-            i += pitch;
-            y += 1;
-        }
-        // Calc bottom line
-        i -= pitch - 1;
-        y -= 1;
-        for x in 1..width - 1 {
-            let (color, black) = self.draw_iter(x + x_pos, y + y_pos);
+        // Side lines
+        for y in y_pos + 1..y_pos + height - 1 {
+            border.push((x_pos, y));
+            if width > 1 {
+                border.push((x_pos + width - 1, y));
+            }
+        }
+        let pixels: Vec<_> = border
+            .iter()
+            .map(|&(x, y)| (tile_x + x, tile_y + y))
+            .collect();
+        for (&(x, y), (color, black)) in border.iter().zip(self.draw_iters(&pixels)) {
             inside |= black;
             outside |= !black;
-            pixels[i] = color;
-            i += 1;
+            tile[(x, y)] = color;
         }
-        (i, inside, outside)
+        (inside, outside)
     }
 
     fn fill_rect(
         &self,
-        ind: usize,
-        i: usize,
-        pixels: &mut [Pixel],
+        tile: &mut Tile,
         (x_pos, y_pos, width, height): (usize, usize, usize, usize),
-        pitch: usize,
         color: bool,
     ) {
         if color {
-            let mut i = i + y_pos * pitch + x_pos + pitch;
-            let mut y = ind / pitch + 2;
-            for _ in 1..height - 1 {
-                for x in 1..width - 1 {
-                    let left = pixels[i + x - 1];
-                    let top = pixels[i + x - pitch];
-                    let (r, g, b) = if (x + y) % 2 != 0 {
-                        Self::lin_inter((left.0, left.1, left.2), (top.0, top.1, top.2), 0.5)
-                    } else {
-                        let ((r, g, b, _), _) = self.draw_iter(x + x_pos, y + y_pos);
-                        (r, g, b)
-                    };
-                    pixels[i + x] = (r, g, b, 255);
+            let (tile_x, tile_y) = tile.position();
+            for y in y_pos + 1..y_pos + height - 1 {
+                let pixel_y = tile_y + y;
+                let even = |x: &usize| (tile_x + x + pixel_y) & 1 == 0;
+                // Every other pixel of the row is computed, all in one batch
+                let pixels: Vec<_> = (x_pos + 1..x_pos + width - 1)
+                    .filter(even)
+                    .map(|x| (tile_x + x, pixel_y))
+                    .collect();
+                for (&(pixel_x, _), ((r, g, b, _), _)) in
+                    pixels.iter().zip(self.draw_iters(&pixels))
+                {
+                    tile[(pixel_x - tile_x, y)] = (r, g, b, 255);
+                }
+                // and the ones in between are blended from the computed
+                // pixel to their left and the one above them
+                for x in (x_pos + 1..x_pos + width - 1).filter(|x| !even(x)) {
+                    let left = tile[(x - 1, y)];
+                    let top = tile[(x, y - 1)];
+                    let (r, g, b) =
+                        Self::lin_inter((left.0, left.1, left.2), (top.0, top.1, top.2), 0.5);
+                    tile[(x, y)] = (r, g, b, 255);
                 }
-                y += 1;
-                i += pitch;
             }
         } else {
-            let mut i = i + y_pos * pitch + x_pos + pitch;
-            for _ in 1..height - 1 {
-                for x in 1..width - 1 {
-                    pixels[i + x] = (0, 0, 0, 0);
+            for y in y_pos + 1..y_pos + height - 1 {
+                let row = tile.row_mut(y);
+                for pixel in &mut row[x_pos + 1..x_pos + width - 1] {
+                    *pixel = (0, 0, 0, 0);
                 }
-                i += pitch;
             }
         }
     }
 
-    fn rect_divide(
-        &self,
-        rects: &mut Vec<(usize, usize, usize, usize)>,
-        ind: usize,
-        i: usize,
-        pixels: &mut [Pixel],
-        pitch: usize,
-    ) -> usize {
+    fn rect_divide(&self, rects: &mut Vec<(usize, usize, usize, usize)>, tile: &mut Tile) {
         let rect = rects.pop().unwrap();
-        let (i_final, inside, outside) = self.draw_rect(ind, i, pixels, rect, pitch);
+        let (inside, outside) = self.draw_rect(tile, rect);
         // The border rendered had only inside points or outside points,
         // so fill the rect with either black or color
         if inside ^ outside {
-            self.fill_rect(ind, i, pixels, rect, pitch, outside);
+            self.fill_rect(tile, rect, outside);
         }
         // Else break up the rectangle until it's good
         else {
             if rect.2 < 6 || rect.3 < 6 {
-                self.fill_rect(ind, i, pixels, rect, pitch, outside);
-                return i_final;
+                self.fill_rect(tile, rect, outside);
+                return;
             }
 
             if rect.2 > rect.3 {
@@ -301,10 +845,65 @@ impl Mandelbrot {
                 rects.push(rect2);
             }
         }
-        i_final
+    }
+
+    // Supersamples the pixels on edges, including ones that only
+    // show up against the neighboring tiles
+    fn refine(
+        &self,
+        tile: &mut Tile,
+        covered: Option<(usize, usize, usize, usize)>,
+        size: usize,
+        threshold: u8,
+        token: &CancelToken,
+    ) {
+        // Copied pixels were already refined in the last frame
+        let copied = |x: usize, y: usize| match covered {
+            Some((cx, cy, cw, ch)) => x >= cx && x < cx + cw && y >= cy && y < cy + ch,
+            None => false,
+        };
+        let (tile_x, tile_y) = tile.position();
+        let (width, height) = tile.dimensions();
+        // Pixels past the edge of the tile aren't drawn yet, so they're
+        // compared with what the last pass had there instead
+        let color = |x: isize, y: isize| {
+            if x >= 0 && y >= 0 && x < width as isize && y < height as isize {
+                Some(tile[(x as usize, y as usize)])
+            } else {
+                self.last_pass(tile_x as isize + x, tile_y as isize + y)
+            }
+        };
+        let marked = Self::edges(width, height, threshold, color);
+        if token.is_cancelled() {
+            return;
+        }
+        let pixels: Vec<_> = marked
+            .iter()
+            .enumerate()
+            .filter(|(i, m)| **m && !copied(i % width, i / width))
+            .map(|(i, _)| (tile_x + i % width, tile_y + i / width))
+            .collect();
+        for (&(pixel_x, pixel_y), (color, _)) in pixels
+            .iter()
+            .zip(self.super_sample(&pixels, size, false, false))
+        {
+            tile[(pixel_x - tile_x, pixel_y - tile_y)] = color;
+        }
     }
 
     fn zoom(&mut self, factor: f64) {
+        let (width, height) = (self.data.width as f64, self.data.height as f64);
+        self.zoom_at(factor, width / 2., height / 2.);
+    }
+
+    // Zooms so the point under the pixel stays where it is on the screen
+    fn zoom_at(&mut self, factor: f64, pixel_x: f64, pixel_y: f64) {
+        let scale = 1. / factor;
+        // Every mapping grows along with the view, so moving the center
+        // that part of the way keeps the point under the pixel in place.
+        // Off the map there's no point, so that's a zoom at the center.
+        let (x, y) = self.offset(pixel_x, pixel_y).unwrap_or((0., 0.));
+        self.shift_center(x * (1. - scale), y * (1. - scale));
         let Data {
             ref mut x_ratio,
             ref mut x_offset,
@@ -335,9 +934,33 @@ impl Mandelbrot {
         *x_offset = xo;
         *y_ratio = yr;
         *y_offset = yo;
+        // Pixels keep their place relative to the fixed one
+        self.moved(scale, pixel_x * (1. - scale), pixel_y * (1. - scale));
+    }
+
+    // Moves the center by a step in the plane. The step is added to the
+    // wide center, so steps smaller than f64 can see still add up. The
+    // center keeps as many digits as the zoom needs, however deep it gets.
+    fn shift_center(&mut self, dx: f64, dy: f64) {
+        let digits = Self::big_digits(self.relative_pixel());
+        let d = &mut self.data;
+        d.center.0 = d.center.0.clone().with_digits(digits) + Big::from_f64(dx);
+        d.center.1 = d.center.1.clone().with_digits(digits) + Big::from_f64(dy);
+        d.window_x = d.center.0.to_f64();
+        d.window_y = d.center.1.to_f64();
     }
 
     fn translate(&mut self, x_percent: f64, y_percent: f64) {
+        // Only move by whole pixels, so that the old frame lines up with the new one.
+        let x_pixels = (self.data.width as f64 * x_percent).round();
+        let y_pixels = (self.data.height as f64 * y_percent).round();
+        self.translate_pixels(x_pixels, y_pixels);
+    }
+
+    fn translate_pixels(&mut self, x_pixels: f64, y_pixels: f64) {
+        // The screen's axes are turned along with the view
+        let (dx, dy) = self.turn(x_pixels * self.data.x_ratio, -y_pixels * self.data.y_ratio);
+        self.shift_center(dx, dy);
         let Data {
             ref mut x_ratio,
             ref mut x_offset,
@@ -352,11 +975,6 @@ impl Mandelbrot {
             ref mut height,
             ..
         } = &mut self.data;
-        // Calc new window_x and window_y
-        let x_dist = *window_width * x_percent;
-        *window_x += x_dist;
-        let y_dist = *window_height * y_percent;
-        *window_y -= y_dist;
         // Calculate useful numbers from those
         let (xr, xo, yr, yo) = Self::get_mandelbrot_vals(
             *window_width,
@@ -370,6 +988,553 @@ impl Mandelbrot {
         *x_offset = xo;
         *y_ratio = yr;
         *y_offset = yo;
+        self.moved(1., x_pixels, -y_pixels);
+    }
+
+    // Turns the view around its center. None of the old frame lines up
+    // with the new one, so it's drawn from scratch.
+    fn rotate(&mut self, degrees: f64) {
+        self.data.angle = (self.data.angle + degrees).rem_euclid(360.);
+        self.restart();
+    }
+
+    // Direction from the center of the window to a pixel, in degrees
+    fn direction(&self, x: i32, y: i32) -> Option<f64> {
+        let dx = x as f64 - self.data.width as f64 / 2.;
+        let dy = y as f64 - self.data.height as f64 / 2.;
+        // Too close to the center for the direction to mean much
+        if dx.abs() < MIN_BOX as f64 && dy.abs() < MIN_BOX as f64 {
+            return None;
+        }
+        Some(dy.atan2(dx).to_degrees())
+    }
+
+    // The view moved, so skip the preview passes if the present buffer
+    // holds a full frame, and copy what's still visible from it
+    fn moved(&mut self, scale: f64, offset_x: f64, offset_y: f64) {
+        let d = &mut self.data;
+        d.render_time = Duration::ZERO;
+        // Pixels of a zoomed frame only match with one sample per pixel
+        let single = matches!(d.sampling, Sampling::Single | Sampling::Adaptive { .. });
+        // and only the flat map moves along with the screen
+        if (scale != 1. && !single) || d.mapping != Mapping::Flat {
+            self.restart();
+            return;
+        }
+        let reuse = match (d.block, d.reuse) {
+            // Nothing was drawn since the last full frame
+            (0, _) => Reuse::Moved {
+                scale,
+                offset_x,
+                offset_y,
+            },
+            // Moved again before the new frame was done, so go
+            // straight from the newest view to the last full frame
+            (
+                1,
+                Reuse::Moved {
+                    scale: old_scale,
+                    offset_x: old_x,
+                    offset_y: old_y,
+                },
+            ) => Reuse::Moved {
+                scale: scale * old_scale,
+                offset_x: offset_x * old_scale + old_x,
+                offset_y: offset_y * old_scale + old_y,
+            },
+            _ => {
+                self.restart();
+                return;
+            }
+        };
+        // Other zoom factors leave too few pixels on the old grid
+        // for the reuse to be worth skipping the coarse passes
+        match reuse {
+            Reuse::Moved { scale, .. } if Self::whole_factor(scale) => {
+                d.block = 1;
+                d.reuse = reuse;
+            }
+            _ => self.restart(),
+        }
+    }
+
+    // Zooms by the part of the wheel ticks that's due by now. The rest
+    // shrinks exponentially, and is done all at once when it's tiny.
+    fn glide_wheel(&mut self) -> bool {
+        let d = &mut self.data;
+        if d.wheel == 0. {
+            return false;
+        }
+        let elapsed = d.wheel_at.elapsed().as_secs_f64() / WHEEL_GLIDE.as_secs_f64();
+        let mut ticks = d.wheel * (1. - (-elapsed).exp());
+        if (d.wheel - ticks).abs() < 0.02 {
+            ticks = d.wheel;
+        }
+        if ticks.abs() < 1e-3 {
+            return false;
+        }
+        d.wheel -= ticks;
+        d.wheel_at = Instant::now();
+        let (mouse_x, mouse_y) = d.mouse;
+        self.zoom_at(SCALE_WHEEL.powf(ticks), mouse_x as f64, mouse_y as f64);
+        true
+    }
+
+    // Moves the view to where the mouse dragged it, and renders it
+    fn commit_drag(&mut self) -> bool {
+        let (dx, dy) = match &mut self.data.drag {
+            Some(drag) if drag.pending != (0, 0) => std::mem::take(&mut drag.pending),
+            _ => return false,
+        };
+        self.data.unpresented.0 += dx;
+        self.data.unpresented.1 += dy;
+        // The picture follows the mouse, so the view moves the other way
+        self.translate_pixels(-dx as f64, dy as f64);
+        true
+    }
+
+    // Shifts the presented frame by however far the view was dragged
+    // since it was drawn, so dragging feels instant
+    fn show_drag(&self) {
+        let (mut x, mut y) = self.data.unpresented;
+        if let Some(drag) = &self.data.drag {
+            x += drag.pending.0;
+            y += drag.pending.1;
+        }
+        if let Some(window) = self.get_window() {
+            window.lock().unwrap().set_offset(x, y);
+        }
+    }
+
+    // How many times the window is as big as the box from start to end,
+    // fitting it both ways, and where the center of the box is.
+    // Boxes thinner than MIN_BOX either way don't count.
+    fn box_shape(&self, start: (i32, i32), end: (i32, i32)) -> Option<(f64, f64, f64)> {
+        let (box_width, box_height) = ((end.0 - start.0).abs(), (end.1 - start.1).abs());
+        if box_width < MIN_BOX || box_height < MIN_BOX {
+            return None;
+        }
+        let (width, height) = (self.data.width as f64, self.data.height as f64);
+        let ratio = f64::min(width / box_width as f64, height / box_height as f64);
+        let center_x = (start.0 + end.0) as f64 / 2.;
+        let center_y = (start.1 + end.1) as f64 / 2.;
+        Some((ratio, center_x, center_y))
+    }
+
+    // The part of the view a box from start to end zooms to. It's grown
+    // to the shape of the window around its center, so nothing in it is cut off.
+    fn selection(&self, start: (i32, i32), end: (i32, i32)) -> Option<Rect> {
+        let (ratio, center_x, center_y) = self.box_shape(start, end)?;
+        let (sel_width, sel_height) = (
+            self.data.width as f64 / ratio,
+            self.data.height as f64 / ratio,
+        );
+        Some(Rect::new(
+            (center_x - sel_width / 2.).round() as i32,
+            (center_y - sel_height / 2.).round() as i32,
+            sel_width.round() as u32,
+            sel_height.round() as u32,
+        ))
+    }
+
+    // Zooms in so the box from start to end fills the window. A box
+    // dragged right to left zooms out instead, so the window fits in the
+    // box. Only the horizontal direction decides, up or down doesn't matter.
+    fn zoom_box(&mut self, start: (i32, i32), end: (i32, i32)) -> bool {
+        let (ratio, center_x, center_y) = match self.box_shape(start, end) {
+            Some(shape) => shape,
+            None => return false,
+        };
+        let (width, height) = (self.data.width as f64, self.data.height as f64);
+        if self.data.mapping != Mapping::Flat {
+            // Pixels don't stay in line under the other mappings, so the
+            // point in the center of the box is found on the map, and moved
+            // to the middle of the window when zooming in. Zooming out puts
+            // what was in the middle into the box instead.
+            let (x, y) = match self.offset(center_x, center_y) {
+                Some(point) => point,
+                None => return false,
+            };
+            // Not 0 for the exponential mapping
+            let (mid_x, mid_y) = self.offset(width / 2., height / 2.).unwrap_or((0., 0.));
+            if end.0 >= start.0 {
+                self.shift_center(x - mid_x, y - mid_y);
+                self.zoom_at(ratio, width / 2., height / 2.);
+            } else {
+                self.shift_center(ratio * (mid_x - x), ratio * (mid_y - y));
+                self.zoom_at(1. / ratio, width / 2., height / 2.);
+            }
+            return true;
+        }
+        // New pixels sit at old pixel * scale + offset, pick those so the
+        // center of the box and the center of the window swap places
+        let (scale, offset_x, offset_y) = if end.0 >= start.0 {
+            let scale = 1. / ratio;
+            (
+                scale,
+                center_x - scale * width / 2.,
+                center_y - scale * height / 2.,
+            )
+        } else {
+            (
+                ratio,
+                width / 2. - ratio * center_x,
+                height / 2. - ratio * center_y,
+            )
+        };
+        if (1. - scale).abs() < 1e-6 {
+            // Same size as the window, so it's only a move
+            self.translate_pixels(offset_x.round(), -offset_y.round());
+        } else {
+            // Any other one of those is a zoom around the point that stays put
+            let fixed_x = offset_x / (1. - scale);
+            let fixed_y = offset_y / (1. - scale);
+            self.zoom_at(1. / scale, fixed_x, fixed_y);
+        }
+        true
+    }
+
+    fn view(&self) -> View {
+        let d = &self.data;
+        View {
+            x: d.center.0.clone(),
+            y: d.center.1.clone(),
+            width: d.window_width,
+            max_iter: d.max_iter,
+            formula: d.formula,
+            palette: d.palette,
+            angle: d.angle,
+        }
+    }
+
+    fn set_view(&mut self, view: View) {
+        let d = &mut self.data;
+        d.window_x = view.x.to_f64();
+        d.window_y = view.y.to_f64();
+        d.center = (view.x, view.y);
+        d.window_width = view.width;
+        d.window_height = view.width * (d.height as f64 / d.width as f64);
+        d.max_iter = view.max_iter;
+        d.formula = view.formula;
+        d.palette = view.palette;
+        d.angle = view.angle;
+        let (xr, xo, yr, yo) = Self::get_mandelbrot_vals(
+            d.window_width,
+            d.window_x,
+            d.width,
+            d.window_height,
+            d.window_y,
+            d.height,
+        );
+        d.x_ratio = xr;
+        d.x_offset = xo;
+        d.y_ratio = yr;
+        d.y_offset = yo;
+        self.restart();
+    }
+
+    // Adds the current view to the history if it's new. Anything that
+    // was gone back over is dropped, like undo in an editor.
+    fn record(&mut self) {
+        let view = self.view();
+        let d = &mut self.data;
+        if d.history[d.history_pos] == view {
+            return;
+        }
+        d.history.truncate(d.history_pos + 1);
+        d.history.push(view);
+        if d.history.len() > HISTORY_LIMIT {
+            d.history.remove(0);
+        }
+        d.history_pos = d.history.len() - 1;
+    }
+
+    // Moves through the history by steps, returns whether the view changed
+    fn go_back(&mut self, steps: isize) -> bool {
+        let pos = self.data.history_pos as isize - steps;
+        if pos < 0 || pos >= self.data.history.len() as isize {
+            return false;
+        }
+        self.data.history_pos = pos as usize;
+        self.set_view(self.data.history[pos as usize].clone());
+        true
+    }
+
+    fn save_bookmark(&mut self, slot: usize) {
+        self.data.bookmarks[slot] = Some(self.view());
+        match view::save_bookmarks(&self.data.bookmarks) {
+            Ok(()) => println!("Saved bookmark {}", slot + 1),
+            Err(e) => println!("Couldn't save bookmarks: {}", e),
+        }
+    }
+
+    fn recall_bookmark(&mut self, slot: usize) -> bool {
+        match self.data.bookmarks[slot].clone() {
+            Some(view) => {
+                self.set_view(view);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Makes new buffers for the new window size, keeping the scale
+    fn resize(&mut self, wid: u32, hei: u32) {
+        let s = self.get_op_mut();
+        s.rect = Rect::new(0, 0, wid, hei);
+        let buffer1 = Pixels::new(wid as usize, hei as usize).unwrap();
+        let buffer2 = Pixels::new(wid as usize, hei as usize).unwrap();
+        s.buffers = [buffer1, buffer2];
+        s.buffer_ind = 0;
+
+        let d = &mut self.data;
+        d.window_width *= wid as f64 / d.width as f64;
+        d.window_height *= hei as f64 / d.height as f64;
+        d.width = wid;
+        d.height = hei;
+
+        let (xr, xo, yr, yo) = Self::get_mandelbrot_vals(
+            d.window_width,
+            d.window_x,
+            d.width,
+            d.window_height,
+            d.window_y,
+            d.height,
+        );
+        d.x_ratio = xr;
+        d.x_offset = xo;
+        d.y_ratio = yr;
+        d.y_offset = yo;
+        // Old passes don't fit anymore, so start over
+        self.restart();
+    }
+
+    fn save_session(&self) {
+        let d = &self.data;
+        let position = self
+            .get_window()
+            .map(|window| window.lock().unwrap().canvas().window().position());
+        let session = Session {
+            view: self.view(),
+            coloring: d.coloring,
+            mapping: d.mapping,
+            bailout: d.bailout,
+            palette_offset: d.palette_offset,
+            palette_scale: d.palette_scale,
+            width: d.width,
+            height: d.height,
+            position,
+        };
+        match session.save(&d.session_file) {
+            Ok(()) => println!("Saved session to {}", d.session_file),
+            Err(e) => println!("Couldn't save session: {}", e),
+        }
+    }
+
+    // Puts the window and view back how they were saved.
+    // Returns whether there was a session to load.
+    fn load_session(&mut self) -> bool {
+        let session = match Session::load(&self.data.session_file) {
+            Ok(session) => session,
+            Err(e) => {
+                println!("Couldn't load session: {}", e);
+                return false;
+            }
+        };
+        let window = match self.get_window() {
+            Some(window) => window,
+            None => return false,
+        };
+        let mut window = window.lock().unwrap();
+        if let Some((x, y)) = session.position {
+            window
+                .canvas_mut()
+                .window_mut()
+                .set_position(WindowPos::Positioned(x), WindowPos::Positioned(y));
+        }
+        // The resize event for this may come late or not at all,
+        // so the new size is taken on right away
+        if (session.width, session.height) != (self.data.width, self.data.height) {
+            let resized = window
+                .canvas_mut()
+                .window_mut()
+                .set_size(session.width, session.height)
+                .map_err(|e| e.to_string())
+                .and_then(|()| window.resized(session.width as usize, session.height as usize));
+            let (width, height) = window.size();
+            match resized {
+                Ok(()) => self.resize(width as u32, height as u32),
+                Err(e) => println!("Couldn't resize the window: {}", e),
+            }
+        }
+        drop(window);
+        self.set_view(session.view);
+        let d = &mut self.data;
+        d.coloring = session.coloring;
+        d.mapping = session.mapping;
+        d.bailout = session.bailout;
+        d.palette_offset = session.palette_offset;
+        d.palette_scale = session.palette_scale;
+        println!("Loaded session from {}", d.session_file);
+        true
+    }
+
+    fn copy_location(&self) {
+        let text = location::encode(&self.view());
+        let window = match self.get_window() {
+            Some(window) => window,
+            None => return,
+        };
+        let window = window.lock().unwrap();
+        match window
+            .canvas()
+            .window()
+            .subsystem()
+            .clipboard()
+            .set_clipboard_text(&text)
+        {
+            Ok(()) => println!("Copied {}", text),
+            Err(e) => println!("Couldn't copy the location: {}", e),
+        }
+    }
+
+    // Takes any location from the clipboard, returns whether it was one
+    fn paste_location(&mut self) -> bool {
+        let window = match self.get_window() {
+            Some(window) => window,
+            None => return false,
+        };
+        let text = window
+            .lock()
+            .unwrap()
+            .canvas()
+            .window()
+            .subsystem()
+            .clipboard()
+            .clipboard_text();
+        match text.and_then(|text| location::import(&text, self.view())) {
+            Ok(view) => {
+                self.set_view(view);
+                true
+            }
+            Err(e) => {
+                println!("Couldn't paste a location: {}", e);
+                false
+            }
+        }
+    }
+
+    fn export_location(&self) {
+        const NAME: &str = "location";
+        let view = self.view();
+        let mut files = vec![(format!("{}.par", NAME), location::to_par(&view, NAME))];
+        if location::needs_frm(&view) {
+            files.push((location::FRM_FILE.to_string(), location::FRM.to_string()));
+        }
+        match location::to_kfr(&view) {
+            Ok(kfr) => files.push((format!("{}.kfr", NAME), kfr)),
+            Err(e) => println!("Skipped the .kfr: {}", e),
+        }
+        for (path, text) in files {
+            match std::fs::write(&path, text) {
+                Ok(()) => println!("Wrote {}", path),
+                Err(e) => println!("Couldn't write {}: {}", path, e),
+            }
+        }
+    }
+
+    // Everything computed so far is useless, so start over with a preview
+    fn restart(&mut self) {
+        self.data.block = COARSE_BLOCK;
+        self.data.reuse = Reuse::Nothing;
+        self.data.render_time = Duration::ZERO;
+    }
+
+    // Where in the fractal a pixel is, if the mapping puts anything there
+    fn plane(&self, pixel_x: f64, pixel_y: f64) -> Option<(f64, f64)> {
+        let d = &self.data;
+        if d.angle != 0. || d.mapping != Mapping::Flat {
+            let (x, y) = self.offset(pixel_x, pixel_y)?;
+            return Some((d.window_x + x, d.window_y + y));
+        }
+        // (((2 * x) / width) - 1) * (wind_wid / 2) =  (wind_wid / width) * x - wind_wid / 2 - x_off;
+        Some((
+            d.x_ratio * pixel_x - d.x_offset,
+            d.y_ratio * pixel_y - d.y_offset,
+        ))
+    }
+
+    // How far a pixel is from the center of the view, in the fractal's units
+    fn offset(&self, pixel_x: f64, pixel_y: f64) -> Option<(f64, f64)> {
+        let d = &self.data;
+        let (x, y) = d.mapping.map(
+            (pixel_x - d.width as f64 / 2.) * d.x_ratio,
+            (pixel_y - d.height as f64 / 2.) * d.y_ratio,
+            d.window_width,
+            d.window_height,
+        )?;
+        Some(self.turn(x, y))
+    }
+
+    // Turns a distance along the screen into one along the fractal's axes
+    fn turn(&self, x: f64, y: f64) -> (f64, f64) {
+        if self.data.angle == 0. {
+            return (x, y);
+        }
+        let (sin, cos) = self.data.angle.to_radians().sin_cos();
+        (x * cos - y * sin, x * sin + y * cos)
+    }
+
+    fn hud_lines(&self) -> Vec<String> {
+        let view = self.view();
+        let zoom = View::default().width / view.width;
+        let cursor = match self.plane(self.data.mouse.0 as f64, self.data.mouse.1 as f64) {
+            Some((x, y)) => format!("Cursor: {} {:+}i", x, y),
+            None => "Cursor: off the map".to_string(),
+        };
+        let mut lines = vec![
+            format!("Center: {} {:+}i", view.x, view.y),
+            format!("Zoom: {:.3e}x", zoom),
+            format!("Angle: {:.2}", view.angle),
+            format!("Max iter: {}", view.max_iter),
+            format!("Precision: {}", self.precision().name()),
+            format!("Render: {} ms", self.data.render_time.as_millis()),
+            format!("Formula: {}", view.formula),
+            format!("Mapping: {}", self.data.mapping),
+            cursor,
+        ];
+        if self.data.stats {
+            lines.push(self.get_stats().to_string());
+        }
+        lines
+    }
+
+    // What the settings window shows, a slot for every kind of setting.
+    // Julia c only has one while the formula is a julia set.
+    fn settings(&self) -> [Option<Setting>; 5] {
+        let d = &self.data;
+        let julia = match d.formula {
+            Formula::Julia(x, y) => Some(Setting::JuliaC(x, y)),
+            _ => None,
+        };
+        [
+            Some(Setting::MaxIter(d.max_iter)),
+            Some(Setting::Bailout(d.bailout)),
+            Some(Setting::PaletteOffset(d.palette_offset)),
+            Some(Setting::PaletteScale(d.palette_scale)),
+            julia,
+        ]
+    }
+
+    fn show_hud(&self) {
+        let lines = if self.data.hud {
+            self.hud_lines()
+        } else {
+            vec![]
+        };
+        if let Some(window) = self.get_window() {
+            window.lock().unwrap().set_hud(lines);
+        }
     }
 }
 
@@ -380,52 +1545,130 @@ impl BasicOpImp for Mandelbrot {
     fn get_op_mut(&mut self) -> &mut BasicOp {
         &mut self.op
     }
-    fn draw(&self, pixels: &mut [Pixel], ind: usize, pitch: usize) {
-        // Calc all top pixels
-        let mut i = 0;
-        while (i + ind) % pitch != 0 {
-            let total_ind = i + ind;
-            let (pixel_x, pixel_y) = Self::ind_to_xy(total_ind, pitch);
-            let (color, _) = self.draw_iter(pixel_x, pixel_y);
-            pixels[i] = color;
-            i += 1;
-        }
-        // Calc middle rect
-        let height = (pixels.len() - i) / pitch;
-        let mut rects = vec![(0, 0, pitch, height)];
-        // Start rect logic
-        let i_final = self.rect_divide(&mut rects, ind, i, pixels, pitch);
+    fn bindings(&self) -> Option<&Bindings> {
+        Some(&self.data.bindings)
+    }
+    fn pending_offset(&self) -> (i32, i32) {
+        match &self.data.drag {
+            Some(drag) => drag.pending,
+            None => (0, 0),
+        }
+    }
+    // A paused drag and a gliding wheel zoom both go on without events
+    fn wants_idle(&self) -> bool {
+        let dragging = matches!(&self.data.drag, Some(drag) if drag.pending != (0, 0));
+        dragging || self.data.wheel != 0.
+    }
+    fn draw(&self, tile: &mut Tile, token: &CancelToken) {
+        // Preview passes don't need any of the rect logic
+        if self.data.block > 1 {
+            self.draw_blocks(tile, self.data.block, token);
+            return;
+        }
+        // Copy whatever is still visible from the last frame, and
+        // start rect logic on the strips around it
+        let (width, height) = tile.dimensions();
+        let covered = self.covered(tile);
+        let mut rects = match covered {
+            Some(rect) => {
+                self.copy_covered(tile, rect);
+                let (x, y, w, h) = rect;
+                vec![
+                    (0, 0, width, y),
+                    (0, y + h, width, height - y - h),
+                    (0, y, x, h),
+                    (x + w, y, width - x - w, h),
+                ]
+                .into_iter()
+                .filter(|r| r.2 > 0 && r.3 > 0)
+                .collect()
+            }
+            None => vec![(0, 0, width, height)],
+        };
         while !rects.is_empty() {
-            self.rect_divide(&mut rects, ind, i, pixels, pitch);
+            if token.is_cancelled() {
+                return;
+            }
+            self.rect_divide(&mut rects, tile);
         }
-        // Calc bottom pixels
-        for (j, pixel) in pixels.iter_mut().enumerate().skip(i_final) {
-            let total_ind = j + ind;
-            let (pixel_x, pixel_y) = Self::ind_to_xy(total_ind, pitch);
-            let (color, _) = self.draw_iter(pixel_x, pixel_y);
-            *pixel = color;
+        // Supersample only where the image isn't flat
+        if let Sampling::Adaptive { size, threshold } = self.data.sampling {
+            self.refine(tile, covered, size, threshold, token);
         }
     }
-    fn modify_data(&mut self) {}
+    fn modify_data(&mut self) {
+        // Move on to the next, finer pass
+        self.data.block /= 2;
+        self.data.reuse = if self.data.block == 1 {
+            Reuse::Preview
+        } else {
+            Reuse::Nothing
+        };
+    }
+    fn is_complete(&self) -> bool {
+        self.data.block == 0
+    }
     fn handle_events(&mut self) -> bool {
         use sdl2::event::{Event, WindowEvent};
+        use sdl2::keyboard::Mod;
+        use sdl2::mouse::{MouseButton, MouseWheelDirection};
 
         let s = self.get_op_mut();
         let list = s.event_list.get_mut().unwrap().split_off(0);
         let mut ret = false;
+        // Whether something shown on the hud changed without a new view
+        let mut hud_changed = false;
+        // What the settings window knows about
+        let mut shown = self.settings();
 
         for event in list {
             // println!("{:?}", event);
             match event {
-                // We finished rendering, and we want to be constantly rendering,
-                // so send true to start again.
-                SdlEvent::User(MainEvent::RenderOpFinish(op)) => {
-                    // If we can't read the op, then it must be this
-                    // so we finished, and there's no reason to not
-                    // restart, so send true back
-                    if op.try_read().is_err() {
+                // We finished rendering a pass, so start the next one
+                // unless the full resolution pass was the one that finished.
+                // A cancelled pass didn't move block on, so it's just redone.
+                // If we can't read the op, then it must be this
+                // so we finished, and only need to restart
+                // if there are passes left
+                SdlEvent::User(MainEvent::RenderOpFinish(op, status)) if op.try_read().is_err() => {
+                    if self.data.block > 0 {
                         ret = true;
                     }
+                    // The new frame already has the dragged view,
+                    // only the part that wasn't rendered yet is left
+                    if status == FrameStatus::Finished {
+                        self.data.render_time += self.get_op().stats.frame_time;
+                        hud_changed = true;
+                        self.data.unpresented = (0, 0);
+                        if self.data.drag.is_some() {
+                            self.show_drag();
+                        }
+                    }
+                }
+                // A slider in the settings window moved
+                SdlEvent::User(MainEvent::Setting(setting)) => {
+                    let before = self.settings();
+                    match setting {
+                        Setting::MaxIter(max_iter) => self.data.max_iter = max_iter,
+                        Setting::Bailout(bailout) => self.data.bailout = bailout,
+                        Setting::PaletteOffset(offset) => self.data.palette_offset = offset,
+                        Setting::PaletteScale(scale) => self.data.palette_scale = scale,
+                        Setting::JuliaC(x, y) => self.data.formula = Formula::Julia(x, y),
+                        // Threads are main's business
+                        Setting::Threads(_) => continue,
+                    }
+                    // Ours coming back, or nothing new
+                    let after = self.settings();
+                    if after == before {
+                        continue;
+                    }
+                    for (slot, (old, new)) in shown.iter_mut().zip(before.iter().zip(after)) {
+                        if *old != new {
+                            *slot = new;
+                        }
+                    }
+                    self.restart();
+                    ret = true;
                 }
                 // Window was resized, so do a ton
                 SdlEvent::Event(Event::Window {
@@ -436,71 +1679,418 @@ impl BasicOpImp for Mandelbrot {
                     if win_id != self.get_op().id() {
                         continue;
                     }
-
-                    let s = self.get_op_mut();
-                    s.rect = Rect::new(0, 0, wid as u32, hei as u32);
-                    let buffer1 = Pixels::new(wid as usize, hei as usize).unwrap();
-                    let buffer2 = Pixels::new(wid as usize, hei as usize).unwrap();
-                    s.buffers = [buffer1, buffer2];
-                    s.buffer_ind = 0;
-
-                    let d = &mut self.data;
-                    d.window_width *= wid as f64 / d.width as f64;
-                    d.window_height *= hei as f64 / d.height as f64;
-                    d.width = wid as u32;
-                    d.height = hei as u32;
-
-                    let (xr, xo, yr, yo) = Self::get_mandelbrot_vals(
-                        d.window_width,
-                        d.window_x,
-                        d.width,
-                        d.window_height,
-                        d.window_y,
-                        d.height,
-                    );
-                    d.x_ratio = xr;
-                    d.x_offset = xo;
-                    d.y_ratio = yr;
-                    d.y_offset = yo;
+                    self.resize(wid as u32, hei as u32);
+                    ret = true;
                 }
                 // User did some keyboard input
                 SdlEvent::Event(Event::KeyDown {
                     window_id,
                     scancode,
+                    keymod,
                     ..
                 }) => {
                     if window_id != self.get_op().id() {
                         continue;
                     }
 
-                    use sdl2::keyboard::Scancode;
-                    const MOVE_AMOUNT: f64 = 0.1;
-                    const SCALE_COARSE: f64 = 1.6;
-                    match scancode.unwrap() {
-                        Scancode::W => self.translate(0., MOVE_AMOUNT),
-                        Scancode::A => self.translate(-MOVE_AMOUNT, 0.),
-                        Scancode::S => self.translate(0., -MOVE_AMOUNT),
-                        Scancode::D => self.translate(MOVE_AMOUNT, 0.),
-                        Scancode::Q => self.zoom(SCALE_COARSE),
-                        Scancode::E => self.zoom(1. / SCALE_COARSE),
-                        Scancode::Up => {
+                    const SCALE_DOUBLE: f64 = 2.;
+                    let bindings = &self.data.bindings;
+                    let action = match scancode.and_then(|code| bindings.action(code, keymod)) {
+                        Some(action) => action,
+                        None => continue,
+                    };
+                    let (move_amount, scale_coarse, rotate_amount) = (
+                        bindings.move_amount,
+                        bindings.scale_coarse,
+                        bindings.rotate_amount,
+                    );
+                    match action {
+                        Action::MoveUp => self.translate(0., move_amount),
+                        Action::MoveLeft => self.translate(-move_amount, 0.),
+                        Action::MoveDown => self.translate(0., -move_amount),
+                        Action::MoveRight => self.translate(move_amount, 0.),
+                        Action::ZoomIn => self.zoom(scale_coarse),
+                        Action::ZoomOut => self.zoom(1. / scale_coarse),
+                        // Zooming by whole numbers lets more of the old frame be reused
+                        Action::ZoomInDouble => self.zoom(SCALE_DOUBLE),
+                        Action::ZoomOutDouble => self.zoom(1. / SCALE_DOUBLE),
+                        Action::RotateLeft => self.rotate(rotate_amount),
+                        Action::RotateRight => self.rotate(-rotate_amount),
+                        Action::ResetRotation => {
+                            if self.data.angle == 0. {
+                                continue;
+                            }
+                            self.rotate(-self.data.angle);
+                        }
+                        Action::MoreIterations => {
                             self.data.max_iter <<= 1;
-                            println!("Scale: {}", (self.data.max_iter as f64).log(2.))
+                            println!("Scale: {}", (self.data.max_iter as f64).log(2.));
+                            self.restart();
                         }
-                        Scancode::Down => {
+                        Action::FewerIterations => {
                             self.data.max_iter >>= 1;
                             if self.data.max_iter == 0 {
                                 self.data.max_iter = 1;
                             }
+                            self.restart();
+                        }
+                        Action::NextSampling => {
+                            self.data.sampling = self.data.sampling.next();
+                            println!("Sampling: {:?}", self.data.sampling);
+                            self.restart();
+                        }
+                        Action::NextFormula => {
+                            self.data.formula = self.data.formula.next();
+                            println!("Formula: {}", self.data.formula);
+                            if let Formula::Custom(script) = self.data.formula {
+                                if let Some(derivative) = script.derivative() {
+                                    println!("f'(z) = {}", derivative);
+                                }
+                            }
+                            self.restart();
                         }
-                        Scancode::Left => {}
-                        Scancode::Right => {}
-                        _ => {}
+                        Action::NextPalette => {
+                            self.data.palette = self.data.palette.next();
+                            println!("Palette: {}", self.data.palette);
+                            self.restart();
+                        }
+                        Action::NextColoring => {
+                            self.data.coloring = self.data.coloring.next();
+                            println!("Coloring: {}", self.data.coloring);
+                            self.restart();
+                        }
+                        Action::NextMapping => {
+                            self.data.mapping = self.data.mapping.next();
+                            println!("Mapping: {}", self.data.mapping);
+                            self.restart();
+                        }
+                        Action::Back => {
+                            if !self.go_back(1) {
+                                continue;
+                            }
+                        }
+                        Action::Forward => {
+                            if !self.go_back(-1) {
+                                continue;
+                            }
+                        }
+                        Action::RecallBookmark(slot) => {
+                            if !self.recall_bookmark(slot) {
+                                continue;
+                            }
+                        }
+                        Action::SaveBookmark(slot) => {
+                            self.save_bookmark(slot);
+                            continue;
+                        }
+                        Action::SaveSession => {
+                            self.save_session();
+                            continue;
+                        }
+                        Action::LoadSession => {
+                            if !self.load_session() {
+                                continue;
+                            }
+                        }
+                        Action::CopyLocation => {
+                            self.copy_location();
+                            continue;
+                        }
+                        Action::PasteLocation => {
+                            if !self.paste_location() {
+                                continue;
+                            }
+                        }
+                        Action::ExportLocation => {
+                            self.export_location();
+                            continue;
+                        }
+                        Action::ToggleHud => {
+                            self.data.hud = !self.data.hud;
+                            hud_changed = true;
+                            continue;
+                        }
+                        Action::ToggleStats => {
+                            self.data.stats = !self.data.stats;
+                            hud_changed = true;
+                            continue;
+                        }
+                        Action::ToggleHelp => {
+                            self.data.help = !self.data.help;
+                            let lines = if self.data.help {
+                                self.data.bindings.help_lines()
+                            } else {
+                                vec![]
+                            };
+                            if let Some(window) = self.get_window() {
+                                window.lock().unwrap().set_help(lines);
+                            }
+                            continue;
+                        }
+                        Action::ShowSettings => {
+                            self.get_op().send(MainEvent::ShowWindows);
+                            continue;
+                        }
+                    }
+                    // A new view was requested, so the passes left
+                    // for the old one were dropped
+                    ret = true;
+                }
+                SdlEvent::Event(Event::MouseMotion {
+                    window_id,
+                    x,
+                    y,
+                    xrel,
+                    yrel,
+                    ..
+                }) => {
+                    if window_id != self.get_op().id() {
+                        continue;
+                    }
+                    self.data.mouse = (x, y);
+                    hud_changed = true;
+                    if let Some(start) = self.data.select {
+                        let selection = self.selection(start, (x, y));
+                        if let Some(window) = self.get_window() {
+                            window.lock().unwrap().set_selection(selection);
+                        }
+                    }
+                    // The picture turns with the mouse, so the view turns the other way
+                    if let (Some(last), Some(now)) = (self.data.twist, self.direction(x, y)) {
+                        self.data.twist = Some(now);
+                        self.rotate(last - now);
+                        ret = true;
+                    }
+                    if let Some(drag) = &mut self.data.drag {
+                        drag.pending.0 += xrel;
+                        drag.pending.1 += yrel;
+                        drag.moved_at = Instant::now();
+                        self.show_drag();
+                    }
+                }
+                // Dragging with the left button pans the view,
+                // and with ctrl held it drags out a box to zoom to
+                SdlEvent::Event(Event::MouseButtonDown {
+                    window_id,
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                }) => {
+                    if window_id != self.get_op().id() {
+                        continue;
+                    }
+                    let ctrl = self.get_window().is_some_and(|window| {
+                        let window = window.lock().unwrap();
+                        let keyboard = window.canvas().window().subsystem().sdl().keyboard();
+                        keyboard
+                            .mod_state()
+                            .intersects(Mod::LCTRLMOD | Mod::RCTRLMOD)
+                    });
+                    if ctrl {
+                        self.data.select = Some((x, y));
+                    } else {
+                        self.data.drag = Some(Drag {
+                            pending: (0, 0),
+                            moved_at: Instant::now(),
+                        });
+                    }
+                }
+                SdlEvent::Event(Event::MouseButtonUp {
+                    window_id,
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                }) => {
+                    if window_id != self.get_op().id() {
+                        continue;
+                    }
+                    let moved = match self.data.select.take() {
+                        Some(start) => {
+                            if let Some(window) = self.get_window() {
+                                window.lock().unwrap().set_selection(None);
+                            }
+                            self.zoom_box(start, (x, y))
+                        }
+                        None => self.commit_drag(),
+                    };
+                    // Motion during the drag may have cancelled a frame
+                    // without moving the view, so that needs redoing too
+                    ret |= moved || self.data.block > 0;
+                    self.data.drag = None;
+                }
+                // Dragging with the right button turns the view around its center
+                SdlEvent::Event(Event::MouseButtonDown {
+                    window_id,
+                    mouse_btn: MouseButton::Right,
+                    x,
+                    y,
+                    ..
+                }) => {
+                    if window_id != self.get_op().id() {
+                        continue;
                     }
+                    self.data.twist = self.direction(x, y);
                 }
+                SdlEvent::Event(Event::MouseButtonUp {
+                    window_id,
+                    mouse_btn: MouseButton::Right,
+                    ..
+                }) => {
+                    if window_id != self.get_op().id() {
+                        continue;
+                    }
+                    self.data.twist = None;
+                }
+                // Zoom in or out around whatever is under the cursor
+                SdlEvent::Event(Event::MouseWheel {
+                    window_id,
+                    y,
+                    direction,
+                    ..
+                }) => {
+                    if window_id != self.get_op().id() || y == 0 {
+                        continue;
+                    }
+                    let steps = match direction {
+                        MouseWheelDirection::Flipped => -y,
+                        _ => y,
+                    };
+                    // The zoom glides there over the next few events
+                    if self.data.wheel == 0. {
+                        self.data.wheel_at = Instant::now();
+                    }
+                    self.data.wheel += steps as f64;
+                }
+                _ => (),
+            }
+        }
+        // The mouse stopped part way through a drag, so render what's there
+        let paused = match &self.data.drag {
+            Some(drag) => drag.moved_at.elapsed() >= DRAG_PAUSE,
+            None => false,
+        };
+        if paused {
+            ret |= self.commit_drag();
+        }
+        ret |= self.glide_wheel();
+        if ret {
+            self.record();
+        }
+        if hud_changed || (ret && self.data.hud) {
+            self.show_hud();
+        }
+        // Keys and loaded views change things the sliders show too
+        for (old, new) in shown.iter().zip(self.settings()) {
+            match new {
+                Some(setting) if *old != new => self.get_op().send(MainEvent::Setting(setting)),
                 _ => (),
             }
         }
         ret
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_sample_is_the_pixel_corner() {
+        let mut points = vec![];
+        Mandelbrot::sample_points(3, 7, 1, false, &mut points);
+        assert_eq!(points, vec![(3., 7.)]);
+    }
+
+    #[test]
+    fn grid_is_centered_on_the_single_sample() {
+        let mut points = vec![];
+        Mandelbrot::sample_points(10, 20, 2, false, &mut points);
+        assert_eq!(
+            points,
+            vec![(9.75, 19.75), (10.25, 19.75), (9.75, 20.25), (10.25, 20.25)]
+        );
+        points.clear();
+        Mandelbrot::sample_points(0, 0, 4, false, &mut points);
+        let (sum_x, sum_y) = points
+            .iter()
+            .fold((0., 0.), |(sx, sy), &(x, y)| (sx + x, sy + y));
+        assert!(sum_x.abs() < 1e-12 && sum_y.abs() < 1e-12);
+    }
+
+    #[test]
+    fn jitter_stays_in_its_cell() {
+        let size = 4;
+        let mut points = vec![];
+        Mandelbrot::sample_points(5, 9, size, true, &mut points);
+        assert_eq!(points.len(), size * size);
+        for (i, &(x, y)) in points.iter().enumerate() {
+            let cell = 1. / size as f64;
+            let left = 5. - 0.5 + (i % size) as f64 * cell;
+            let top = 9. - 0.5 + (i / size) as f64 * cell;
+            assert!(x >= left && x < left + cell, "sample {} at x {}", i, x);
+            assert!(y >= top && y < top + cell, "sample {} at y {}", i, y);
+        }
+        // The same pixel gets the same samples every frame, but
+        // they don't all sit in the same spot of their cells
+        let mut again = vec![];
+        Mandelbrot::sample_points(5, 9, size, true, &mut again);
+        assert_eq!(points, again);
+        let (jx, jy) = Mandelbrot::jitter(5, 9, 0);
+        assert_ne!((jx, jy), Mandelbrot::jitter(5, 9, 1));
+        assert_ne!((jx, jy), Mandelbrot::jitter(6, 9, 0));
+    }
+
+    const DARK: Pixel = (0, 0, 0, 255);
+    const LIGHT: Pixel = (200, 200, 200, 255);
+
+    #[test]
+    fn flat_tiles_have_no_edges() {
+        let marked = Mandelbrot::edges(4, 3, 12, |_, _| Some(DARK));
+        assert!(marked.iter().all(|m| !m));
+    }
+
+    #[test]
+    fn edges_inside_mark_both_sides() {
+        // Left half dark, right half light
+        let marked = Mandelbrot::edges(4, 2, 12, |x, _| Some(if x < 2 { DARK } else { LIGHT }));
+        let row = [false, true, true, false];
+        assert_eq!(marked, [row, row].concat());
+    }
+
+    #[test]
+    fn edges_across_the_tile_border_are_found() {
+        // Flat tile, with something else to the right of it
+        let color = |x: isize, _| Some(if x < 4 { DARK } else { LIGHT });
+        let marked = Mandelbrot::edges(4, 2, 12, color);
+        let row = [false, false, false, true];
+        assert_eq!(marked, [row, row].concat());
+        // Nothing known past the border means nothing to compare with
+        let color = |x: isize, y: isize| {
+            if x < 0 || y < 0 || x >= 4 || y >= 2 {
+                None
+            } else {
+                Some(DARK)
+            }
+        };
+        assert!(Mandelbrot::edges(4, 2, 12, color).iter().all(|m| !m));
+    }
+
+    #[test]
+    fn small_differences_are_below_the_threshold() {
+        let close = (10, 10, 10, 255);
+        let marked = Mandelbrot::edges(2, 1, 12, |x, _| Some(if x == 0 { DARK } else { close }));
+        assert_eq!(marked, [false, false]);
+    }
+
+    #[test]
+    fn only_whole_zoom_factors_reuse_pixels() {
+        assert!(Mandelbrot::whole_factor(1.));
+        assert!(Mandelbrot::whole_factor(2.));
+        assert!(Mandelbrot::whole_factor(0.5));
+        assert!(Mandelbrot::whole_factor(1. / 3.));
+        assert!(!Mandelbrot::whole_factor(1.1));
+        assert!(!Mandelbrot::whole_factor(1. / 1.5));
+    }
+}
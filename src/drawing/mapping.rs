@@ -0,0 +1,170 @@
+// Ways of laying the fractal out on the screen, other than flat. All of
+// them work on distances from the center of the view, so they keep
+// working at zooms where only offsets from the center can be told apart.
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mapping {
+    Flat,
+    // Log-polar: the angle around the center goes across, and the distance
+    // shrinks exponentially going down. The top row is the edge of the view,
+    // and every window width further down is 535 times deeper, so a tall
+    // strip holds a whole zoom in one picture.
+    Exponential,
+    // z -> r^2 / z around the center, with r half the width of the view.
+    // The edge of the view stays put, and the point at infinity is in the middle.
+    Inverse,
+    // The Riemann sphere, turned so the center of the view faces the screen
+    // and the point at infinity is at the top of the rim. Near the middle it
+    // looks like the flat map. The front half is everything above a line one
+    // ball radius below the center, squeezed up toward infinity.
+    Sphere,
+}
+
+impl Mapping {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Flat => Self::Exponential,
+            Self::Exponential => Self::Inverse,
+            Self::Inverse => Self::Sphere,
+            Self::Sphere => Self::Flat,
+        }
+    }
+
+    // Takes where a pixel would be on the flat map, as an offset from the
+    // center of a view width by height big, and gives where it is on this
+    // one. None for pixels that don't show any point, like the corners
+    // around the sphere.
+    pub fn map(self, x: f64, y: f64, width: f64, height: f64) -> Option<(f64, f64)> {
+        let radius = width / 2.;
+        match self {
+            Self::Flat => Some((x, y)),
+            Self::Exponential => {
+                let angle = 2. * PI * x / width;
+                let distance = radius * (-2. * PI * (y + height / 2.) / width).exp();
+                Some((distance * angle.cos(), distance * angle.sin()))
+            }
+            Self::Inverse => {
+                let norm = x * x + y * y;
+                if norm == 0. {
+                    return None;
+                }
+                let scale = radius * radius / norm;
+                Some((x * scale, -y * scale))
+            }
+            Self::Sphere => {
+                // Point on the ball, with z coming out of the screen
+                let ball = width.min(height) / 2.;
+                let (x, y) = (x / ball, y / ball);
+                let norm = x * x + y * y;
+                if norm > 1. {
+                    return None;
+                }
+                let z = (1. - norm).sqrt();
+                // Projected from the top of the rim, which is infinity, and
+                // moved so the point facing the screen is the center
+                let below = 1. + y;
+                if below < 1e-12 {
+                    return None;
+                }
+                Some((ball * x / below, ball * (1. - z / below)))
+            }
+        }
+    }
+
+    // The smallest distance between neighboring pixels, given what it
+    // is on the flat map. That's the one precision has to be picked for.
+    pub fn smallest_step(self, step: f64, width: f64, height: f64) -> f64 {
+        match self {
+            Self::Flat => step,
+            // The bottom row is the deepest
+            Self::Exponential => step * PI * (-2. * PI * height / width).exp(),
+            // Pixels near the edge of the view, or at the bottom of the
+            // ball, come out about half as big, and everywhere else they're bigger
+            Self::Inverse | Self::Sphere => step / 2.,
+        }
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Flat => "flat",
+            Self::Exponential => "exponential",
+            Self::Inverse => "inverse",
+            Self::Sphere => "sphere",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Mapping {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "flat" => Ok(Self::Flat),
+            "exponential" => Ok(Self::Exponential),
+            "inverse" => Ok(Self::Inverse),
+            "sphere" => Ok(Self::Sphere),
+            _ => Err(format!("Unknown mapping: {}", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAPPINGS: [Mapping; 4] = [
+        Mapping::Flat,
+        Mapping::Exponential,
+        Mapping::Inverse,
+        Mapping::Sphere,
+    ];
+
+    fn near(a: (f64, f64), b: (f64, f64)) -> bool {
+        (a.0 - b.0).abs() < 1e-9 && (a.1 - b.1).abs() < 1e-9
+    }
+
+    #[test]
+    fn the_sphere_faces_the_center() {
+        let sphere = |x, y| Mapping::Sphere.map(x, y, 4., 2.);
+        assert!(near(sphere(0., 0.).unwrap(), (0., 0.)));
+        // Close to the middle it's the flat map
+        let (x, y) = sphere(1e-4, -2e-4).unwrap();
+        assert!((x - 1e-4).abs() < 1e-6 && (y + 2e-4).abs() < 1e-6);
+        // Left and right, up and down all show, not only one half
+        let (left, right) = (sphere(-0.5, 0.).unwrap(), sphere(0.5, 0.).unwrap());
+        assert!(left.0 < 0. && right.0 > 0. && near(left, (-right.0, right.1)));
+        let (up, down) = (sphere(0., -0.5).unwrap(), sphere(0., 0.5).unwrap());
+        assert!(up.1 < 0. && down.1 > 0.);
+        // The bottom of the rim is one ball radius down, and the top is infinity
+        assert!(near(sphere(0., 1.).unwrap(), (0., 1.)));
+        assert!(sphere(0., -0.999_999).unwrap().1 < -1e3);
+        assert_eq!(sphere(0., -1.), None);
+        assert_eq!(sphere(1., 0.5), None);
+    }
+
+    // Zooming scales the view's width, so the center stays put only if
+    // every mapping grows along with it
+    #[test]
+    fn mappings_scale_with_the_view() {
+        for mapping in MAPPINGS.iter() {
+            for &(x, y) in &[(0.3, -0.2), (-0.7, 0.4), (0.05, 0.9)] {
+                let (a, b) = mapping.map(x, y, 4., 2.).unwrap();
+                let (c, d) = mapping.map(x / 8., y / 8., 0.5, 0.25).unwrap();
+                assert!(near((a / 8., b / 8.), (c, d)), "{}", mapping);
+            }
+        }
+    }
+
+    #[test]
+    fn names_read_back() {
+        for mapping in MAPPINGS.iter() {
+            assert_eq!(mapping.to_string().parse::<Mapping>(), Ok(*mapping));
+        }
+        assert!("round".parse::<Mapping>().is_err());
+    }
+}
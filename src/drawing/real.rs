@@ -0,0 +1,476 @@
+// The kinds of real numbers a Complex can be made of. f64 is what everything
+// runs on normally, f32 is there for speed over accuracy, and the other two
+// are for zooms too deep for f64 to tell pixels apart.
+//
+// Only + - * / and sqrt are done at the full precision of the wider types.
+// The others are worked out through f64, which is plenty for coloring, and
+// the escape loop never needs them.
+use bigdecimal::num_bigint::BigInt;
+use bigdecimal::BigDecimal;
+use std::fmt::{self, Debug};
+use std::ops::{Add, Div, Mul, Neg, Sub};
+use std::str::FromStr;
+
+pub trait Real:
+    Clone
+    + PartialOrd
+    + Debug
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Neg<Output = Self>
+{
+    fn from_f64(x: f64) -> Self;
+    fn to_f64(&self) -> f64;
+    // Keeps as many of the digits as the type can
+    fn from_big(x: &Big) -> Self {
+        Self::from_f64(x.to_f64())
+    }
+
+    fn sqrt(&self) -> Self {
+        Self::from_f64(self.to_f64().sqrt())
+    }
+    fn exp(&self) -> Self {
+        Self::from_f64(self.to_f64().exp())
+    }
+    fn ln(&self) -> Self {
+        Self::from_f64(self.to_f64().ln())
+    }
+    fn sin(&self) -> Self {
+        Self::from_f64(self.to_f64().sin())
+    }
+    fn cos(&self) -> Self {
+        Self::from_f64(self.to_f64().cos())
+    }
+    fn sinh(&self) -> Self {
+        Self::from_f64(self.to_f64().sinh())
+    }
+    fn cosh(&self) -> Self {
+        Self::from_f64(self.to_f64().cosh())
+    }
+    // The angle of the point (x, self)
+    fn atan2(&self, x: &Self) -> Self {
+        Self::from_f64(self.to_f64().atan2(x.to_f64()))
+    }
+    fn is_finite(&self) -> bool {
+        self.to_f64().is_finite()
+    }
+
+    fn zero() -> Self {
+        Self::from_f64(0.)
+    }
+    fn one() -> Self {
+        Self::from_f64(1.)
+    }
+    fn abs(&self) -> Self {
+        if *self < Self::zero() {
+            -self.clone()
+        } else {
+            self.clone()
+        }
+    }
+}
+
+impl Real for f64 {
+    fn from_f64(x: f64) -> Self {
+        x
+    }
+    fn to_f64(&self) -> f64 {
+        *self
+    }
+    fn sqrt(&self) -> Self {
+        f64::sqrt(*self)
+    }
+    fn abs(&self) -> Self {
+        f64::abs(*self)
+    }
+}
+
+impl Real for f32 {
+    fn from_f64(x: f64) -> Self {
+        x as f32
+    }
+    fn to_f64(&self) -> f64 {
+        *self as f64
+    }
+    fn sqrt(&self) -> Self {
+        f32::sqrt(*self)
+    }
+    fn exp(&self) -> Self {
+        f32::exp(*self)
+    }
+    fn ln(&self) -> Self {
+        f32::ln(*self)
+    }
+    fn sin(&self) -> Self {
+        f32::sin(*self)
+    }
+    fn cos(&self) -> Self {
+        f32::cos(*self)
+    }
+    fn abs(&self) -> Self {
+        f32::abs(*self)
+    }
+}
+
+// An unevaluated sum of two f64s, hi + lo, where lo is below the last bit
+// of hi. That gives about 32 digits, with hardware speed for the parts.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct DoubleDouble {
+    hi: f64,
+    lo: f64,
+}
+
+impl DoubleDouble {
+    // a + b exactly, as the rounded sum and the error
+    fn two_sum(a: f64, b: f64) -> Self {
+        let s = a + b;
+        let v = s - a;
+        let e = (a - (s - v)) + (b - v);
+        DoubleDouble { hi: s, lo: e }
+    }
+
+    // The same, when |a| >= |b| is already known
+    fn quick_two_sum(a: f64, b: f64) -> Self {
+        let s = a + b;
+        DoubleDouble {
+            hi: s,
+            lo: b - (s - a),
+        }
+    }
+
+    // a * b exactly, the fused multiply add gives the rounding error
+    fn two_prod(a: f64, b: f64) -> Self {
+        let p = a * b;
+        DoubleDouble {
+            hi: p,
+            lo: a.mul_add(b, -p),
+        }
+    }
+
+    // f(hi) + f'(hi) * lo, to take lo into account at all
+    fn first_order(self, f: fn(f64) -> f64, derivative: f64) -> Self {
+        Self::two_sum(f(self.hi), derivative * self.lo)
+    }
+}
+
+impl Add for DoubleDouble {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        let s = Self::two_sum(self.hi, other.hi);
+        let t = Self::two_sum(self.lo, other.lo);
+        let s = Self::quick_two_sum(s.hi, s.lo + t.hi);
+        Self::quick_two_sum(s.hi, s.lo + t.lo)
+    }
+}
+
+impl Sub for DoubleDouble {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        self + -other
+    }
+}
+
+impl Mul for DoubleDouble {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        let p = Self::two_prod(self.hi, other.hi);
+        let lo = p.lo + (self.hi * other.lo + self.lo * other.hi);
+        Self::quick_two_sum(p.hi, lo)
+    }
+}
+
+// Long division, one f64 sized digit at a time
+impl Div for DoubleDouble {
+    type Output = Self;
+    fn div(self, other: Self) -> Self {
+        let q1 = self.hi / other.hi;
+        let r = self - other * Self::from_f64(q1);
+        let q2 = r.hi / other.hi;
+        let r = r - other * Self::from_f64(q2);
+        let q3 = r.hi / other.hi;
+        Self::quick_two_sum(q1, q2) + Self::from_f64(q3)
+    }
+}
+
+impl Neg for DoubleDouble {
+    type Output = Self;
+    fn neg(self) -> Self {
+        DoubleDouble {
+            hi: -self.hi,
+            lo: -self.lo,
+        }
+    }
+}
+
+impl Real for DoubleDouble {
+    fn from_f64(x: f64) -> Self {
+        DoubleDouble { hi: x, lo: 0. }
+    }
+    fn to_f64(&self) -> f64 {
+        self.hi + self.lo
+    }
+    // The f64 closest to x, then the closest to what that misses by
+    fn from_big(x: &Big) -> Self {
+        let hi = x.to_f64();
+        let lo = (x.clone() - Big::from_f64(hi)).to_f64();
+        Self::quick_two_sum(hi, lo)
+    }
+    // One Newton step from the f64 root doubles the digits
+    fn sqrt(&self) -> Self {
+        if self.hi <= 0. {
+            return Self::from_f64(self.hi.sqrt());
+        }
+        let x = Self::from_f64(self.hi.sqrt());
+        x + (*self - x * x) / (x + x)
+    }
+    fn exp(&self) -> Self {
+        self.first_order(f64::exp, self.hi.exp())
+    }
+    fn ln(&self) -> Self {
+        self.first_order(f64::ln, 1. / self.hi)
+    }
+    fn sin(&self) -> Self {
+        self.first_order(f64::sin, self.hi.cos())
+    }
+    fn cos(&self) -> Self {
+        self.first_order(f64::cos, -self.hi.sin())
+    }
+    fn sinh(&self) -> Self {
+        self.first_order(f64::sinh, self.hi.cosh())
+    }
+    fn cosh(&self) -> Self {
+        self.first_order(f64::cosh, self.hi.sinh())
+    }
+}
+
+// Significant digits a Big keeps at the least
+const BIG_DIGITS: u64 = 64;
+
+// As many digits as it's given, but slow. Results are rounded to the most
+// digits of the two sides, so they don't keep growing, and one number with
+// more digits brings everything worked out from it up to the same.
+// BigDecimal only divides and takes roots to 100 digits.
+#[derive(Clone, Debug)]
+pub struct Big {
+    value: BigDecimal,
+    digits: u64,
+}
+
+impl Big {
+    fn round(value: BigDecimal, digits: u64) -> Self {
+        let value = if value.digits() > digits {
+            value.with_prec(digits)
+        } else {
+            value
+        };
+        Big { value, digits }
+    }
+
+    // The same number kept to this many digits from now on
+    pub fn with_digits(self, digits: u64) -> Self {
+        Self::round(self.value, digits.max(BIG_DIGITS))
+    }
+
+    fn digits_with(&self, other: &Self) -> u64 {
+        self.digits.max(other.digits)
+    }
+}
+
+// Only the value counts, not how many digits are kept of it
+impl PartialEq for Big {
+    fn eq(&self, other: &Self) -> bool {
+        self.value == other.value
+    }
+}
+
+impl PartialOrd for Big {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        self.value.partial_cmp(&other.value)
+    }
+}
+
+impl Add for Big {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        let digits = self.digits_with(&other);
+        Self::round(self.value + other.value, digits)
+    }
+}
+
+impl Sub for Big {
+    type Output = Self;
+    fn sub(self, other: Self) -> Self {
+        let digits = self.digits_with(&other);
+        Self::round(self.value - other.value, digits)
+    }
+}
+
+impl Mul for Big {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        let digits = self.digits_with(&other);
+        Self::round(self.value * other.value, digits)
+    }
+}
+
+impl Div for Big {
+    type Output = Self;
+    fn div(self, other: Self) -> Self {
+        let digits = self.digits_with(&other);
+        Self::round(self.value / other.value, digits)
+    }
+}
+
+impl Neg for Big {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Big {
+            value: -self.value,
+            digits: self.digits,
+        }
+    }
+}
+
+impl Real for Big {
+    // Every f64 is a whole number times a power of two, so it can be written
+    // as a decimal exactly. BigDecimal's own conversion stops at 15 digits.
+    // There's nothing for infinity or NaN, so they turn into 0.
+    fn from_f64(x: f64) -> Self {
+        if x == 0. || !x.is_finite() {
+            return Self::round(BigDecimal::from(0), BIG_DIGITS);
+        }
+        let bits = x.to_bits();
+        let exponent = ((bits >> 52) & 0x7ff) as i64;
+        let fraction = bits & ((1 << 52) - 1);
+        // Subnormals don't have the hidden leading 1
+        let (mantissa, exponent) = if exponent == 0 {
+            (fraction, -1074)
+        } else {
+            (fraction | (1 << 52), exponent - 1075)
+        };
+        let mantissa = if x < 0. {
+            -BigInt::from(mantissa)
+        } else {
+            BigInt::from(mantissa)
+        };
+        let x = if exponent >= 0 {
+            BigDecimal::from(mantissa << exponent as usize)
+        } else {
+            // m / 2^k is m * 5^k / 10^k
+            let k = -exponent;
+            BigDecimal::new(mantissa * BigInt::from(5).pow(k as u32), k)
+        };
+        Self::round(x, BIG_DIGITS)
+    }
+    // BigDecimal's own conversion is off in the last bits, and makes 0 of
+    // anything under about 1e-20. Rust's parsing rounds the digits right.
+    fn to_f64(&self) -> f64 {
+        let (digits, scale) = self.value.as_bigint_and_exponent();
+        format!("{}e{}", digits, -scale).parse().unwrap_or(f64::NAN)
+    }
+    fn from_big(x: &Big) -> Self {
+        x.clone()
+    }
+    fn sqrt(&self) -> Self {
+        match self.value.sqrt() {
+            Some(x) => Self::round(x, self.digits),
+            None => Self::from_f64(f64::NAN),
+        }
+    }
+    fn is_finite(&self) -> bool {
+        true
+    }
+}
+
+// Written the shortest way that reads back the same, so a center that's
+// still an f64 looks like one, and one zoomed in past that has every digit
+impl fmt::Display for Big {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let x = self.to_f64();
+        if Self::from_f64(x) == *self {
+            write!(f, "{}", x)
+        } else {
+            write!(f, "{}", self.value.normalized())
+        }
+    }
+}
+
+// Keeps every digit given. Up to 17 digits is how Display writes an f64,
+// so those are taken as the f64 they stand for and read back the same.
+// There's no inf or NaN to read.
+impl FromStr for Big {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let x: BigDecimal = s
+            .trim()
+            .parse()
+            .map_err(|_| format!("Not a valid number: {}", s))?;
+        if x.digits() <= 17 {
+            if let Ok(x) = s.trim().parse() {
+                return Ok(Self::from_f64(x));
+            }
+        }
+        let digits = x.digits();
+        Ok(Self::round(x, digits.max(BIG_DIGITS)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn big_keeps_every_digit_of_an_f64() {
+        for &x in &[0.1, -1.999_999_9, 1e-300, 5e-324, 1e300] {
+            assert_eq!(Big::from_f64(x).to_f64(), x);
+        }
+        let sum = Big::from_f64(0.1) + Big::from_f64(0.2);
+        assert_ne!(sum, Big::from_f64(0.1 + 0.2));
+    }
+
+    #[test]
+    fn big_prints_and_parses_back_the_same() {
+        assert_eq!(Big::from_f64(-0.75).to_string(), "-0.75");
+        let deep = Big::from_f64(-0.75) + Big::from_f64(1e-40);
+        let text = deep.to_string();
+        assert!(text.len() > 40);
+        assert_eq!(text.parse::<Big>().unwrap(), deep);
+        assert!("inf".parse::<Big>().is_err());
+    }
+
+    #[test]
+    fn big_keeps_the_most_digits_it_was_given() {
+        let tiny = Big::from_f64(1e-90);
+        // Lost under the default digits
+        assert_eq!(Big::one() + tiny.clone(), Big::one());
+        let wide = Big::one().with_digits(100);
+        let sum = wide.clone() + tiny.clone();
+        assert_ne!(sum, Big::one());
+        // Everything worked out from it keeps as many
+        let back = sum * Big::from_f64(2.) - wide.clone() - wide;
+        assert!((back.to_f64() - 2e-90).abs() < 1e-100);
+    }
+
+    #[test]
+    fn double_double_keeps_what_f64_drops() {
+        // f64 can't tell this from -0.75
+        assert_eq!(-0.75 + 1e-25, -0.75);
+        let x = Big::from_f64(-0.75) + Big::from_f64(1e-25);
+        let wide = DoubleDouble::from_big(&x);
+        let rest = wide - DoubleDouble::from_f64(-0.75);
+        assert!((rest.to_f64() - 1e-25).abs() < 1e-38);
+        assert_eq!(f64::from_big(&x), -0.75);
+    }
+
+    #[test]
+    fn double_double_arithmetic() {
+        let third = DoubleDouble::one() / DoubleDouble::from_f64(3.);
+        let back = third * DoubleDouble::from_f64(3.) - DoubleDouble::one();
+        assert!(back.to_f64().abs() < 1e-30);
+        let root = DoubleDouble::from_f64(2.).sqrt();
+        let error = root * root - DoubleDouble::from_f64(2.);
+        assert!(error.to_f64().abs() < 1e-30);
+    }
+}
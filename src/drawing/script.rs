@@ -0,0 +1,954 @@
+// Formulas typed in by the user, for fractals that aren't built in.
+// They're read from formulas.txt, which looks like
+//
+//     # z starts at z0 and becomes the next z until bailout is true
+//     sine-mix {
+//         z0 = c
+//         z = z^3 + c*sin(z)
+//         bailout = |z| > 2
+//     }
+//     # Newton's method on f, starting at the point
+//     newton3 { f = z^3 - 1 }
+//
+// Statements can be split by semicolons as well as lines. z0 and bailout
+// can be left out, then z starts at 0, or at c for Newton's method, and
+// escapes once |z| is past the bailout radius.
+//
+// c is the point being drawn. There's i, pi and e, + - * / ^, the
+// comparisons < > <= >= which give 1 or 0, |x| for the size of x, and
+// sin cos tan sinh cosh exp log sqrt conj re im.
+//
+// Text is split into tokens, parsed into an Expr tree, and compiled
+// into a Program which runs on a small stack.
+use super::complex::Complex;
+use super::symbolic;
+use std::fmt;
+use std::sync::OnceLock;
+
+pub const FORMULA_FILE: &str = "formulas.txt";
+
+// The formulas there are until someone writes their own file
+const EXAMPLES: &str = "\
+cubic { z = z^3 + c }
+sine-mix { z0 = c; z = z^3 + c*sin(z) }
+exp { z0 = c; z = exp(z) + c; bailout = re(z) > 50 }
+newton3 { f = z^3 - 1 }
+newton-sin { f = sin(z) - 1/2 }
+";
+
+// Location strings give a name's length in one byte
+pub const MAX_NAME: usize = u8::MAX as usize;
+
+// Newton's method is done once a step's |step|^2 is less than this
+const CONVERGED: f64 = 1e-12;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+    Less,
+    Greater,
+    LessEq,
+    GreaterEq,
+}
+
+impl BinOp {
+    pub fn apply(self, a: Complex, b: Complex) -> Complex {
+        // Comparisons only look at the real parts
+        let test = |t: bool| if t { Complex::ONE } else { Complex::ZERO };
+        match self {
+            Self::Add => a + b,
+            Self::Sub => a - b,
+            Self::Mul => a * b,
+            Self::Div => a / b,
+            Self::Pow => a.powc(b),
+            Self::Less => test(a.re < b.re),
+            Self::Greater => test(a.re > b.re),
+            Self::LessEq => test(a.re <= b.re),
+            Self::GreaterEq => test(a.re >= b.re),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Func {
+    Sin,
+    Cos,
+    Tan,
+    Sinh,
+    Cosh,
+    Exp,
+    Log,
+    Sqrt,
+    Abs,
+    Conj,
+    Re,
+    Im,
+}
+
+const FUNCS: [(&str, Func); 12] = [
+    ("sin", Func::Sin),
+    ("cos", Func::Cos),
+    ("tan", Func::Tan),
+    ("sinh", Func::Sinh),
+    ("cosh", Func::Cosh),
+    ("exp", Func::Exp),
+    ("log", Func::Log),
+    ("sqrt", Func::Sqrt),
+    ("abs", Func::Abs),
+    ("conj", Func::Conj),
+    ("re", Func::Re),
+    ("im", Func::Im),
+];
+
+impl Func {
+    pub fn name(self) -> &'static str {
+        FUNCS.iter().find(|&&(_, f)| f == self).unwrap().0
+    }
+
+    pub fn apply(self, a: Complex) -> Complex {
+        match self {
+            Self::Sin => a.sin(),
+            Self::Cos => a.cos(),
+            Self::Tan => a.tan(),
+            Self::Sinh => a.sinh(),
+            Self::Cosh => a.cosh(),
+            Self::Exp => a.exp(),
+            Self::Log => a.ln(),
+            Self::Sqrt => a.sqrt(),
+            Self::Abs => Complex::real(a.abs()),
+            Self::Conj => a.conj(),
+            Self::Re => Complex::real(a.re),
+            Self::Im => Complex::real(a.im),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Num(Complex),
+    Z,
+    C,
+    Neg(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    Call(Func, Box<Expr>),
+}
+
+impl Expr {
+    pub fn binary(op: BinOp, a: Expr, b: Expr) -> Self {
+        Expr::Binary(op, Box::new(a), Box::new(b))
+    }
+
+    pub fn call(func: Func, a: Expr) -> Self {
+        Expr::Call(func, Box::new(a))
+    }
+
+    fn uses_z_or_c(&self) -> bool {
+        match self {
+            Expr::Num(_) => false,
+            Expr::Z | Expr::C => true,
+            Expr::Neg(a) | Expr::Call(_, a) => a.uses_z_or_c(),
+            Expr::Binary(_, a, b) => a.uses_z_or_c() || b.uses_z_or_c(),
+        }
+    }
+
+    fn eval(&self, z: Complex, c: Complex) -> Complex {
+        match self {
+            Expr::Num(x) => *x,
+            Expr::Z => z,
+            Expr::C => c,
+            Expr::Neg(a) => -a.eval(z, c),
+            Expr::Binary(op, a, b) => op.apply(a.eval(z, c), b.eval(z, c)),
+            Expr::Call(func, a) => func.apply(a.eval(z, c)),
+        }
+    }
+}
+
+// Written back out with only the brackets that are needed
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+impl Expr {
+    // How tightly the expression binds, as in the grammar further down
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Binary(op, ..) => match op {
+                BinOp::Less | BinOp::Greater | BinOp::LessEq | BinOp::GreaterEq => 1,
+                BinOp::Add | BinOp::Sub => 2,
+                BinOp::Mul | BinOp::Div => 3,
+                BinOp::Pow => 5,
+            },
+            Expr::Neg(_) => 4,
+            Expr::Num(x) if x.re < 0. || (x.re != 0. && x.im != 0.) => 4,
+            _ => 6,
+        }
+    }
+
+    // Brackets go around anything looser than min
+    fn write(&self, f: &mut fmt::Formatter<'_>, min: u8) -> fmt::Result {
+        let precedence = self.precedence();
+        if precedence < min {
+            write!(f, "(")?;
+        }
+        match self {
+            Expr::Num(x) if x.im == 0. => write!(f, "{}", x.re)?,
+            Expr::Num(x) if x.re == 0. => write!(f, "{}*i", x.im)?,
+            Expr::Num(x) => write!(f, "{} + {}*i", x.re, x.im)?,
+            Expr::Z => write!(f, "z")?,
+            Expr::C => write!(f, "c")?,
+            Expr::Neg(a) => {
+                write!(f, "-")?;
+                a.write(f, 4)?;
+            }
+            Expr::Call(func, a) => {
+                write!(f, "{}(", func.name())?;
+                a.write(f, 0)?;
+                write!(f, ")")?;
+            }
+            Expr::Binary(op, a, b) => {
+                let symbol = match op {
+                    BinOp::Add => " + ",
+                    BinOp::Sub => " - ",
+                    BinOp::Mul => "*",
+                    BinOp::Div => "/",
+                    BinOp::Pow => "^",
+                    BinOp::Less => " < ",
+                    BinOp::Greater => " > ",
+                    BinOp::LessEq => " <= ",
+                    BinOp::GreaterEq => " >= ",
+                };
+                // Left to right, except for powers which go right to left
+                let (left, right) = match op {
+                    BinOp::Pow => (6, 4),
+                    _ => (precedence, precedence + 1),
+                };
+                a.write(f, left)?;
+                write!(f, "{}", symbol)?;
+                b.write(f, right)?;
+            }
+        }
+        if precedence < min {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Num(f64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 13] = [
+    "<=", ">=", "<", ">", "+", "-", "*", "/", "^", "(", ")", "|", ",",
+];
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = text.trim_start();
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap();
+        let len = if c.is_ascii_digit() || c == '.' {
+            // Digits, then maybe an exponent like e-5
+            let mut len = rest
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(rest.len());
+            let after = &rest[len..];
+            if let Some(exponent) = after.strip_prefix(|c| c == 'e' || c == 'E') {
+                let digits = exponent.trim_start_matches(['+', '-']);
+                if digits.starts_with(|c: char| c.is_ascii_digit()) {
+                    let sign = exponent.len() - digits.len();
+                    let count = digits
+                        .find(|c: char| !c.is_ascii_digit())
+                        .unwrap_or(digits.len());
+                    len += 1 + sign + count;
+                }
+            }
+            let number = &rest[..len];
+            tokens.push(Token::Num(
+                number
+                    .parse()
+                    .map_err(|_| format!("Not a valid number: {}", number))?,
+            ));
+            len
+        } else if c.is_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !c.is_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            tokens.push(Token::Name(rest[..len].to_lowercase()));
+            len
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|s| rest.starts_with(*s))
+                .ok_or(format!("Unexpected {}", c))?;
+            tokens.push(Token::Symbol(symbol));
+            symbol.len()
+        };
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+// From the loosest binding up:
+//   compare: sum (< > <= >= sum)?
+//   sum:     product (+ - product)*
+//   product: unary (* / unary)*
+//   unary:   -unary | power
+//   power:   atom (^ unary)?
+//   atom:    number | name | name(compare) | (compare) | |compare|
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn symbol(&mut self, symbols: &[&str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Symbol(s)) if symbols.contains(s) => {
+                let s = *s;
+                self.pos += 1;
+                Some(s)
+            }
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        match self.symbol(&[symbol]) {
+            Some(_) => Ok(()),
+            None => Err(format!("Expected {}", symbol)),
+        }
+    }
+
+    fn compare(&mut self) -> Result<Expr, String> {
+        let a = self.sum()?;
+        let op = match self.symbol(&["<", ">", "<=", ">="]) {
+            Some("<") => BinOp::Less,
+            Some(">") => BinOp::Greater,
+            Some("<=") => BinOp::LessEq,
+            Some(_) => BinOp::GreaterEq,
+            None => return Ok(a),
+        };
+        Ok(Expr::binary(op, a, self.sum()?))
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut a = self.product()?;
+        while let Some(s) = self.symbol(&["+", "-"]) {
+            let op = if s == "+" { BinOp::Add } else { BinOp::Sub };
+            a = Expr::binary(op, a, self.product()?);
+        }
+        Ok(a)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut a = self.unary()?;
+        while let Some(s) = self.symbol(&["*", "/"]) {
+            let op = if s == "*" { BinOp::Mul } else { BinOp::Div };
+            a = Expr::binary(op, a, self.unary()?);
+        }
+        Ok(a)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.symbol(&["-"]).is_some() {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.power()
+    }
+
+    // Goes right to left, so z^2^3 is z^(2^3), and -z^2 is -(z^2)
+    fn power(&mut self) -> Result<Expr, String> {
+        let a = self.atom()?;
+        if self.symbol(&["^"]).is_some() {
+            return Ok(Expr::binary(BinOp::Pow, a, self.unary()?));
+        }
+        Ok(a)
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Num(x)) => Ok(Expr::Num(Complex::real(x))),
+            Some(Token::Name(name)) => match name.as_str() {
+                "z" => Ok(Expr::Z),
+                "c" | "pixel" => Ok(Expr::C),
+                "i" => Ok(Expr::Num(Complex::I)),
+                "pi" => Ok(Expr::Num(Complex::real(std::f64::consts::PI))),
+                "e" => Ok(Expr::Num(Complex::real(std::f64::consts::E))),
+                _ => {
+                    let func = FUNCS
+                        .iter()
+                        .find(|(n, _)| *n == name)
+                        .map(|&(_, func)| func)
+                        .ok_or(format!("Unknown name: {}", name))?;
+                    self.expect("(")?;
+                    let a = self.compare()?;
+                    self.expect(")")?;
+                    Ok(Expr::call(func, a))
+                }
+            },
+            Some(Token::Symbol("(")) => {
+                let a = self.compare()?;
+                self.expect(")")?;
+                Ok(a)
+            }
+            Some(Token::Symbol("|")) => {
+                let a = self.compare()?;
+                self.expect("|")?;
+                Ok(Expr::call(Func::Abs, a))
+            }
+            Some(Token::Symbol(s)) => Err(format!("Unexpected {}", s)),
+            None => Err("Formula ends too early".to_string()),
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+    };
+    let expr = parser.compare()?;
+    match parser.next() {
+        None => Ok(expr),
+        Some(Token::Num(x)) => Err(format!("Unexpected {} after the formula", x)),
+        Some(Token::Name(name)) => Err(format!("Unexpected {} after the formula", name)),
+        Some(Token::Symbol(s)) => Err(format!("Unexpected {} after the formula", s)),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Push(Complex),
+    Z,
+    C,
+    Neg,
+    Binary(BinOp),
+    // Powers that are whole numbers are much cheaper than powc,
+    // and real ones a little
+    PowInt(i32),
+    PowReal(f64),
+    Call(Func),
+}
+
+// An Expr flattened into postfix order
+#[derive(Clone, Debug, PartialEq)]
+pub struct Program {
+    code: Vec<Op>,
+}
+
+impl Program {
+    pub fn compile(expr: &Expr) -> Self {
+        let mut code = vec![];
+        Self::emit(expr, &mut code);
+        Program { code }
+    }
+
+    fn emit(expr: &Expr, code: &mut Vec<Op>) {
+        if !expr.uses_z_or_c() {
+            code.push(Op::Push(expr.eval(Complex::ZERO, Complex::ZERO)));
+            return;
+        }
+        match expr {
+            Expr::Num(x) => code.push(Op::Push(*x)),
+            Expr::Z => code.push(Op::Z),
+            Expr::C => code.push(Op::C),
+            Expr::Neg(a) => {
+                Self::emit(a, code);
+                code.push(Op::Neg);
+            }
+            Expr::Binary(BinOp::Pow, a, b) if !b.uses_z_or_c() => {
+                Self::emit(a, code);
+                let n = b.eval(Complex::ZERO, Complex::ZERO);
+                if n.im == 0. && n.re.fract() == 0. && n.re.abs() <= 64. {
+                    code.push(Op::PowInt(n.re as i32));
+                } else if n.im == 0. {
+                    code.push(Op::PowReal(n.re));
+                } else {
+                    code.push(Op::Push(n));
+                    code.push(Op::Binary(BinOp::Pow));
+                }
+            }
+            Expr::Binary(op, a, b) => {
+                Self::emit(a, code);
+                Self::emit(b, code);
+                code.push(Op::Binary(*op));
+            }
+            Expr::Call(func, a) => {
+                Self::emit(a, code);
+                code.push(Op::Call(*func));
+            }
+        }
+    }
+
+    // The stack is passed in so it's only allocated once per batch of points
+    pub fn run(&self, z: Complex, c: Complex, stack: &mut Vec<Complex>) -> Complex {
+        stack.clear();
+        for op in &self.code {
+            match *op {
+                Op::Push(x) => stack.push(x),
+                Op::Z => stack.push(z),
+                Op::C => stack.push(c),
+                Op::Binary(op) => {
+                    let b = stack.pop().unwrap();
+                    let a = stack.last_mut().unwrap();
+                    *a = op.apply(*a, b);
+                }
+                unary => {
+                    let a = stack.last_mut().unwrap();
+                    *a = match unary {
+                        Op::Neg => -*a,
+                        Op::PowInt(n) => a.powi(n),
+                        Op::PowReal(n) => a.powf(n),
+                        Op::Call(func) => func.apply(*a),
+                        _ => unreachable!(),
+                    };
+                }
+            }
+        }
+        stack.pop().unwrap()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Kind {
+    Escape {
+        start: Program,
+        step: Program,
+        // None for escaping past the bailout radius
+        bailout: Option<Program>,
+    },
+    Newton {
+        start: Program,
+        f: Program,
+        derivative: Program,
+        // Kept to show what was worked out
+        derivative_expr: Expr,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Script {
+    pub name: String,
+    kind: Kind,
+}
+
+impl Script {
+    pub fn parse(name: &str, body: &str) -> Result<Self, String> {
+        let (mut start, mut step, mut bailout, mut f) = (None, None, None, None);
+        for statement in body.split(['\n', ';']) {
+            let statement = statement.trim();
+            if statement.is_empty() {
+                continue;
+            }
+            let (target, text) = statement.split_once('=').ok_or(format!(
+                "Expected something like z = ..., got {}",
+                statement
+            ))?;
+            let expr = parse(text).map_err(|e| format!("{}: {}", statement, e))?;
+            let slot = match target.trim() {
+                "z0" => &mut start,
+                "z" => &mut step,
+                "bailout" => &mut bailout,
+                "f" => &mut f,
+                target => return Err(format!("Can't set {}", target)),
+            };
+            if slot.replace(expr).is_some() {
+                return Err(format!("{} is set twice", target.trim()));
+            }
+        }
+        let kind = match (step, f) {
+            (Some(step), None) => Kind::Escape {
+                start: Program::compile(&start.unwrap_or(Expr::Num(Complex::ZERO))),
+                step: Program::compile(&step),
+                bailout: bailout.as_ref().map(Program::compile),
+            },
+            (None, Some(f)) => {
+                if bailout.is_some() {
+                    return Err("Newton's method has no bailout".to_string());
+                }
+                let derivative = symbolic::derivative(&f, &Expr::Z)?;
+                Kind::Newton {
+                    start: Program::compile(&start.unwrap_or(Expr::C)),
+                    f: Program::compile(&f),
+                    derivative: Program::compile(&derivative),
+                    derivative_expr: derivative,
+                }
+            }
+            (Some(_), Some(_)) => return Err("Only one of z and f can be set".to_string()),
+            (None, None) => return Err("Needs z = ... or f = ...".to_string()),
+        };
+        Ok(Script {
+            name: name.to_string(),
+            kind,
+        })
+    }
+
+    // f' for Newton's method
+    pub fn derivative(&self) -> Option<&Expr> {
+        match &self.kind {
+            Kind::Newton {
+                derivative_expr, ..
+            } => Some(derivative_expr),
+            Kind::Escape { .. } => None,
+        }
+    }
+
+    // Like the built in formulas, gives how many iterations the point took
+    // and the final |z|^2. For Newton's method that's how long it took to
+    // settle on a root, and points that never do count as inside.
+    pub fn escape(
+        &self,
+        c: Complex,
+        max_iter: usize,
+        bailout: f64,
+        stack: &mut Vec<Complex>,
+    ) -> (usize, f64) {
+        match &self.kind {
+            Kind::Escape {
+                start,
+                step,
+                bailout: test,
+            } => {
+                let mut z = start.run(Complex::ZERO, c, stack);
+                let mut iteration = 0;
+                while iteration < max_iter && z.is_finite() {
+                    let escaped = match test {
+                        Some(test) => test.run(z, c, stack).re != 0.,
+                        None => z.norm_sqr() > bailout,
+                    };
+                    if escaped {
+                        break;
+                    }
+                    z = step.run(z, c, stack);
+                    iteration += 1;
+                }
+                // Coloring expects |z| to be past 2
+                let mag = z.norm_sqr();
+                (
+                    iteration,
+                    if mag.is_finite() {
+                        mag.max(4.)
+                    } else {
+                        f64::MAX
+                    },
+                )
+            }
+            Kind::Newton {
+                start,
+                f,
+                derivative,
+                ..
+            } => {
+                let mut z = start.run(Complex::ZERO, c, stack);
+                for iteration in 0..max_iter {
+                    let step = f.run(z, c, stack) / derivative.run(z, c, stack);
+                    if !step.is_finite() {
+                        break;
+                    }
+                    z = z - step;
+                    if step.norm_sqr() < CONVERGED {
+                        return (iteration, 4.);
+                    }
+                }
+                (max_iter, 0.)
+            }
+        }
+    }
+}
+
+// Every formula from the file, loaded the first time it's needed
+pub fn library() -> &'static [Script] {
+    static LIBRARY: OnceLock<Vec<Script>> = OnceLock::new();
+    LIBRARY.get_or_init(|| {
+        load().unwrap_or_else(|e| {
+            println!("Couldn't load formulas: {}", e);
+            parse_file(EXAMPLES).unwrap()
+        })
+    })
+}
+
+pub fn find(name: &str) -> Option<&'static Script> {
+    library().iter().find(|script| script.name == name)
+}
+
+// Fractint doesn't care about case in names
+pub fn find_any_case(name: &str) -> Option<&'static Script> {
+    library()
+        .iter()
+        .find(|script| script.name.eq_ignore_ascii_case(name))
+}
+
+fn load() -> Result<Vec<Script>, String> {
+    let text = match std::fs::read_to_string(FORMULA_FILE) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => EXAMPLES.to_string(),
+        Err(e) => return Err(format!("{}: {}", FORMULA_FILE, e)),
+    };
+    parse_file(&text).map_err(|e| format!("{}: {}", FORMULA_FILE, e))
+}
+
+// Formulas are "name { statements }", and # starts a comment
+fn parse_file(text: &str) -> Result<Vec<Script>, String> {
+    let text: String = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut scripts: Vec<Script> = vec![];
+    let mut rest = text.as_str();
+    while let Some(open) = rest.find('{') {
+        let name = rest[..open].trim();
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(format!(
+                "Formula names are letters, digits, - and _, got {}",
+                name
+            ));
+        }
+        if name.len() > MAX_NAME {
+            return Err(format!(
+                "Formula names can be at most {} bytes, {} is {}",
+                MAX_NAME,
+                name,
+                name.len()
+            ));
+        }
+        let close = rest[open..]
+            .find('}')
+            .ok_or(format!("{} has no closing }}", name))?
+            + open;
+        if scripts.iter().any(|script| script.name == name) {
+            return Err(format!("There's more than one {}", name));
+        }
+        let script =
+            Script::parse(name, &rest[open + 1..close]).map_err(|e| format!("{}: {}", name, e))?;
+        scripts.push(script);
+        rest = &rest[close + 1..];
+    }
+    if !rest.trim().is_empty() {
+        return Err(format!("Expected a formula, got {}", rest.trim()));
+    }
+    Ok(scripts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: Complex, b: Complex) -> bool {
+        (a - b).abs() < 1e-12
+    }
+
+    fn run(text: &str, z: Complex, c: Complex) -> Complex {
+        Program::compile(&parse(text).unwrap()).run(z, c, &mut vec![])
+    }
+
+    #[test]
+    fn splits_numbers_names_and_symbols() {
+        let tokens = tokenize("2.5e-3*Sin(z)<=1e2").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Num(2.5e-3),
+                Token::Symbol("*"),
+                Token::Name("sin".to_string()),
+                Token::Symbol("("),
+                Token::Name("z".to_string()),
+                Token::Symbol(")"),
+                Token::Symbol("<="),
+                Token::Num(100.),
+            ]
+        );
+        // An e without digits after it is the constant
+        assert_eq!(
+            tokenize("2e").unwrap(),
+            vec![Token::Num(2.), Token::Name("e".to_string())]
+        );
+        assert_eq!(tokenize("1..2").unwrap_err(), "Not a valid number: 1..2");
+        assert_eq!(tokenize("z % 2").unwrap_err(), "Unexpected %");
+    }
+
+    #[test]
+    fn powers_bind_right_to_left_and_tighter_than_minus() {
+        let z = || Box::new(Expr::Z);
+        let num = |x| Box::new(Expr::Num(Complex::real(x)));
+        assert_eq!(
+            parse("z^2^3").unwrap(),
+            Expr::Binary(
+                BinOp::Pow,
+                z(),
+                Box::new(Expr::Binary(BinOp::Pow, num(2.), num(3.)))
+            )
+        );
+        assert_eq!(
+            parse("-z^2").unwrap(),
+            Expr::Neg(Box::new(Expr::Binary(BinOp::Pow, z(), num(2.))))
+        );
+        assert_eq!(
+            parse("|z| > 2").unwrap(),
+            Expr::Binary(
+                BinOp::Greater,
+                Box::new(Expr::call(Func::Abs, Expr::Z)),
+                num(2.)
+            )
+        );
+        assert_eq!(parse("z - c - 1").unwrap().to_string(), "z - c - 1");
+        assert_eq!(parse("z - (c - 1)").unwrap().to_string(), "z - (c - 1)");
+        assert_eq!(parse("(z^2)^3").unwrap().to_string(), "(z^2)^3");
+        assert_eq!(parse("z^-2").unwrap().to_string(), "z^-2");
+    }
+
+    #[test]
+    fn explains_what_is_wrong() {
+        assert_eq!(parse("z +").unwrap_err(), "Formula ends too early");
+        assert_eq!(parse("sin z").unwrap_err(), "Expected (");
+        assert_eq!(parse("(z").unwrap_err(), "Expected )");
+        assert_eq!(parse("w").unwrap_err(), "Unknown name: w");
+        assert_eq!(parse("z c").unwrap_err(), "Unexpected c after the formula");
+    }
+
+    #[test]
+    fn folds_constants_and_uses_cheap_powers() {
+        let code = |text| Program::compile(&parse(text).unwrap()).code;
+        let pi = std::f64::consts::PI;
+        assert_eq!(
+            code("z*(2*pi - 1)"),
+            vec![
+                Op::Z,
+                Op::Push(Complex::real(2. * pi - 1.)),
+                Op::Binary(BinOp::Mul)
+            ]
+        );
+        assert_eq!(code("z^(1 + 2)"), vec![Op::Z, Op::PowInt(3)]);
+        assert_eq!(code("z^0.5"), vec![Op::Z, Op::PowReal(0.5)]);
+        assert_eq!(
+            code("z^i"),
+            vec![Op::Z, Op::Push(Complex::I), Op::Binary(BinOp::Pow)]
+        );
+        // Too big for powi to be any faster
+        assert_eq!(code("z^100"), vec![Op::Z, Op::PowReal(100.)]);
+    }
+
+    #[test]
+    fn programs_agree_with_the_tree() {
+        let (z, c) = (Complex::new(0.3, -0.7), Complex::new(-1.1, 0.4));
+        for text in [
+            "z^3 + c*sin(z)",
+            "exp(z)/(c - 1) - conj(z)^2.5",
+            "-z^2 + |c|*i",
+            "re(z) > im(c)",
+            "sqrt(log(cosh(z))) + tan(c)^z",
+        ]
+        .iter()
+        {
+            let expr = parse(text).unwrap();
+            assert!(close(run(text, z, c), expr.eval(z, c)), "{}", text);
+        }
+        assert!(close(run("z^2 + c", z, c), z * z + c));
+        assert_eq!(run("|z| > 2", Complex::real(3.), c), Complex::ONE);
+        assert_eq!(run("|z| > 2", Complex::real(1.), c), Complex::ZERO);
+    }
+
+    #[test]
+    fn reads_formulas_from_a_file() {
+        let scripts = parse_file(EXAMPLES).unwrap();
+        let names: Vec<_> = scripts.iter().map(|script| script.name.as_str()).collect();
+        assert_eq!(names, ["cubic", "sine-mix", "exp", "newton3", "newton-sin"]);
+        let scripts = parse_file("# z^2 + c\nm { z = z^2 + c # again\n }").unwrap();
+        assert_eq!(scripts, [Script::parse("m", "z = z^2 + c").unwrap()]);
+        assert_eq!(
+            parse_file("a { z = z }\na { z = c }").unwrap_err(),
+            "There's more than one a"
+        );
+        assert_eq!(parse_file("a { z = z").unwrap_err(), "a has no closing }");
+        assert_eq!(
+            parse_file("a b { z = z }").unwrap_err(),
+            "Formula names are letters, digits, - and _, got a b"
+        );
+        assert_eq!(
+            parse_file("a { z = z } b").unwrap_err(),
+            "Expected a formula, got b"
+        );
+        let long = "x".repeat(MAX_NAME + 1);
+        assert!(parse_file(&format!("{} {{ z = z }}", long)).is_err());
+        assert!(parse_file(&format!("{} {{ z = z }}", &long[1..])).is_ok());
+    }
+
+    #[test]
+    fn checks_what_is_set() {
+        let error = |body| Script::parse("s", body).unwrap_err();
+        assert_eq!(error("z = z; z = c"), "z is set twice");
+        assert_eq!(error("w = z"), "Can't set w");
+        assert_eq!(error("z"), "Expected something like z = ..., got z");
+        assert_eq!(error("z0 = c"), "Needs z = ... or f = ...");
+        assert_eq!(error("z = z; f = z"), "Only one of z and f can be set");
+        assert_eq!(
+            error("f = z; bailout = 1"),
+            "Newton's method has no bailout"
+        );
+        assert_eq!(error("z = z +"), "z = z +: Formula ends too early");
+        assert!(Script::parse("s", "z = z^2 + c")
+            .unwrap()
+            .derivative()
+            .is_none());
+    }
+
+    #[test]
+    fn escapes_like_the_mandelbrot_set() {
+        let script = Script::parse("m", "z = z^2 + c").unwrap();
+        let stack = &mut vec![];
+        // In the main cardioid, so it never gets out
+        assert_eq!(
+            script.escape(Complex::real(-0.5), 100, 4., stack),
+            (100, 4.)
+        );
+        // 0, 1, 2, then 5 which is past 2
+        let (iteration, mag) = script.escape(Complex::real(1.), 100, 4., stack);
+        assert_eq!((iteration, mag), (3, 25.));
+        // A bailout of its own replaces the radius
+        let script = Script::parse("m", "z = z^2 + c; bailout = re(z) > 1.5").unwrap();
+        assert_eq!(script.escape(Complex::real(1.), 100, 4., stack).0, 2);
+    }
+
+    #[test]
+    fn newton_settles_on_a_root() {
+        let script = Script::parse("n", "f = z^3 - 1").unwrap();
+        let stack = &mut vec![];
+        let (iteration, mag) = script.escape(Complex::new(0.9, 0.1), 100, 4., stack);
+        assert!(iteration < 10);
+        assert_eq!(mag, 4.);
+        // The derivative is 0 there, so there's no step to take
+        assert_eq!(script.escape(Complex::ZERO, 100, 4., stack), (100, 0.));
+        assert_eq!(
+            run(
+                &script.derivative().unwrap().to_string(),
+                Complex::real(2.),
+                Complex::ZERO
+            ),
+            Complex::real(12.)
+        );
+    }
+}
@@ -0,0 +1,207 @@
+// Everything needed to get back to exactly what was on screen, saved as
+// a plain text file that's easy to send around and edit by hand:
+//
+//     # newtons-fractal session
+//     x = -0.761574
+//     y = -0.0847596
+//     width = 0.00125
+//     max_iter = 1024
+//     formula = mandelbrot
+//     palette = fire
+//     angle = 0
+//     coloring = smooth
+//     mapping = flat
+//     bailout = 4
+//     palette_offset = 0
+//     palette_scale = 1
+//     window_width = 600
+//     window_height = 600
+//     window_x = 660
+//     window_y = 240
+//
+// Keys that are left out keep their defaults. The center is written with
+// every digit it has, so a deep zoom opens right where it was saved.
+use super::mapping::Mapping;
+use super::view::{Coloring, View};
+use crate::numbers::{self, positive, real};
+use std::fmt;
+use std::str::FromStr;
+
+pub const SESSION_FILE: &str = "session.txt";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Session {
+    pub view: View,
+    pub coloring: Coloring,
+    pub mapping: Mapping,
+    pub bailout: f64,
+    pub palette_offset: f64,
+    pub palette_scale: f64,
+    // Size of the main window, and where it is on screen if that's known
+    pub width: u32,
+    pub height: u32,
+    pub position: Option<(i32, i32)>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Session {
+            view: View::default(),
+            coloring: Coloring::Smooth,
+            mapping: Mapping::Flat,
+            bailout: 4.,
+            palette_offset: 0.,
+            palette_scale: 1.,
+            width: crate::MAIN_WIDTH as u32,
+            height: crate::MAIN_HEIGHT as u32,
+            position: None,
+        }
+    }
+}
+
+impl Session {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        text.parse().map_err(|e| format!("{}{}", path, e))
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        std::fs::write(path, self.to_string()).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    fn apply(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let view = &mut self.view;
+        match name {
+            "x" => view.x = value.parse()?,
+            "y" => view.y = value.parse()?,
+            "width" => view.width = positive(real(value)?)?,
+            "max_iter" => view.max_iter = positive(numbers::parse(value)?)?,
+            "formula" => view.formula = value.parse()?,
+            "palette" => view.palette = value.parse()?,
+            "angle" => view.angle = real(value)?,
+            "coloring" => self.coloring = value.parse()?,
+            "mapping" => self.mapping = value.parse()?,
+            "bailout" => self.bailout = positive(real(value)?)?,
+            "palette_offset" => self.palette_offset = real(value)?,
+            "palette_scale" => self.palette_scale = positive(real(value)?)?,
+            "window_width" => self.width = positive(numbers::parse(value)?)?,
+            "window_height" => self.height = positive(numbers::parse(value)?)?,
+            "window_x" => self.position.get_or_insert((0, 0)).0 = numbers::parse(value)?,
+            "window_y" => self.position.get_or_insert((0, 0)).1 = numbers::parse(value)?,
+            _ => return Err(format!("Unknown setting: {}", name)),
+        }
+        Ok(())
+    }
+}
+
+// Floats are written the shortest way that still reads back as
+// the same number, so nothing is lost on the way through the file
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let view = &self.view;
+        writeln!(f, "# newtons-fractal session")?;
+        writeln!(f, "x = {}", view.x)?;
+        writeln!(f, "y = {}", view.y)?;
+        writeln!(f, "width = {}", view.width)?;
+        writeln!(f, "max_iter = {}", view.max_iter)?;
+        writeln!(f, "formula = {}", view.formula)?;
+        writeln!(f, "palette = {}", view.palette)?;
+        writeln!(f, "angle = {}", view.angle)?;
+        writeln!(f, "coloring = {}", self.coloring)?;
+        writeln!(f, "mapping = {}", self.mapping)?;
+        writeln!(f, "bailout = {}", self.bailout)?;
+        writeln!(f, "palette_offset = {}", self.palette_offset)?;
+        writeln!(f, "palette_scale = {}", self.palette_scale)?;
+        writeln!(f, "window_width = {}", self.width)?;
+        writeln!(f, "window_height = {}", self.height)?;
+        if let Some((x, y)) = self.position {
+            writeln!(f, "window_x = {}", x)?;
+            writeln!(f, "window_y = {}", y)?;
+        }
+        Ok(())
+    }
+}
+
+// Errors start with the line number, so load can put the file name in front
+impl FromStr for Session {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut session = Session::default();
+        for (line_num, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = |e: String| format!(":{}: {}", line_num + 1, e);
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| err("Expected a setting like name = value".to_string()))?;
+            session.apply(name.trim(), value.trim()).map_err(err)?;
+        }
+        Ok(session)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drawing::real::{Big, Real};
+    use crate::drawing::view::{Formula, Palette};
+
+    #[test]
+    fn prints_and_parses_back_the_same() {
+        let mut session = Session {
+            coloring: Coloring::Banded,
+            mapping: Mapping::Inverse,
+            bailout: 100.,
+            palette_offset: 0.3,
+            palette_scale: 2.5,
+            width: 800,
+            height: 450,
+            position: Some((-20, 40)),
+            ..Session::default()
+        };
+        let view = &mut session.view;
+        // Digits f64 only just holds, so a rounded print would show
+        view.x = Big::from_f64(-0.743_643_887_037_158_7);
+        view.y = Big::from_f64(0.131_825_904_205_311_98);
+        view.width = 1.2345678901234567e-13;
+        view.max_iter = 5000;
+        view.formula = Formula::Julia(-0.8, 0.156);
+        view.palette = Palette::Fire;
+        view.angle = 33.3;
+        let parsed: Session = session.to_string().parse().unwrap();
+        assert_eq!(parsed, session);
+    }
+
+    #[test]
+    fn deep_zooms_keep_the_whole_center() {
+        let mut session = Session::default();
+        let view = &mut session.view;
+        // Far past what f64 can tell from -0.75 and 0.1
+        view.x = Big::from_f64(-0.75) + Big::from_f64(3e-41);
+        view.y = Big::from_f64(0.1) - Big::from_f64(7e-42);
+        view.width = 1e-40;
+        assert_eq!(view.x.to_f64(), -0.75);
+        let parsed: Session = session.to_string().parse().unwrap();
+        assert_eq!(parsed, session);
+        assert_ne!(parsed.view.x, Big::from_f64(-0.75));
+    }
+
+    #[test]
+    fn left_out_keys_keep_their_defaults() {
+        let parsed: Session = "# only the center\nx = 0.25\ny = -0.5\n".parse().unwrap();
+        let mut expected = Session::default();
+        expected.view.x = Big::from_f64(0.25);
+        expected.view.y = Big::from_f64(-0.5);
+        assert_eq!(parsed, expected);
+    }
+
+    #[test]
+    fn errors_start_with_the_line() {
+        let e = "x = 1\n\nwidth = -2".parse::<Session>().err().unwrap();
+        assert_eq!(e, ":3: Has to be more than 0, got -2");
+        let e = "zoom = 2".parse::<Session>().err().unwrap();
+        assert_eq!(e, ":1: Unknown setting: zoom");
+    }
+}
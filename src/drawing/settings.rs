@@ -0,0 +1,434 @@
+// A control panel in its own window. Every row is a slider, and moving one
+// sends a Setting event that the fractal picks up.
+use super::session::Session;
+use super::view::Formula;
+use crate::drawing::basic_render_op::{BasicOp, BasicOpImp};
+use crate::events::{MainEvent, SdlEvent, Setting};
+use crate::rendering::{CancelToken, Pixel, RenderOpReference, Tile};
+use crate::windows::{font, Window};
+use sdl2::event::EventSender;
+use std::sync::{Arc, Mutex, RwLock};
+
+pub const PANEL_WIDTH: u32 = 320;
+const ROW_HEIGHT: u32 = 44;
+const MARGIN: u32 = 12;
+// Labels are drawn with font pixels this big
+const TEXT_SCALE: u32 = 2;
+const TEXT_TOP: u32 = 6;
+const TRACK_TOP: u32 = 30;
+const TRACK_HEIGHT: u32 = 4;
+const KNOB_WIDTH: u32 = 8;
+const KNOB_HEIGHT: u32 = 14;
+
+const BACKGROUND: Pixel = (32, 32, 40, 255);
+const TRACK: Pixel = (80, 80, 96, 255);
+const FILLED: Pixel = (57, 125, 209, 255);
+const FOREGROUND: Pixel = (235, 235, 235, 255);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Control {
+    MaxIter,
+    Bailout,
+    PaletteOffset,
+    PaletteScale,
+    JuliaX,
+    JuliaY,
+    Threads,
+}
+
+const CONTROLS: [Control; 7] = [
+    Control::MaxIter,
+    Control::Bailout,
+    Control::PaletteOffset,
+    Control::PaletteScale,
+    Control::JuliaX,
+    Control::JuliaY,
+    Control::Threads,
+];
+
+impl Control {
+    fn label(self) -> &'static str {
+        match self {
+            Self::MaxIter => "Max iter",
+            Self::Bailout => "Bailout",
+            Self::PaletteOffset => "Palette offset",
+            Self::PaletteScale => "Palette scale",
+            Self::JuliaX => "Julia x",
+            Self::JuliaY => "Julia y",
+            Self::Threads => "Threads",
+        }
+    }
+
+    // Lowest and highest value, and whether the slider is logarithmic
+    fn range(self) -> (f64, f64, bool) {
+        match self {
+            Self::MaxIter => (16., 65536., true),
+            Self::Bailout => (2., 1000., true),
+            Self::PaletteOffset => (0., 1., false),
+            Self::PaletteScale => (0.1, 10., true),
+            Self::JuliaX | Self::JuliaY => (-2., 2., false),
+            Self::Threads => (1., num_cpus::get() as f64 * 4., false),
+        }
+    }
+
+    fn whole(self) -> bool {
+        matches!(self, Self::MaxIter | Self::Threads)
+    }
+
+    // Where on the track a value is, from 0 to 1
+    fn fraction(self, value: f64) -> f64 {
+        let (min, max, log) = self.range();
+        let fraction = if log {
+            // Anything under the lowest value would be NaN
+            (value.max(min) / min).ln() / (max / min).ln()
+        } else {
+            (value - min) / (max - min)
+        };
+        fraction.clamp(0., 1.)
+    }
+
+    fn value(self, fraction: f64) -> f64 {
+        let (min, max, log) = self.range();
+        let fraction = fraction.clamp(0., 1.);
+        let value = if log {
+            min * (max / min).powf(fraction)
+        } else {
+            min + (max - min) * fraction
+        };
+        if self.whole() {
+            value.round()
+        } else {
+            value
+        }
+    }
+}
+
+struct Data {
+    values: [f64; CONTROLS.len()],
+    // The row of the slider being dragged
+    dragging: Option<usize>,
+}
+
+impl Data {
+    // Moves the sliders to a value that was changed somewhere else,
+    // except the one being dragged. Returns whether any of them moved.
+    fn follow(&mut self, setting: Setting) -> bool {
+        let values = match setting {
+            Setting::MaxIter(max_iter) => vec![(Control::MaxIter, max_iter as f64)],
+            Setting::Bailout(bailout) => vec![(Control::Bailout, bailout)],
+            Setting::PaletteOffset(offset) => vec![(Control::PaletteOffset, offset)],
+            Setting::PaletteScale(scale) => vec![(Control::PaletteScale, scale)],
+            Setting::JuliaC(x, y) => vec![(Control::JuliaX, x), (Control::JuliaY, y)],
+            Setting::Threads(count) => vec![(Control::Threads, count as f64)],
+        };
+        let mut moved = false;
+        for (control, value) in values {
+            let row = Settings::row(control);
+            if self.dragging == Some(row) || self.values[row] == value {
+                continue;
+            }
+            self.values[row] = value;
+            moved = true;
+        }
+        moved
+    }
+}
+
+pub struct Settings {
+    data: Data,
+    op: BasicOp,
+    sender: EventSender,
+}
+
+impl Settings {
+    pub fn init(
+        window: Arc<Mutex<Window>>,
+        sender: EventSender,
+        start: &Session,
+        threads: usize,
+    ) -> RenderOpReference {
+        let (w, h) = window.lock().unwrap().size();
+        let (julia_x, julia_y) = match start.view.formula {
+            Formula::Julia(x, y) => (x, y),
+            _ => (-0.8, 0.156),
+        };
+        let data = Data {
+            values: [
+                start.view.max_iter as f64,
+                start.bailout,
+                start.palette_offset,
+                start.palette_scale,
+                julia_x,
+                julia_y,
+                threads as f64,
+            ],
+            dragging: None,
+        };
+        let op = BasicOp::init(Some(window), w, h, 0, 0);
+        Arc::new(RwLock::new(Box::new(Settings { data, op, sender })))
+    }
+
+    // Enough room for every row
+    pub fn height() -> u32 {
+        ROW_HEIGHT * CONTROLS.len() as u32
+    }
+
+    fn text(&self, row: usize) -> String {
+        let control = CONTROLS[row];
+        let value = self.data.values[row];
+        if control.whole() {
+            format!("{}: {}", control.label(), value)
+        } else {
+            format!("{}: {:.3}", control.label(), value)
+        }
+    }
+
+    fn knob_x(&self, row: usize) -> u32 {
+        let track = self.get_op().rect.width() - 2 * MARGIN;
+        let fraction = CONTROLS[row].fraction(self.data.values[row]);
+        MARGIN + (fraction * track as f64).round() as u32
+    }
+
+    fn pixel(&self, x: u32, y: u32, texts: &[String]) -> Pixel {
+        let row = (y / ROW_HEIGHT) as usize;
+        if row >= CONTROLS.len() {
+            return BACKGROUND;
+        }
+        let row_y = y % ROW_HEIGHT;
+        let right = self.get_op().rect.width() - MARGIN;
+        let knob_x = self.knob_x(row);
+        // Knob over the track
+        let knob_top = TRACK_TOP + TRACK_HEIGHT / 2 - KNOB_HEIGHT / 2;
+        if (knob_top..knob_top + KNOB_HEIGHT).contains(&row_y)
+            && x + KNOB_WIDTH / 2 >= knob_x
+            && x < knob_x + KNOB_WIDTH / 2
+        {
+            return FOREGROUND;
+        }
+        if (TRACK_TOP..TRACK_TOP + TRACK_HEIGHT).contains(&row_y) && (MARGIN..right).contains(&x) {
+            return if x < knob_x { FILLED } else { TRACK };
+        }
+        // Label text, a glyph is 5 pixels wide with a gap of 1
+        let text_height = font::GLYPH_HEIGHT * TEXT_SCALE;
+        if (TEXT_TOP..TEXT_TOP + text_height).contains(&row_y) && x >= MARGIN {
+            let font_x = (x - MARGIN) / TEXT_SCALE;
+            let font_y = (row_y - TEXT_TOP) / TEXT_SCALE;
+            let column = (font_x / (font::GLYPH_WIDTH + 1)) as usize;
+            let glyph_x = font_x % (font::GLYPH_WIDTH + 1);
+            if let Some(c) = texts[row].chars().nth(column) {
+                let bits = font::glyph(c)[font_y as usize];
+                if glyph_x < font::GLYPH_WIDTH
+                    && bits & (1 << (font::GLYPH_WIDTH - 1 - glyph_x)) != 0
+                {
+                    return FOREGROUND;
+                }
+            }
+        }
+        BACKGROUND
+    }
+
+    // Moves the slider in row to under the mouse. Returns whether it changed.
+    fn slide(&mut self, row: usize, mouse_x: i32) -> bool {
+        let track = (self.get_op().rect.width() - 2 * MARGIN) as f64;
+        let fraction = (mouse_x as f64 - MARGIN as f64) / track;
+        let value = CONTROLS[row].value(fraction);
+        if value == self.data.values[row] {
+            return false;
+        }
+        self.data.values[row] = value;
+        // New threads are only made once the slider is let go
+        if CONTROLS[row] != Control::Threads {
+            self.send(row);
+        }
+        true
+    }
+
+    fn row(control: Control) -> usize {
+        CONTROLS.iter().position(|&c| c == control).unwrap()
+    }
+
+    fn get(&self, control: Control) -> f64 {
+        self.data.values[Self::row(control)]
+    }
+
+    fn send(&self, row: usize) {
+        let value = self.data.values[row];
+        let setting = match CONTROLS[row] {
+            Control::MaxIter => Setting::MaxIter(value as usize),
+            Control::Bailout => Setting::Bailout(value),
+            Control::PaletteOffset => Setting::PaletteOffset(value),
+            Control::PaletteScale => Setting::PaletteScale(value),
+            Control::JuliaX | Control::JuliaY => {
+                Setting::JuliaC(self.get(Control::JuliaX), self.get(Control::JuliaY))
+            }
+            Control::Threads => Setting::Threads(value as usize),
+        };
+        self.sender
+            .push_custom_event(MainEvent::Setting(setting))
+            .unwrap();
+    }
+}
+
+impl BasicOpImp for Settings {
+    fn get_op(&self) -> &BasicOp {
+        &self.op
+    }
+    fn get_op_mut(&mut self) -> &mut BasicOp {
+        &mut self.op
+    }
+    fn draw(&self, tile: &mut Tile, _token: &CancelToken) {
+        let texts: Vec<String> = (0..CONTROLS.len()).map(|row| self.text(row)).collect();
+        let (tile_x, tile_y) = tile.position();
+        let (width, height) = tile.dimensions();
+        for y in 0..height {
+            for x in 0..width {
+                tile[(x, y)] = self.pixel((tile_x + x) as u32, (tile_y + y) as u32, &texts);
+            }
+        }
+    }
+    // Every frame is drawn in one go, and quickly,
+    // so nothing is worth cancelling one for
+    fn interrupted_by(&self, _event: &SdlEvent) -> bool {
+        false
+    }
+    fn modify_data(&mut self) {}
+    fn is_complete(&self) -> bool {
+        true
+    }
+    fn handle_events(&mut self) -> bool {
+        use sdl2::event::Event;
+        use sdl2::mouse::MouseButton;
+
+        let s = self.get_op_mut();
+        let list = s.event_list.get_mut().unwrap().split_off(0);
+        let mut ret = false;
+
+        for event in list {
+            match event {
+                SdlEvent::Event(Event::MouseButtonDown {
+                    window_id,
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                }) => {
+                    if window_id != self.get_op().id() {
+                        continue;
+                    }
+                    let row = (y.max(0) as u32 / ROW_HEIGHT) as usize;
+                    if row < CONTROLS.len() {
+                        self.data.dragging = Some(row);
+                        ret |= self.slide(row, x);
+                    }
+                }
+                SdlEvent::Event(Event::MouseMotion { window_id, x, .. }) => {
+                    if window_id != self.get_op().id() {
+                        continue;
+                    }
+                    if let Some(row) = self.data.dragging {
+                        ret |= self.slide(row, x);
+                    }
+                }
+                SdlEvent::Event(Event::MouseButtonUp {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                }) => {
+                    if let Some(row) = self.data.dragging.take() {
+                        if CONTROLS[row] == Control::Threads {
+                            self.send(row);
+                        }
+                    }
+                }
+                // The fractal's keys changed something
+                SdlEvent::User(MainEvent::Setting(setting)) => {
+                    ret |= self.data.follow(setting);
+                }
+                _ => (),
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sliders_go_from_the_lowest_to_the_highest_value() {
+        for &control in CONTROLS.iter() {
+            let (min, max, _) = control.range();
+            assert_eq!(control.value(0.), min, "{}", control.label());
+            assert_eq!(control.value(1.), max, "{}", control.label());
+            assert_eq!(control.fraction(min), 0., "{}", control.label());
+            assert!((control.fraction(max) - 1.).abs() < 1e-12);
+            // Past either end of the track is the end of the track
+            assert_eq!(control.value(-0.5), min);
+            assert_eq!(control.value(1.5), max);
+            assert_eq!(control.fraction(min - 1.), 0.);
+            assert_eq!(control.fraction(max * 2.), 1.);
+            assert_eq!(control.fraction(-1e9), 0.);
+        }
+    }
+
+    #[test]
+    fn log_sliders_double_as_often_as_linear_ones_add() {
+        // 16 to 65536 is 12 doublings, so every twelfth of the track is one
+        assert_eq!(Control::MaxIter.value(0.5), 1024.);
+        assert_eq!(Control::MaxIter.value(1. / 12.), 32.);
+        assert!((Control::MaxIter.fraction(256.) - 4. / 12.).abs() < 1e-12);
+        assert!((Control::PaletteScale.value(0.5) - 1.).abs() < 1e-12);
+        assert_eq!(Control::JuliaX.value(0.5), 0.);
+        assert_eq!(Control::JuliaY.value(0.25), -1.);
+        assert_eq!(Control::PaletteOffset.fraction(0.3), 0.3);
+        for &fraction in &[0.1, 0.37, 0.9] {
+            for &control in &[Control::Bailout, Control::PaletteScale, Control::JuliaX] {
+                let back = control.fraction(control.value(fraction));
+                assert!((back - fraction).abs() < 1e-12, "{}", control.label());
+            }
+        }
+    }
+
+    #[test]
+    fn whole_values_are_rounded() {
+        // 16 * 2^(12 * 0.3) is 194.0..., and 16 * 2^(12 * 0.31) is 210.8...
+        assert_eq!(Control::MaxIter.value(0.3), 194.);
+        assert_eq!(Control::MaxIter.value(0.31), 211.);
+        for i in 0..=100 {
+            let fraction = i as f64 / 100.;
+            for &control in &[Control::MaxIter, Control::Threads] {
+                let value = control.value(fraction);
+                assert_eq!(value, value.round(), "{}", control.label());
+            }
+        }
+        // Other sliders keep their fractions
+        let bailout = Control::Bailout.value(0.3);
+        assert_ne!(bailout, bailout.round());
+    }
+
+    #[test]
+    fn sliders_follow_changes_except_the_one_being_dragged() {
+        let mut data = Data {
+            values: [1000., 4., 0., 1., -0.8, 0.156, 4.],
+            dragging: None,
+        };
+        assert!(data.follow(Setting::MaxIter(2000)));
+        assert_eq!(data.values[0], 2000.);
+        // Nothing moved if the value is already there
+        assert!(!data.follow(Setting::MaxIter(2000)));
+        assert!(data.follow(Setting::JuliaC(0.3, 0.5)));
+        assert_eq!(&data.values[4..6], &[0.3, 0.5]);
+
+        // The row under the mouse keeps where it was dragged to,
+        // and the rest of a change still goes through
+        data.dragging = Some(Settings::row(Control::JuliaX));
+        assert!(data.follow(Setting::JuliaC(-1., 1.)));
+        assert_eq!(&data.values[4..6], &[0.3, 1.]);
+        assert!(!data.follow(Setting::JuliaC(-1., 1.)));
+        assert!(data.follow(Setting::Bailout(16.)));
+        assert_eq!(data.values[1], 16.);
+        data.dragging = Some(Settings::row(Control::Bailout));
+        assert!(!data.follow(Setting::Bailout(32.)));
+        assert_eq!(data.values[1], 16.);
+    }
+}
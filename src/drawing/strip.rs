@@ -0,0 +1,180 @@
+// Zoom videos from a single picture. The exponential mapping lays a whole
+// zoom out as a tall strip, where every row down zooms in by the same
+// amount. A frame of the video is then only a lookup into the strip, so
+// the whole video costs about as much as rendering a few frames of it.
+use super::mapping::Mapping;
+use super::session::Session;
+use crate::image;
+use crate::rendering::Pixel;
+use std::f64::consts::PI;
+use std::path::Path;
+
+// Width of the view in the first frame, so it starts on the whole set
+const START_WIDTH: f64 = 4.;
+// Rows rendered at a time, as a view of their own
+const BAND_ROWS: u32 = 256;
+
+// Turns a session for the last frame of a video into one for the strip
+// the video can be made from. The size of the session is the frame size.
+pub fn layout(session: &mut Session) -> Result<(), String> {
+    let (frame_width, frame_height) = (session.width as f64, session.height as f64);
+    let end_width = session.view.width;
+    if end_width >= START_WIDTH {
+        return Err(format!(
+            "A zoom video needs a view narrower than {}, got {}",
+            START_WIDTH, end_width
+        ));
+    }
+    // The corners of a frame are as far out as it goes, and the strip
+    // needs a pixel for every pixel of the frame around that circle
+    let corner = frame_width.hypot(frame_height) / 2.;
+    let strip_width = (2. * PI * corner).ceil();
+    let rows = |zoom: f64| strip_width / (2. * PI) * zoom.ln();
+    // From the corners of the first frame down to the ones of the
+    // last, and then on to the pixel in the middle of the last
+    let needed = rows(START_WIDTH / end_width) + rows(2. * corner);
+    // Rows only come whole, so the first frame starts out a little
+    // wider to put the last one exactly where it was asked for
+    let strip_height = needed.ceil() + 1.;
+    let wider = ((strip_height - 1. - needed) * 2. * PI / strip_width).exp();
+    session.view.width = 2. * corner * START_WIDTH / frame_width * wider;
+    session.mapping = Mapping::Exponential;
+    session.width = strip_width as u32;
+    session.height = strip_height as u32;
+    Ok(())
+}
+
+// Renders the strip laid out in session a band of rows at a time, and
+// writes every band straight to the ppm at path, so a strip far taller
+// than fits in memory is never held whole. render gives the pixels of
+// the session it's handed.
+pub fn render(
+    session: &Session,
+    path: &str,
+    mut render: impl FnMut(&Session) -> Result<Vec<Pixel>, String>,
+) -> Result<(), String> {
+    let (width, height) = (session.width, session.height);
+    let mut file = image::PpmWriter::create(path, width as usize, height as usize)?;
+    for first in (0..height).step_by(BAND_ROWS as usize) {
+        let rows = BAND_ROWS.min(height - first);
+        let pixels = render(&band(session, first, rows))?;
+        file.write_rows(&pixels[..(width * rows) as usize])?;
+        println!("Rendered {} of {} rows", first + rows, height);
+    }
+    file.finish()
+}
+
+// Every row of the strip zooms in by the same amount, so the rows from
+// first on are the top of the same strip zoomed in that much further
+fn band(session: &Session, first: u32, rows: u32) -> Session {
+    let mut band = session.clone();
+    band.view.width *= (-2. * PI * first as f64 / session.width as f64).exp();
+    band.height = rows;
+    band
+}
+
+// Writes width by height frames zooming down the strip at a steady speed,
+// as name-00000.ppm, name-00001.ppm and on next to the strip
+pub fn video(path: &str, frames: usize, width: usize, height: usize) -> Result<(), String> {
+    let (strip_width, strip_height, strip) = image::load_ppm(path)?;
+    let rows_per_e = strip_width as f64 / (2. * PI);
+    let corner = (width as f64).hypot(height as f64) / 2.;
+    // Row the corners of the last frame are on, so its middle is the last row
+    let last = (strip_height - 1) as f64 - rows_per_e * (2. * corner).ln();
+    if last < 0. {
+        return Err(format!(
+            "{} is too short for {}x{} frames",
+            path, width, height
+        ));
+    }
+    let stem = Path::new(path).with_extension("");
+    let stem = stem.display();
+    let mut pixels = vec![(0, 0, 0, 255); width * height];
+    for frame in 0..frames {
+        let edge = if frames > 1 {
+            last * frame as f64 / (frames - 1) as f64
+        } else {
+            last
+        };
+        for y in 0..height {
+            for x in 0..width {
+                // Pixels are sampled at their corner, like when they're rendered
+                let dx = x as f64 - width as f64 / 2.;
+                let dy = y as f64 - height as f64 / 2.;
+                // The angle goes across the strip, starting on the left
+                let u = strip_width as f64 * (0.5 + dy.atan2(dx) / (2. * PI));
+                let v = edge + rows_per_e * (corner / dx.hypot(dy)).ln();
+                pixels[y * width + x] = sample(&strip, strip_width, strip_height, u, v);
+            }
+        }
+        let name = format!("{}-{:05}.ppm", stem, frame);
+        image::save(&name, width, height, &pixels)?;
+    }
+    println!("Wrote {} frames as {}-00000.ppm and on", frames, stem);
+    Ok(())
+}
+
+// Blends the four pixels around (u, v). The strip goes all
+// the way around the center, so it wraps at the sides.
+fn sample(strip: &[Pixel], width: usize, height: usize, u: f64, v: f64) -> Pixel {
+    let v = v.clamp(0., (height - 1) as f64);
+    let (left, top) = (u.floor(), v.floor());
+    let (across, down) = (u - left, v - top);
+    let x0 = (left as isize).rem_euclid(width as isize) as usize;
+    let x1 = (x0 + 1) % width;
+    let y0 = top as usize;
+    let y1 = (y0 + 1).min(height - 1);
+    let corners = [
+        strip[y0 * width + x0],
+        strip[y0 * width + x1],
+        strip[y1 * width + x0],
+        strip[y1 * width + x1],
+    ];
+    let mix = |channel: fn(Pixel) -> u8| {
+        let [a, b, c, d] = corners.map(|p| channel(p) as f64);
+        let upper = a + (b - a) * across;
+        let lower = c + (d - c) * across;
+        (upper + (lower - upper) * down).round() as u8
+    };
+    (mix(|p| p.0), mix(|p| p.1), mix(|p| p.2), 255)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Where the mapping puts a row's pixel, the way Mandelbrot samples it
+    fn point(session: &Session, x: u32, row: u32) -> (f64, f64) {
+        let (width, height) = (session.width as f64, session.height as f64);
+        let ratio = session.view.width / width;
+        Mapping::Exponential
+            .map(
+                (x as f64 - width / 2.) * ratio,
+                (row as f64 - height / 2.) * ratio,
+                session.view.width,
+                session.view.width * height / width,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn bands_line_up_with_the_whole_strip() {
+        let mut session = Session {
+            width: 320,
+            height: 180,
+            ..Session::default()
+        };
+        session.view.width = 1e-6;
+        layout(&mut session).unwrap();
+        for &(first, rows) in &[(0, 256), (256, 256), (session.height - 10, 10)] {
+            let band = band(&session, first, rows);
+            assert_eq!((band.width, band.height), (session.width, rows));
+            for &(x, row) in &[(0, 0), (17, 3), (session.width - 1, rows - 1)] {
+                let (a, b) = point(&session, x, first + row);
+                let (c, d) = point(&band, x, row);
+                let size = a.hypot(b);
+                assert!((a - c).abs() < 1e-9 * size && (b - d).abs() < 1e-9 * size);
+            }
+        }
+    }
+}
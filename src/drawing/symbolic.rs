@@ -0,0 +1,294 @@
+// Derivatives of formula trees, worked out symbolically so the result is
+// a formula too and can be compiled like any other. Newton's method takes
+// d/dz of f, and anything else that wants d/dz or d/dc can use the same.
+// Escape time scripts don't track dz/dc along the orbit yet, since there's
+// no distance estimation coloring to use it. When there is, it's the
+// derivatives of z = ... by both z and c, chained every step.
+//
+// The rules make a lot of dead weight like 0 * x and x ^ 1, so the result
+// goes through simplify, which can also be used on its own.
+use super::complex::Complex;
+use super::script::{BinOp, Expr, Func};
+
+// wrt is the variable, Expr::Z or Expr::C, and the other one is held fixed
+pub fn derivative(expr: &Expr, wrt: &Expr) -> Result<Expr, String> {
+    Ok(simplify(&differentiate(expr, wrt)?))
+}
+
+fn num(x: f64) -> Expr {
+    Expr::Num(Complex::real(x))
+}
+
+fn neg(a: Expr) -> Expr {
+    Expr::Neg(Box::new(a))
+}
+
+fn contains(expr: &Expr, part: &Expr) -> bool {
+    if expr == part {
+        return true;
+    }
+    match expr {
+        Expr::Num(_) | Expr::Z | Expr::C => false,
+        Expr::Neg(a) | Expr::Call(_, a) => contains(a, part),
+        Expr::Binary(_, a, b) => contains(a, part) || contains(b, part),
+    }
+}
+
+fn differentiate(expr: &Expr, wrt: &Expr) -> Result<Expr, String> {
+    use BinOp::*;
+    let d = |e: &Expr| differentiate(e, wrt);
+    Ok(match expr {
+        Expr::Z | Expr::C if expr == wrt => num(1.),
+        Expr::Num(_) | Expr::Z | Expr::C => num(0.),
+        Expr::Neg(a) => neg(d(a)?),
+        Expr::Binary(op, a, b) => {
+            let (a, b) = (&**a, &**b);
+            match op {
+                Add | Sub => Expr::binary(*op, d(a)?, d(b)?),
+                Mul => Expr::binary(
+                    Add,
+                    Expr::binary(Mul, d(a)?, b.clone()),
+                    Expr::binary(Mul, a.clone(), d(b)?),
+                ),
+                Div => Expr::binary(
+                    Div,
+                    Expr::binary(
+                        Sub,
+                        Expr::binary(Mul, d(a)?, b.clone()),
+                        Expr::binary(Mul, a.clone(), d(b)?),
+                    ),
+                    Expr::binary(Pow, b.clone(), num(2.)),
+                ),
+                // a^n has the usual rule when n is held fixed
+                Pow if !contains(b, wrt) => Expr::binary(
+                    Mul,
+                    Expr::binary(
+                        Mul,
+                        b.clone(),
+                        Expr::binary(Pow, a.clone(), Expr::binary(Sub, b.clone(), num(1.))),
+                    ),
+                    d(a)?,
+                ),
+                // otherwise (a^b)' = a^b * (b' log a + b a' / a)
+                Pow => Expr::binary(
+                    Mul,
+                    expr.clone(),
+                    Expr::binary(
+                        Add,
+                        Expr::binary(Mul, d(b)?, Expr::call(Func::Log, a.clone())),
+                        Expr::binary(Div, Expr::binary(Mul, b.clone(), d(a)?), a.clone()),
+                    ),
+                ),
+                Less | Greater | LessEq | GreaterEq => {
+                    return Err("Comparisons can't be differentiated".to_string())
+                }
+            }
+        }
+        Expr::Call(func, a) => {
+            let a = &**a;
+            let outer = match func {
+                Func::Sin => Expr::call(Func::Cos, a.clone()),
+                Func::Cos => neg(Expr::call(Func::Sin, a.clone())),
+                Func::Tan => Expr::binary(
+                    Div,
+                    num(1.),
+                    Expr::binary(BinOp::Pow, Expr::call(Func::Cos, a.clone()), num(2.)),
+                ),
+                Func::Sinh => Expr::call(Func::Cosh, a.clone()),
+                Func::Cosh => Expr::call(Func::Sinh, a.clone()),
+                Func::Exp => expr.clone(),
+                Func::Log => Expr::binary(Div, num(1.), a.clone()),
+                Func::Sqrt => Expr::binary(Div, num(0.5), expr.clone()),
+                // None of these are analytic
+                Func::Abs | Func::Conj | Func::Re | Func::Im => {
+                    return Err(format!("{} can't be differentiated", func.name()))
+                }
+            };
+            Expr::binary(Mul, outer, d(a)?)
+        }
+    })
+}
+
+// No rule makes the tree bigger, but some only move parts around, like
+// constants going to the front of a product, so its size alone doesn't
+// prove the passes settle. Derivatives of real formulas take two or three.
+// The cap is there in case two rules ever undo each other, and stopping
+// early still leaves a correct tree, only an untidy one.
+const MAX_PASSES: usize = 32;
+
+// Rewrites the tree bottom up until nothing changes. Constants are folded,
+// identities like x + 0, x * 1 and x ^ 1 are dropped, constant factors are
+// gathered at the front of products, and repeats like x * x become powers.
+pub fn simplify(expr: &Expr) -> Expr {
+    let mut expr = expr.clone();
+    for _ in 0..MAX_PASSES {
+        let next = simplify_once(&expr);
+        if next == expr {
+            break;
+        }
+        expr = next;
+    }
+    expr
+}
+
+fn simplify_once(expr: &Expr) -> Expr {
+    use BinOp::*;
+    let is = |e: &Expr, x: f64| *e == num(x);
+    match expr {
+        Expr::Num(_) | Expr::Z | Expr::C => expr.clone(),
+        Expr::Neg(a) => match simplify_once(a) {
+            // Subtracting keeps -0 out
+            Expr::Num(x) => Expr::Num(Complex::ZERO - x),
+            Expr::Neg(b) => *b,
+            Expr::Binary(Mul, b, c) if matches!(*b, Expr::Num(_)) => Expr::binary(Mul, neg(*b), *c),
+            a => neg(a),
+        },
+        Expr::Call(func, a) => match simplify_once(a) {
+            Expr::Num(x) => Expr::Num(func.apply(x)),
+            a => Expr::call(*func, a),
+        },
+        Expr::Binary(op, a, b) => {
+            let (a, b) = (simplify_once(a), simplify_once(b));
+            if let (Expr::Num(x), Expr::Num(y)) = (&a, &b) {
+                return Expr::Num(op.apply(*x, *y));
+            }
+            match (op, a, b) {
+                (Add, a, b) if is(&a, 0.) => b,
+                (Add | Sub, a, b) if is(&b, 0.) => a,
+                (Sub, a, b) if is(&a, 0.) => neg(b),
+                (Sub, a, b) if a == b => num(0.),
+                (Add, a, Expr::Neg(b)) => Expr::binary(Sub, a, *b),
+                (Sub, a, Expr::Neg(b)) => Expr::binary(Add, a, *b),
+                (Add, a, b) if a == b => Expr::binary(Mul, num(2.), a),
+                (Mul, a, b) if is(&a, 0.) || is(&b, 0.) => num(0.),
+                (Mul, a, b) if is(&a, 1.) => b,
+                (Mul | Div, a, b) if is(&b, 1.) => a,
+                (Mul, a, b) if is(&a, -1.) => neg(b),
+                (Mul, Expr::Neg(a), b) => neg(Expr::binary(Mul, *a, b)),
+                (Mul, a, Expr::Neg(b)) => neg(Expr::binary(Mul, a, *b)),
+                // Constants go in front, and join up with a constant already there
+                (Mul, a, b @ Expr::Num(_)) => Expr::binary(Mul, b, a),
+                (Mul, Expr::Num(x), Expr::Binary(Mul, b, c)) => match *b {
+                    Expr::Num(y) => Expr::binary(Mul, Expr::Num(x * y), *c),
+                    b => Expr::binary(Mul, Expr::Num(x), Expr::binary(Mul, b, *c)),
+                },
+                (Mul, a, b) if a == b => Expr::binary(Pow, a, num(2.)),
+                (Div, a, _) if is(&a, 0.) => num(0.),
+                (Div, a, b) if a == b => num(1.),
+                (Pow, _, b) if is(&b, 0.) => num(1.),
+                (Pow, a, b) if is(&b, 1.) => a,
+                (Pow, a, _) if is(&a, 1.) => num(1.),
+                // (x^a)^b is only x^(ab) for sure when both are whole numbers
+                (Pow, Expr::Binary(Pow, x, a), b) if whole(&a) && whole(&b) => {
+                    Expr::binary(Pow, *x, Expr::binary(Mul, *a, b))
+                }
+                (op, a, b) => Expr::binary(*op, a, b),
+            }
+        }
+    }
+}
+
+fn whole(expr: &Expr) -> bool {
+    matches!(expr, Expr::Num(x) if x.im == 0. && x.re.fract() == 0.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drawing::script::{parse, Program};
+
+    fn d(text: &str) -> Expr {
+        derivative(&parse(text).unwrap(), &Expr::Z).unwrap()
+    }
+
+    fn simplified(text: &str) -> String {
+        simplify(&parse(text).unwrap()).to_string()
+    }
+
+    #[test]
+    fn differentiates_the_usual_functions() {
+        assert_eq!(d("z^3 - 1").to_string(), "3*z^2");
+        assert_eq!(d("sin(z)").to_string(), "cos(z)");
+        assert_eq!(d("exp(z)").to_string(), "exp(z)");
+        assert_eq!(d("sin(z^2)").to_string(), "cos(z^2)*(2*z)");
+        assert_eq!(d("z^z").to_string(), "z^z*(log(z) + 1)");
+        // c is held fixed
+        assert_eq!(d("c*z + c^2").to_string(), "c");
+        assert_eq!(
+            derivative(&parse("c*z + c^2").unwrap(), &Expr::C)
+                .unwrap()
+                .to_string(),
+            "z + 2*c"
+        );
+    }
+
+    #[test]
+    fn agrees_with_finite_differences() {
+        let h = 1e-6;
+        let c = Complex::new(-0.4, 0.6);
+        let stack = &mut vec![];
+        for text in [
+            "z^3 - 1",
+            "sin(z^2)",
+            "z^z",
+            "tan(z)/z",
+            "sqrt(c*z) - cosh(z)",
+            "log(z^2 + c)*sinh(z)",
+            "exp(sin(z^3*c))/(z^2 - c)^-3",
+            "z^2.5 + c^z",
+        ]
+        .iter()
+        {
+            let f = Program::compile(&parse(text).unwrap());
+            let derivative = Program::compile(&d(text));
+            for &z in &[Complex::new(0.7, 0.2), Complex::new(-1.3, 0.9)] {
+                let step = Complex::real(h);
+                let estimate =
+                    (f.run(z + step, c, stack) - f.run(z - step, c, stack)) / Complex::real(2. * h);
+                let exact = derivative.run(z, c, stack);
+                assert!(
+                    (exact - estimate).abs() < 1e-6 * exact.abs().max(1.),
+                    "{} at {:?}: {:?} vs {:?}",
+                    text,
+                    z,
+                    exact,
+                    estimate
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn turns_down_what_has_no_derivative() {
+        let error = |text| derivative(&parse(text).unwrap(), &Expr::Z).unwrap_err();
+        assert_eq!(error("|z|"), "abs can't be differentiated");
+        assert_eq!(error("conj(z) + 1"), "conj can't be differentiated");
+        assert_eq!(error("z > 1"), "Comparisons can't be differentiated");
+    }
+
+    #[test]
+    fn drops_identities_and_folds_constants() {
+        assert_eq!(simplified("z*1"), "z");
+        assert_eq!(simplified("1*z"), "z");
+        assert_eq!(simplified("0*z"), "0");
+        assert_eq!(simplified("z*0"), "0");
+        assert_eq!(simplified("z + 0 - 0"), "z");
+        assert_eq!(simplified("0 - z"), "-z");
+        assert_eq!(simplified("z - z"), "0");
+        assert_eq!(simplified("z/z + z^0"), "2");
+        assert_eq!(simplified("z^1/1"), "z");
+        assert_eq!(simplified("--z"), "z");
+        assert_eq!(simplified("2*3 + sin(0)"), "6");
+    }
+
+    #[test]
+    fn gathers_constants_and_repeats() {
+        assert_eq!(simplified("z*3*2"), "6*z");
+        assert_eq!(simplified("2*-z"), "-2*z");
+        assert_eq!(simplified("z + z"), "2*z");
+        assert_eq!(simplified("z*z"), "z^2");
+        assert_eq!(simplified("(z^2)^3"), "z^6");
+        // Not the same as z for z with a negative real part
+        assert_eq!(simplified("(z^0.5)^2"), "(z^0.5)^2");
+    }
+}
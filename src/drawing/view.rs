@@ -0,0 +1,452 @@
+// Everything that decides what a frame looks like, apart from the
+// window size, along with saving views to and loading them from text.
+use super::real::{Big, Real};
+use super::script::{self, Script};
+use crate::numbers::{self, positive, real};
+use std::fmt;
+use std::str::FromStr;
+
+const BOOKMARK_FILE: &str = "bookmarks.txt";
+pub const BOOKMARK_SLOTS: usize = 9;
+
+// Interesting points to start out with as bookmarks 1 and up
+const SEEDS: [(f64, f64); 6] = [
+    (0.001643721971153, -0.822467633298876),
+    (-0.761574, -0.0847596),
+    (-std::f64::consts::E / 7., -std::f64::consts::E / 20.),
+    (-0.10715079727776, -0.91210278793461),
+    (-1.74790375491685, 0.00194820459426),
+    (-0.52303294558693, 0.52633610977926),
+];
+
+// The iterated function
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Formula {
+    Mandelbrot,
+    // Takes the absolute value of both parts of z before squaring
+    BurningShip,
+    // Squares the conjugate of z
+    Tricorn,
+    // z starts at the point and c stays fixed
+    Julia(f64, f64),
+    // One from the formula file
+    Custom(&'static Script),
+}
+
+impl Formula {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Mandelbrot => Self::BurningShip,
+            Self::BurningShip => Self::Tricorn,
+            Self::Tricorn => Self::Julia(-0.8, 0.156),
+            Self::Julia(..) => Self::custom_after(0),
+            Self::Custom(current) => {
+                let library = script::library();
+                let pos = library.iter().position(|s| std::ptr::eq(s, current));
+                Self::custom_after(pos.unwrap() + 1)
+            }
+        }
+    }
+
+    // The custom formula at pos, going back to the start after the last one
+    fn custom_after(pos: usize) -> Self {
+        script::library()
+            .get(pos)
+            .map_or(Self::Mandelbrot, Self::Custom)
+    }
+}
+
+impl fmt::Display for Formula {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Mandelbrot => write!(f, "mandelbrot"),
+            Self::BurningShip => write!(f, "burning-ship"),
+            Self::Tricorn => write!(f, "tricorn"),
+            Self::Julia(x, y) => write!(f, "julia:{},{}", x, y),
+            Self::Custom(script) => write!(f, "custom:{}", script.name),
+        }
+    }
+}
+
+impl FromStr for Formula {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "mandelbrot" => Ok(Self::Mandelbrot),
+            "burning-ship" => Ok(Self::BurningShip),
+            "tricorn" => Ok(Self::Tricorn),
+            _ if s.starts_with("custom:") => {
+                let name = &s["custom:".len()..];
+                script::find(name)
+                    .map(Self::Custom)
+                    .ok_or(format!("No formula named {} in the formula file", name))
+            }
+            _ => {
+                let param = s
+                    .strip_prefix("julia:")
+                    .ok_or(format!("Unknown formula: {}", s))?;
+                let (x, y) = param
+                    .split_once(',')
+                    .ok_or(format!("Julia needs a point like julia:x,y, got {}", s))?;
+                Ok(Self::Julia(real(x)?, real(y)?))
+            }
+        }
+    }
+}
+
+// The colors cycled through as the escape time goes up
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Palette {
+    Classic,
+    Fire,
+    Ice,
+    Gray,
+}
+
+impl Palette {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Classic => Self::Fire,
+            Self::Fire => Self::Ice,
+            Self::Ice => Self::Gray,
+            Self::Gray => Self::Classic,
+        }
+    }
+
+    pub fn colors(self) -> &'static [(u8, u8, u8)] {
+        match self {
+            Self::Classic => &[
+                (66, 30, 15),
+                (25, 7, 26),
+                (9, 1, 47),
+                (4, 4, 73),
+                (0, 7, 100),
+                (12, 44, 138),
+                (24, 82, 177),
+                (57, 125, 209),
+                (134, 181, 229),
+                (211, 236, 248),
+                (241, 233, 191),
+                (248, 201, 95),
+                (255, 170, 0),
+                (204, 128, 0),
+                (153, 87, 0),
+                (106, 52, 3),
+            ],
+            Self::Fire => &[
+                (20, 0, 0),
+                (120, 10, 0),
+                (220, 60, 0),
+                (255, 160, 20),
+                (255, 240, 150),
+                (255, 160, 20),
+                (220, 60, 0),
+                (120, 10, 0),
+            ],
+            Self::Ice => &[
+                (0, 10, 30),
+                (0, 50, 110),
+                (40, 130, 200),
+                (180, 230, 250),
+                (255, 255, 255),
+                (180, 230, 250),
+                (40, 130, 200),
+                (0, 50, 110),
+            ],
+            Self::Gray => &[(30, 30, 30), (225, 225, 225)],
+        }
+    }
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Classic => "classic",
+            Self::Fire => "fire",
+            Self::Ice => "ice",
+            Self::Gray => "gray",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "classic" => Ok(Self::Classic),
+            "fire" => Ok(Self::Fire),
+            "ice" => Ok(Self::Ice),
+            "gray" => Ok(Self::Gray),
+            _ => Err(format!("Unknown palette: {}", s)),
+        }
+    }
+}
+
+// How the escape time is turned into a spot in the palette
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Coloring {
+    // Blends between colors using how far past the bailout the point got
+    Smooth,
+    // One flat color for every iteration count
+    Banded,
+}
+
+impl Coloring {
+    pub fn next(self) -> Self {
+        match self {
+            Self::Smooth => Self::Banded,
+            Self::Banded => Self::Smooth,
+        }
+    }
+}
+
+impl fmt::Display for Coloring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Smooth => write!(f, "smooth"),
+            Self::Banded => write!(f, "banded"),
+        }
+    }
+}
+
+impl FromStr for Coloring {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "smooth" => Ok(Self::Smooth),
+            "banded" => Ok(Self::Banded),
+            _ => Err(format!("Unknown coloring: {}", s)),
+        }
+    }
+}
+
+// A spot in the fractal, and how it's drawn. The height of the
+// view follows from width and the shape of the window.
+#[derive(Clone, Debug, PartialEq)]
+pub struct View {
+    // The center, to every digit it was zoomed in to
+    pub x: Big,
+    pub y: Big,
+    pub width: f64,
+    pub max_iter: usize,
+    pub formula: Formula,
+    pub palette: Palette,
+    // Degrees the view is turned counterclockwise around its center
+    pub angle: f64,
+}
+
+impl View {
+    fn seed(x: f64, y: f64) -> Self {
+        View {
+            x: Big::from_f64(x),
+            y: Big::from_f64(y),
+            width: 1.,
+            max_iter: 1 << 8,
+            formula: Formula::Mandelbrot,
+            palette: Palette::Classic,
+            angle: 0.,
+        }
+    }
+}
+
+impl Default for View {
+    fn default() -> Self {
+        let (x, y) = SEEDS[0];
+        Self::seed(x, y)
+    }
+}
+
+// Written as "x y width max_iter formula palette angle"
+impl fmt::Display for View {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} {} {} {} {}",
+            self.x, self.y, self.width, self.max_iter, self.formula, self.palette, self.angle
+        )
+    }
+}
+
+impl FromStr for View {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        // Views from before rotation have no angle
+        if parts.len() != 6 && parts.len() != 7 {
+            return Err(format!(
+                "Expected 6 or 7 values in a view, got {}",
+                parts.len()
+            ));
+        }
+        Ok(View {
+            x: parts[0].parse()?,
+            y: parts[1].parse()?,
+            width: positive(real(parts[2])?)?,
+            max_iter: positive(numbers::parse(parts[3])?)?,
+            formula: parts[4].parse()?,
+            palette: parts[5].parse()?,
+            angle: parts.get(6).map_or(Ok(0.), |angle| real(angle))?,
+        })
+    }
+}
+
+pub type Bookmarks = [Option<View>; BOOKMARK_SLOTS];
+
+// Reads the bookmark file. If there's no file yet, the slots
+// start out with the seed points.
+pub fn load_bookmarks() -> Result<Bookmarks, String> {
+    match std::fs::read_to_string(BOOKMARK_FILE) {
+        Ok(text) => parse_bookmarks(&text).map_err(|e| format!("{}{}", BOOKMARK_FILE, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            let mut bookmarks = Bookmarks::default();
+            for (slot, &(x, y)) in SEEDS.iter().enumerate() {
+                bookmarks[slot] = Some(View::seed(x, y));
+            }
+            Ok(bookmarks)
+        }
+        Err(e) => Err(format!("{}: {}", BOOKMARK_FILE, e)),
+    }
+}
+
+pub fn save_bookmarks(bookmarks: &Bookmarks) -> Result<(), String> {
+    std::fs::write(BOOKMARK_FILE, bookmarks_text(bookmarks))
+        .map_err(|e| format!("{}: {}", BOOKMARK_FILE, e))
+}
+
+// Every line is "slot view", for the slots that have a view. Errors start
+// with the line number, so load_bookmarks can put the file name in front.
+fn parse_bookmarks(text: &str) -> Result<Bookmarks, String> {
+    let mut bookmarks = Bookmarks::default();
+    for (line_num, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let err = |e: String| format!(":{}: {}", line_num + 1, e);
+        let (slot, view) = line
+            .split_once(' ')
+            .ok_or_else(|| err("Expected a slot and a view".to_string()))?;
+        let slot: usize = numbers::parse(slot).map_err(err)?;
+        if !(1..=BOOKMARK_SLOTS).contains(&slot) {
+            return Err(err(format!(
+                "Slot {} isn't in 1 to {}",
+                slot, BOOKMARK_SLOTS
+            )));
+        }
+        bookmarks[slot - 1] = Some(view.parse().map_err(err)?);
+    }
+    Ok(bookmarks)
+}
+
+fn bookmarks_text(bookmarks: &Bookmarks) -> String {
+    let mut text = String::new();
+    for (slot, view) in bookmarks.iter().enumerate() {
+        if let Some(view) = view {
+            text += &format!("{} {}\n", slot + 1, view);
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deep_centers_keep_every_digit() {
+        let view = View {
+            x: "-1.7499999999999999999999999999999999999999123"
+                .parse()
+                .unwrap(),
+            width: 1e-40,
+            ..View::default()
+        };
+        let parsed: View = view.to_string().parse().unwrap();
+        assert_eq!(parsed, view);
+        assert_ne!(parsed.x, Big::from_f64(-1.75));
+    }
+
+    #[test]
+    fn views_from_before_rotation_have_no_angle() {
+        let view: View = "-0.5 0 3 100 tricorn fire".parse().unwrap();
+        assert_eq!(view.angle, 0.);
+        assert_eq!(view.formula, Formula::Tricorn);
+        let view: View = "-0.5 0 3 100 julia:-0.8,0.156 gray 45".parse().unwrap();
+        assert_eq!(view.formula, Formula::Julia(-0.8, 0.156));
+        assert_eq!(view.angle, 45.);
+    }
+
+    #[test]
+    fn views_turn_down_numbers_that_cant_be() {
+        let e = |s: &str| s.parse::<View>().err().unwrap();
+        assert_eq!(
+            e("0 0 1 100 mandelbrot"),
+            "Expected 6 or 7 values in a view, got 5"
+        );
+        assert_eq!(
+            e("0 0 0 100 mandelbrot classic"),
+            "Has to be more than 0, got 0"
+        );
+        assert_eq!(
+            e("0 0 -1 100 mandelbrot classic"),
+            "Has to be more than 0, got -1"
+        );
+        assert_eq!(
+            e("0 0 inf 100 mandelbrot classic"),
+            "Not a finite number: inf"
+        );
+        assert_eq!(
+            e("0 0 NaN 100 mandelbrot classic"),
+            "Not a finite number: NaN"
+        );
+        assert_eq!(
+            e("inf 0 1 100 mandelbrot classic"),
+            "Not a valid number: inf"
+        );
+        assert_eq!(
+            e("0 0 1 0 mandelbrot classic"),
+            "Has to be more than 0, got 0"
+        );
+        assert_eq!(
+            e("0 0 1 100 mandelbrot classic NaN"),
+            "Not a finite number: NaN"
+        );
+        assert_eq!(
+            e("0 0 1 100 julia:inf,0 classic"),
+            "Not a finite number: inf"
+        );
+    }
+
+    #[test]
+    fn bookmarks_read_back_what_was_written() {
+        let mut bookmarks = Bookmarks::default();
+        bookmarks[0] = Some(View::default());
+        bookmarks[4] = Some(View {
+            x: "0.2500000000000000000000000000000000001".parse().unwrap(),
+            width: 1e-35,
+            formula: Formula::BurningShip,
+            palette: Palette::Ice,
+            angle: -30.,
+            ..View::default()
+        });
+        let text = bookmarks_text(&bookmarks);
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.starts_with("1 "));
+        assert_eq!(parse_bookmarks(&text).unwrap(), bookmarks);
+    }
+
+    #[test]
+    fn bookmark_errors_start_with_the_line() {
+        let view = View::default().to_string();
+        let e = |s: &str| parse_bookmarks(s).err().unwrap();
+        assert_eq!(e(&format!("\n10 {}", view)), ":2: Slot 10 isn't in 1 to 9");
+        assert_eq!(e(&format!("0 {}", view)), ":1: Slot 0 isn't in 1 to 9");
+        assert_eq!(e("3"), ":1: Expected a slot and a view");
+        assert_eq!(
+            e("x 0 0 1 1 mandelbrot classic"),
+            ":1: Not a valid number: x"
+        );
+        assert_eq!(e("1 0 0 1 1 mandelbrot pink"), ":1: Unknown palette: pink");
+    }
+}
@@ -0,0 +1,382 @@
+// The part of a plane the window shows, for the ops that need no more than
+// a center, a width and an even scale: the Buddhabrot and Lyapunov ops.
+// They also share their keys, wheel and resizing through ViewportOp, and
+// only say what they draw and what the formula key does.
+use super::basic_render_op::BasicOpImp;
+use super::bindings::{Action, Bindings};
+use crate::events::{MainEvent, SdlEvent, Setting};
+use crate::rendering::Pixels;
+use sdl2::rect::Rect;
+
+// How much one wheel tick zooms
+const SCALE_WHEEL: f64 = 1.25;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    // Size of the window in pixels
+    pub pixel_width: u32,
+    pub pixel_height: u32,
+    // Whether y grows going up the screen. The complex plane has it
+    // going down, the same as the escape time ops draw it.
+    pub y_up: bool,
+}
+
+impl Viewport {
+    // Size of a pixel on the plane
+    pub fn ratio(&self) -> f64 {
+        self.width / self.pixel_width as f64
+    }
+
+    pub fn height(&self) -> f64 {
+        self.ratio() * self.pixel_height as f64
+    }
+
+    // Which way y goes for a pixel further down
+    fn down(&self) -> f64 {
+        if self.y_up {
+            -1.
+        } else {
+            1.
+        }
+    }
+
+    // Where the corner of a pixel is on the plane
+    pub fn plane(&self, pixel_x: f64, pixel_y: f64) -> (f64, f64) {
+        let ratio = self.ratio();
+        (
+            self.x + (pixel_x - self.pixel_width as f64 / 2.) * ratio,
+            self.y + (pixel_y - self.pixel_height as f64 / 2.) * ratio * self.down(),
+        )
+    }
+
+    // The pixel a point is in, if it's on screen
+    pub fn pixel(&self, (x, y): (f64, f64)) -> Option<(usize, usize)> {
+        let ratio = self.ratio();
+        let (width, height) = (self.pixel_width as f64, self.pixel_height as f64);
+        let px = (x - self.x) / ratio + width / 2.;
+        let py = (y - self.y) / ratio * self.down() + height / 2.;
+        if px < 0. || py < 0. || px >= width || py >= height {
+            return None;
+        }
+        Some((px as usize, py as usize))
+    }
+
+    // Moves by fractions of the view, right and up the screen
+    pub fn translate(&mut self, x_fraction: f64, y_fraction: f64) {
+        self.x += self.width * x_fraction;
+        self.y -= self.height() * y_fraction * self.down();
+    }
+
+    // Zooms so the point under the pixel stays where it is on the screen
+    pub fn zoom_at(&mut self, factor: f64, pixel_x: f64, pixel_y: f64) {
+        let (x, y) = self.plane(pixel_x, pixel_y);
+        self.x += (x - self.x) * (1. - 1. / factor);
+        self.y += (y - self.y) * (1. - 1. / factor);
+        self.width /= factor;
+    }
+
+    pub fn zoom(&mut self, factor: f64) {
+        let (width, height) = (self.pixel_width as f64, self.pixel_height as f64);
+        self.zoom_at(factor, width / 2., height / 2.);
+    }
+
+    // A new window size, keeping the scale and the center
+    pub fn resize(&mut self, pixel_width: u32, pixel_height: u32) {
+        self.width *= pixel_width as f64 / self.pixel_width as f64;
+        self.pixel_width = pixel_width;
+        self.pixel_height = pixel_height;
+    }
+}
+
+// What the ops on a viewport have in common, besides the viewport
+pub struct Controls {
+    pub viewport: Viewport,
+    pub max_iter: usize,
+    pub bindings: Bindings,
+    pub hud: bool,
+    pub help: bool,
+    // Last known position of the mouse in the window
+    pub mouse: (i32, i32),
+}
+
+impl Controls {
+    pub fn new(viewport: Viewport, max_iter: usize, bindings: Bindings) -> Self {
+        let mouse = (
+            viewport.pixel_width as i32 / 2,
+            viewport.pixel_height as i32 / 2,
+        );
+        Controls {
+            viewport,
+            max_iter,
+            bindings,
+            hud: true,
+            help: false,
+            mouse,
+        }
+    }
+}
+
+pub trait ViewportOp: BasicOpImp {
+    fn controls(&self) -> &Controls;
+    fn controls_mut(&mut self) -> &mut Controls;
+    // The view or max_iter changed, so whatever was drawn is out of date
+    fn reset(&mut self);
+    // The formula key, which every op has its own use for
+    fn next_formula(&mut self);
+    fn hud_lines(&self) -> Vec<String>;
+
+    // Lets the settings window know about a new max iter from the keys
+    fn show_max_iter(&self) {
+        let setting = Setting::MaxIter(self.controls().max_iter);
+        self.get_op().send(MainEvent::Setting(setting));
+    }
+
+    fn show_hud(&self) {
+        let lines = if self.controls().hud {
+            self.hud_lines()
+        } else {
+            vec![]
+        };
+        if let Some(window) = &self.get_op().window {
+            window.lock().unwrap().set_hud(lines);
+        }
+    }
+
+    // Makes new buffers for the new window size
+    fn resize(&mut self, width: u32, height: u32) {
+        let s = self.get_op_mut();
+        s.rect = Rect::new(0, 0, width, height);
+        let buffer1 = Pixels::new(width as usize, height as usize).unwrap();
+        let buffer2 = Pixels::new(width as usize, height as usize).unwrap();
+        s.buffers = [buffer1, buffer2];
+        s.buffer_ind = 0;
+        self.controls_mut().viewport.resize(width, height);
+        self.reset();
+    }
+
+    // Does what a bound key asks. Returns whether it's something that
+    // needs a new frame, and None for keys that don't change the picture.
+    fn act(&mut self, action: Action) -> Option<bool> {
+        let c = self.controls_mut();
+        let (move_amount, scale_coarse) = (c.bindings.move_amount, c.bindings.scale_coarse);
+        let viewport = &mut c.viewport;
+        match action {
+            Action::MoveUp => viewport.translate(0., move_amount),
+            Action::MoveLeft => viewport.translate(-move_amount, 0.),
+            Action::MoveDown => viewport.translate(0., -move_amount),
+            Action::MoveRight => viewport.translate(move_amount, 0.),
+            Action::ZoomIn => viewport.zoom(scale_coarse),
+            Action::ZoomOut => viewport.zoom(1. / scale_coarse),
+            Action::ZoomInDouble => viewport.zoom(2.),
+            Action::ZoomOutDouble => viewport.zoom(0.5),
+            Action::MoreIterations => {
+                c.max_iter <<= 1;
+                self.show_max_iter();
+            }
+            Action::FewerIterations => {
+                c.max_iter = (c.max_iter >> 1).max(1);
+                self.show_max_iter();
+            }
+            Action::NextFormula => {
+                self.next_formula();
+                return Some(true);
+            }
+            Action::ToggleHud => {
+                c.hud = !c.hud;
+                return Some(false);
+            }
+            Action::ToggleHelp => {
+                c.help = !c.help;
+                let lines = if c.help {
+                    c.bindings.help_lines()
+                } else {
+                    vec![]
+                };
+                if let Some(window) = &self.get_op().window {
+                    window.lock().unwrap().set_help(lines);
+                }
+                return None;
+            }
+            Action::ShowSettings => {
+                self.get_op().send(MainEvent::ShowWindows);
+                return None;
+            }
+            // The rest is for the escape time ops
+            _ => return None,
+        }
+        self.reset();
+        Some(true)
+    }
+
+    // The whole of handle_events for these ops
+    fn handle_viewport_events(&mut self) -> bool {
+        use sdl2::event::{Event, WindowEvent};
+        use sdl2::mouse::MouseWheelDirection;
+
+        let s = self.get_op_mut();
+        let list = s.event_list.get_mut().unwrap().split_off(0);
+        let mut ret = false;
+        let mut hud_changed = false;
+
+        for event in list {
+            match event {
+                // Keep going until the picture is done
+                SdlEvent::User(MainEvent::RenderOpFinish(op, _)) if op.try_read().is_err() => {
+                    ret |= !self.is_complete();
+                    hud_changed = true;
+                }
+                SdlEvent::User(MainEvent::Setting(Setting::MaxIter(max_iter))) => {
+                    // Ours coming back, or nothing new
+                    if max_iter == self.controls().max_iter {
+                        continue;
+                    }
+                    self.controls_mut().max_iter = max_iter;
+                    self.reset();
+                    ret = true;
+                    hud_changed = true;
+                }
+                SdlEvent::Event(Event::Window {
+                    win_event: WindowEvent::Resized(wid, hei),
+                    window_id: win_id,
+                    ..
+                }) => {
+                    if win_id != self.get_op().id() {
+                        continue;
+                    }
+                    ViewportOp::resize(self, wid as u32, hei as u32);
+                    ret = true;
+                }
+                SdlEvent::Event(Event::KeyDown {
+                    window_id,
+                    scancode,
+                    keymod,
+                    ..
+                }) => {
+                    if window_id != self.get_op().id() {
+                        continue;
+                    }
+                    let bindings = &self.controls().bindings;
+                    let action = match scancode.and_then(|code| bindings.action(code, keymod)) {
+                        Some(action) => action,
+                        None => continue,
+                    };
+                    if let Some(redraw) = self.act(action) {
+                        ret |= redraw;
+                        hud_changed = true;
+                    }
+                }
+                SdlEvent::Event(Event::MouseMotion {
+                    window_id, x, y, ..
+                }) => {
+                    if window_id != self.get_op().id() {
+                        continue;
+                    }
+                    self.controls_mut().mouse = (x, y);
+                    hud_changed = true;
+                }
+                // Zoom in or out around whatever is under the cursor
+                SdlEvent::Event(Event::MouseWheel {
+                    window_id,
+                    y,
+                    direction,
+                    ..
+                }) => {
+                    if window_id != self.get_op().id() || y == 0 {
+                        continue;
+                    }
+                    let steps = match direction {
+                        MouseWheelDirection::Flipped => -y,
+                        _ => y,
+                    };
+                    let c = self.controls_mut();
+                    let (mouse_x, mouse_y) = c.mouse;
+                    let factor = SCALE_WHEEL.powi(steps);
+                    c.viewport.zoom_at(factor, mouse_x as f64, mouse_y as f64);
+                    self.reset();
+                    ret = true;
+                    hud_changed = true;
+                }
+                _ => (),
+            }
+        }
+        if hud_changed {
+            self.show_hud();
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewport(y_up: bool) -> Viewport {
+        Viewport {
+            x: 3.,
+            y: -1.,
+            width: 2.,
+            pixel_width: 400,
+            pixel_height: 300,
+            y_up,
+        }
+    }
+
+    #[test]
+    fn pixels_and_points_go_back_and_forth() {
+        for &y_up in &[false, true] {
+            let view = viewport(y_up);
+            assert_eq!(view.plane(200., 150.), (3., -1.));
+            for &(x, y) in &[(0, 0), (17, 250), (399, 299)] {
+                // A little into the pixel, so rounding doesn't move it to a neighbor
+                let point = view.plane(x as f64 + 0.5, y as f64 + 0.5);
+                assert_eq!(view.pixel(point), Some((x, y)));
+            }
+            assert_eq!(view.pixel(view.plane(-1., 10.)), None);
+            assert_eq!(view.pixel(view.plane(10., 300.)), None);
+        }
+    }
+
+    #[test]
+    fn y_goes_up_only_when_asked() {
+        let (_, top) = viewport(true).plane(200., 0.);
+        assert!(top > -1.);
+        let (_, top) = viewport(false).plane(200., 0.);
+        assert!(top < -1.);
+    }
+
+    #[test]
+    fn moving_up_shows_what_was_above() {
+        for &y_up in &[false, true] {
+            let mut view = viewport(y_up);
+            let above = view.plane(200., 0.);
+            view.translate(0., 0.5);
+            assert_eq!(view.plane(200., 150.), above);
+            view.translate(0.25, 0.);
+            assert_eq!(view.x, 3.5);
+        }
+    }
+
+    #[test]
+    fn zooming_keeps_the_point_under_the_mouse() {
+        for &y_up in &[false, true] {
+            let mut view = viewport(y_up);
+            let point = view.plane(50., 220.);
+            view.zoom_at(4., 50., 220.);
+            assert_eq!(view.width, 0.5);
+            let (x, y) = view.plane(50., 220.);
+            assert!((x - point.0).abs() < 1e-12 && (y - point.1).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn resizing_keeps_the_scale() {
+        let mut view = viewport(true);
+        let ratio = view.ratio();
+        view.resize(800, 100);
+        assert_eq!((view.ratio(), view.width), (ratio, 4.));
+        assert_eq!((view.x, view.y), (3., -1.));
+    }
+}
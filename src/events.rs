@@ -1,4 +1,4 @@
-use crate::rendering::RenderOpReference;
+use crate::rendering::{FrameStatus, RenderOpReference};
 use crate::windows::Window;
 use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
@@ -10,7 +10,25 @@ use std::sync::{Arc, Mutex};
 pub enum MainEvent {
     Quit(Result<(), String>),
     RenderOpStart(RenderOpReference),
-    RenderOpFinish(RenderOpReference),
+    RenderOpFinish(RenderOpReference, FrameStatus),
+    Setting(Setting),
+    // Brings back the windows that were closed
+    ShowWindows,
+}
+
+// A value changed in the settings window, or in an op that
+// the settings window shows it for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Setting {
+    MaxIter(usize),
+    Bailout(f64),
+    // Shifts the palette by a fraction of its length
+    PaletteOffset(f64),
+    // Stretches the palette over more or fewer iterations
+    PaletteScale(f64),
+    // The fixed point of julia sets
+    JuliaC(f64, f64),
+    Threads(usize),
 }
 
 impl std::fmt::Debug for MainEvent {
@@ -22,8 +40,14 @@ impl std::fmt::Debug for MainEvent {
             Self::RenderOpStart(_) => {
                 write!(f, "MainEvent::RenderOpStart")
             }
-            Self::RenderOpFinish(_) => {
-                write!(f, "MainEvent::RenderOpFinish")
+            Self::RenderOpFinish(_, status) => {
+                write!(f, "MainEvent::RenderOpFinish({:?})", status)
+            }
+            Self::Setting(setting) => {
+                write!(f, "MainEvent::Setting({:?})", setting)
+            }
+            Self::ShowWindows => {
+                write!(f, "MainEvent::ShowWindows")
             }
         }
     }
@@ -38,8 +62,14 @@ impl std::fmt::Display for MainEvent {
             Self::RenderOpStart(_) => {
                 write!(f, "MainEvent::RenderOpStart")
             }
-            Self::RenderOpFinish(_) => {
-                write!(f, "MainEvent::RenderOpFinish")
+            Self::RenderOpFinish(_, status) => {
+                write!(f, "MainEvent::RenderOpFinish({:?})", status)
+            }
+            Self::Setting(setting) => {
+                write!(f, "MainEvent::Setting({:?})", setting)
+            }
+            Self::ShowWindows => {
+                write!(f, "MainEvent::ShowWindows")
             }
         }
     }
@@ -49,8 +79,13 @@ impl std::fmt::Display for MainEvent {
 pub enum SdlEvent {
     Event(Event),
     User(MainEvent),
+    // Nothing happened for IDLE_TIME while an op wanted to know,
+    // so it gets a chance to act on things that depend on time passing
+    Idle,
 }
 
+const IDLE_TIME: u32 = 50;
+
 pub struct EventHandler {
     event_pump: EventPump,
     windows: Vec<Arc<Mutex<Window>>>,
@@ -78,10 +113,21 @@ impl EventHandler {
 
     pub fn handle_events(&mut self) -> Vec<MainEvent> {
         let mut ret = vec![];
-        let event = self.event_pump.wait_event();
         // Change event into sdl_event b/c as_user_event_type
         // can only be done once.
-        let event = Self::transform_event(event);
+        // Only wake up without events when some op is waiting on time
+        let idle = self
+            .render_ops
+            .iter()
+            .any(|op| op.read().unwrap().wants_idle());
+        let event = if idle {
+            match self.event_pump.wait_event_timeout(IDLE_TIME) {
+                Some(event) => Self::transform_event(event),
+                None => SdlEvent::Idle,
+            }
+        } else {
+            Self::transform_event(self.event_pump.wait_event())
+        };
         // println!("{:?}", event);
         // Send events to ops and tell them to handle
         // if applicable
@@ -95,6 +141,10 @@ impl EventHandler {
                 if should_start {
                     ret.push(MainEvent::RenderOpStart(op_ref.clone()));
                 }
+            } else if op.interrupted_by(&event) {
+                // The op is busy with a frame that's now out of date,
+                // starting it again cancels that frame
+                ret.push(MainEvent::RenderOpStart(op_ref.clone()));
             }
         }
         // Handle events
@@ -129,14 +179,47 @@ impl EventHandler {
                         ret.push(MainEvent::Quit(Err("Window Resize Event Fail".to_string())));
                     }
                 }
+                // sdl2 only quits once every window is closed, so closing
+                // the first one quits, and closing any other just hides it
+                Event::Window {
+                    win_event: WindowEvent::Close,
+                    window_id: id,
+                    ..
+                } => {
+                    for (ind, window) in self.windows.iter().enumerate() {
+                        let mut window = window.lock().unwrap();
+                        if *id != window.id() {
+                            continue;
+                        }
+                        if ind == 0 {
+                            ret.push(MainEvent::Quit(Ok(())));
+                        } else {
+                            window.canvas_mut().window_mut().hide();
+                        }
+                    }
+                }
                 // Default, do nothing
                 _ => (),
             },
-            SdlEvent::User(event) => {
-                if let MainEvent::RenderOpFinish(op) = event {
-                    ret.push(MainEvent::RenderOpFinish(op.clone()));
+            SdlEvent::User(event) => match event {
+                MainEvent::RenderOpFinish(op, status) => {
+                    ret.push(MainEvent::RenderOpFinish(op.clone(), *status));
                 }
-            }
+                // The render threads belong to the main thread
+                MainEvent::Setting(Setting::Threads(count)) => {
+                    ret.push(MainEvent::Setting(Setting::Threads(*count)));
+                }
+                MainEvent::ShowWindows => {
+                    for window in &self.windows {
+                        let mut window = window.lock().unwrap();
+                        let window = window.canvas_mut().window_mut();
+                        window.show();
+                        window.raise();
+                    }
+                }
+                _ => (),
+            },
+            SdlEvent::Idle => (),
         }
         ret
     }
@@ -0,0 +1,211 @@
+// Writes rendered frames to image files, picking the format by extension
+use crate::rendering::Pixel;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+pub fn save(path: &str, width: usize, height: usize, pixels: &[Pixel]) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+    let mut out = BufWriter::new(file);
+    let lower = path.to_lowercase();
+    let written = if lower.ends_with(".bmp") {
+        write_bmp(&mut out, width, height, pixels)
+    } else if lower.ends_with(".ppm") {
+        write_ppm(&mut out, width, height, pixels)
+    } else {
+        return Err(format!("Can't tell the image format of {}", path));
+    };
+    written
+        .and_then(|_| out.flush())
+        .map_err(|e| format!("{}: {}", path, e))
+}
+
+// Binary ppm, which is a small header and then the rgb bytes
+fn write_ppm(
+    out: &mut impl Write,
+    width: usize,
+    height: usize,
+    pixels: &[Pixel],
+) -> std::io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", width, height)?;
+    write_rgb(out, &pixels[..width * height])
+}
+
+fn write_rgb(out: &mut impl Write, pixels: &[Pixel]) -> std::io::Result<()> {
+    let mut bytes = Vec::with_capacity(pixels.len() * 3);
+    for &(r, g, b, _) in pixels {
+        bytes.extend_from_slice(&[r, g, b]);
+    }
+    out.write_all(&bytes)
+}
+
+// Writes a binary ppm a few rows at a time, for images too tall
+// to hold in memory all at once
+pub struct PpmWriter {
+    out: BufWriter<File>,
+    path: String,
+    width: usize,
+    // Rows that haven't been written yet
+    rows_left: usize,
+}
+
+impl PpmWriter {
+    pub fn create(path: &str, width: usize, height: usize) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut out = BufWriter::new(file);
+        write!(out, "P6\n{} {}\n255\n", width, height).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(PpmWriter {
+            out,
+            path: path.to_string(),
+            width,
+            rows_left: height,
+        })
+    }
+
+    // Takes whole rows, the next ones down
+    pub fn write_rows(&mut self, pixels: &[Pixel]) -> Result<(), String> {
+        let rows = pixels.len() / self.width;
+        if !pixels.len().is_multiple_of(self.width) || rows > self.rows_left {
+            return Err(format!(
+                "{}: {} pixels don't fit in the rows that are left",
+                self.path,
+                pixels.len()
+            ));
+        }
+        self.rows_left -= rows;
+        write_rgb(&mut self.out, pixels).map_err(|e| format!("{}: {}", self.path, e))
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        if self.rows_left != 0 {
+            return Err(format!(
+                "{}: Ended {} rows short",
+                self.path, self.rows_left
+            ));
+        }
+        self.out
+            .flush()
+            .map_err(|e| format!("{}: {}", self.path, e))
+    }
+}
+
+// Reads back a binary ppm like write_ppm makes, comments and all
+pub fn load_ppm(path: &str) -> Result<(usize, usize, Vec<Pixel>), String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let err = |e: &str| format!("{}: {}", path, e);
+    // The header is four words, and a # starts a comment up to the end of the line
+    let mut words = vec![];
+    let mut pos = 0;
+    while words.len() < 4 {
+        while pos < bytes.len() && bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if pos < bytes.len() && bytes[pos] == b'#' {
+            while pos < bytes.len() && bytes[pos] != b'\n' {
+                pos += 1;
+            }
+            continue;
+        }
+        let start = pos;
+        while pos < bytes.len() && !bytes[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if start == pos {
+            return Err(err("The header is cut short"));
+        }
+        words.push(String::from_utf8_lossy(&bytes[start..pos]).into_owned());
+    }
+    if words[0] != "P6" || words[3] != "255" {
+        return Err(err("Only binary ppm with 8 bit colors can be read"));
+    }
+    let size = |word: &str| {
+        word.parse::<usize>()
+            .map_err(|_| err(&format!("Not a valid size: {}", word)))
+    };
+    let (width, height) = (size(&words[1])?, size(&words[2])?);
+    // One whitespace byte ends the header
+    let data = bytes.get(pos + 1..).unwrap_or(&[]);
+    if data.len() < width * height * 3 {
+        return Err(err("The pixels are cut short"));
+    }
+    let pixels = data
+        .chunks_exact(3)
+        .take(width * height)
+        .map(|rgb| (rgb[0], rgb[1], rgb[2], 255))
+        .collect();
+    Ok((width, height, pixels))
+}
+
+// 24 bit bmp. Rows go from the bottom up, in bgr order,
+// and each one is padded to a multiple of 4 bytes.
+fn write_bmp(
+    out: &mut impl Write,
+    width: usize,
+    height: usize,
+    pixels: &[Pixel],
+) -> std::io::Result<()> {
+    let row_size = (width * 3).div_ceil(4) * 4;
+    let data_size = row_size * height;
+    let header_size = 14 + 40;
+    // File header
+    out.write_all(b"BM")?;
+    out.write_all(&((header_size + data_size) as u32).to_le_bytes())?;
+    out.write_all(&0u32.to_le_bytes())?;
+    out.write_all(&(header_size as u32).to_le_bytes())?;
+    // Info header
+    out.write_all(&40u32.to_le_bytes())?;
+    out.write_all(&(width as i32).to_le_bytes())?;
+    out.write_all(&(height as i32).to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&24u16.to_le_bytes())?;
+    out.write_all(&0u32.to_le_bytes())?;
+    out.write_all(&(data_size as u32).to_le_bytes())?;
+    // 72 dpi, and no color table
+    out.write_all(&2835i32.to_le_bytes())?;
+    out.write_all(&2835i32.to_le_bytes())?;
+    out.write_all(&0u32.to_le_bytes())?;
+    out.write_all(&0u32.to_le_bytes())?;
+    let mut row = vec![0; row_size];
+    for y in (0..height).rev() {
+        for (x, &(r, g, b, _)) in pixels[y * width..(y + 1) * width].iter().enumerate() {
+            row[x * 3..x * 3 + 3].copy_from_slice(&[b, g, r]);
+        }
+        out.write_all(&row)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rows_written_in_bands_read_back_whole() {
+        let path = std::env::temp_dir().join(format!("bands-{}.ppm", std::process::id()));
+        let path = path.to_str().unwrap();
+        let pixels: Vec<Pixel> = (0..12u8).map(|i| (i, i * 2, i * 3, 255)).collect();
+        let mut file = PpmWriter::create(path, 3, 4).unwrap();
+        file.write_rows(&pixels[..3]).unwrap();
+        assert!(file.write_rows(&pixels[..2]).is_err());
+        file.write_rows(&pixels[3..9]).unwrap();
+        file.write_rows(&pixels[9..]).unwrap();
+        assert!(file.write_rows(&pixels[..3]).is_err());
+        file.finish().unwrap();
+        let read = load_ppm(path);
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(read.unwrap(), (3, 4, pixels));
+    }
+
+    #[test]
+    fn missing_rows_are_an_error() {
+        let path = std::env::temp_dir().join(format!("short-{}.ppm", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut file = PpmWriter::create(path, 2, 2).unwrap();
+        file.write_rows(&[(0, 0, 0, 255); 2]).unwrap();
+        let finished = file.finish();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            finished.unwrap_err(),
+            format!("{}: Ended 1 rows short", path)
+        );
+    }
+}
@@ -1,54 +1,129 @@
 extern crate sdl2;
+mod cli;
 mod drawing;
 mod events;
+mod image;
+mod numbers;
 mod rendering;
 mod windows;
 
-use events::MainEvent;
-use rendering::{main_loop, ThreadMessage};
+use events::{MainEvent, Setting};
+use rendering::{main_loop, render_still, FrameStatus, RenderOpReference, ThreadMessage};
 use sdl2::video::WindowPos;
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
 use std::time::Instant;
-use windows::WindowBuilder;
+use windows::{Window, WindowBuilder};
 
 const MAIN_WIDTH: usize = 600;
 const MAIN_HEIGHT: usize = 600;
 
 pub fn main() -> Result<(), String> {
+    let options = match cli::parse(std::env::args().skip(1)) {
+        Ok(cli::Command::Run(options)) => *options,
+        Ok(cli::Command::Video {
+            strip,
+            frames,
+            width,
+            height,
+        }) => return drawing::video_from_strip(&strip, frames, width, height),
+        Ok(cli::Command::Help) => {
+            print!("{}", cli::USAGE);
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("Error: {}\n\nRun with --help to see the options", e);
+            std::process::exit(2);
+        }
+    };
+    let threads = options.threads.unwrap_or(num_cpus::get() * 2);
+    // Without a window to look at, the view is rendered right
+    // into the op's buffer, saved, and the program is done
+    if let Some(path) = &options.output {
+        if options.strip {
+            let mut band = options.clone();
+            drawing::render_strip(&options.session, path, |session| {
+                band.session = session.clone();
+                let op = init_main_op(&band, None);
+                render_still(&op, threads)?;
+                let op = op.read().unwrap();
+                let pixels: &[rendering::Pixel] = op.get_present_buffer().into();
+                Ok(pixels.to_vec())
+            })?;
+        } else {
+            let op = init_main_op(&options, None);
+            render_still(&op, threads)?;
+            let op = op.read().unwrap();
+            let buffer = op.get_present_buffer();
+            let (width, height) = buffer.dimensions();
+            image::save(path, width, height, buffer.into())?;
+        }
+        println!("Saved {}", path);
+        return Ok(());
+    }
     // Call setup functions for sdl2
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let event_system = sdl_context.event().unwrap();
     event_system.register_custom_event::<MainEvent>().unwrap();
+    // A loaded session puts the window back where it was
+    let (main_x, main_y) = match options.session.position {
+        Some((x, y)) => (WindowPos::Positioned(x), WindowPos::Positioned(y)),
+        None => (WindowPos::Centered, WindowPos::Centered),
+    };
     // Call Main Window Init from windows.rs
     let main_window = Arc::new(Mutex::new(
         WindowBuilder::new(
             &video_subsystem,
             "➕Newton's Fractal➕",
-            MAIN_WIDTH as u32,
-            MAIN_HEIGHT as u32,
+            options.session.width,
+            options.session.height,
             |a, b| (a, b),
         )
-        .set_position(WindowPos::Centered, WindowPos::Centered)
+        .set_position(main_x, main_y)
         .set_resizable(true)
         .build()?,
     ));
 
     // Init rendering ops
-    let main_op = drawing::Mandelbrot::init(main_window.clone());
+    let main_op = init_main_op(&options, Some(main_window.clone()));
+    // Sliders for the render settings, just right of the main window
+    let (x, y) = main_window.lock().unwrap().canvas().window().position();
+    let settings_window = Arc::new(Mutex::new(
+        WindowBuilder::new(
+            &video_subsystem,
+            "Settings",
+            drawing::PANEL_WIDTH,
+            drawing::Settings::height(),
+            |a, b| (a, b),
+        )
+        .set_position(
+            WindowPos::Positioned(x + options.session.width as i32),
+            WindowPos::Positioned(y),
+        )
+        .build()?,
+    ));
+    let settings_op = drawing::Settings::init(
+        settings_window.clone(),
+        event_system.event_sender(),
+        &options.session,
+        threads,
+    );
+    let windows = vec![main_window, settings_window];
+    let ops = vec![main_op, settings_op];
 
     // Start rendering thread
     let (tx, rx) = mpsc::channel();
     let rendering_transmitter = event_system.event_sender();
-    let main_thread = thread::spawn(move || main_loop(rendering_transmitter, rx));
+    let main_thread = thread::spawn(move || main_loop(rendering_transmitter, rx, threads));
 
     // Init event watcher
-    let mut event_handler =
-        events::EventHandler::init(&sdl_context, vec![main_window], vec![main_op.clone()])?;
+    let mut event_handler = events::EventHandler::init(&sdl_context, windows, ops.clone())?;
 
     // Send rendering ops
-    tx.send(ThreadMessage::StartOp(main_op.clone())).unwrap();
+    for op in ops {
+        tx.send(ThreadMessage::StartOp(op)).unwrap();
+    }
 
     // Start the event loop, handle all events, and manage rendering ops's
     // status. Also, keep track of and print framerate.
@@ -67,22 +142,53 @@ pub fn main() -> Result<(), String> {
                         .unwrap();
                     return result;
                 }
-                MainEvent::RenderOpFinish(op) => {
+                MainEvent::RenderOpFinish(_, FrameStatus::Cancelled) => {}
+                MainEvent::RenderOpFinish(op, FrameStatus::Finished) => {
                     let op = op.read().unwrap();
-                    let window = op.get_window();
+                    let window = match op.get_window() {
+                        Some(window) => window,
+                        None => continue,
+                    };
                     let mut window_mut = window.lock().unwrap();
-                    window_mut.present(op.get_present_buffer(), *op.get_rect());
+                    window_mut.present(
+                        op.get_present_buffer(),
+                        *op.get_rect(),
+                        op.pending_offset(),
+                    );
                     // Framerate
                     println_framerate(&mut now);
                 }
                 MainEvent::RenderOpStart(op) => {
                     tx.send(ThreadMessage::StartOp(op)).unwrap();
                 }
+                MainEvent::Setting(Setting::Threads(count)) => {
+                    tx.send(ThreadMessage::SetThreads(count)).unwrap();
+                }
+                // The ops and the event handler pick up everything else themselves
+                MainEvent::Setting(_) | MainEvent::ShowWindows => {}
             }
         }
     }
 }
 
+// The op that draws the fractal, in the window if there is one
+fn init_main_op(options: &cli::Options, window: Option<Arc<Mutex<Window>>>) -> RenderOpReference {
+    match options.fractal {
+        cli::Fractal::Mandelbrot => {
+            drawing::Mandelbrot::init(window, &options.session, options.session_file.clone())
+        }
+        cli::Fractal::Buddhabrot => {
+            drawing::Buddhabrot::init(window, &options.session, options.view_given)
+        }
+        cli::Fractal::Lyapunov => drawing::Lyapunov::init(
+            window,
+            &options.session,
+            options.view_given,
+            options.sequence.clone(),
+        ),
+    }
+}
+
 fn println_framerate(instant: &mut Instant) {
     let time_elapsed = Instant::elapsed(instant).as_micros();
     *instant = Instant::now();
@@ -0,0 +1,33 @@
+// Reading numbers typed in by hand, on the command line or in one of the
+// text files. Errors only say what's wrong with the number, whoever asked
+// for it puts where it came from in front.
+use std::fmt::Display;
+use std::str::FromStr;
+
+pub fn parse<T: FromStr>(s: &str) -> Result<T, String> {
+    s.trim()
+        .parse()
+        .map_err(|_| format!("Not a valid number: {}", s))
+}
+
+// Keeps out inf and NaN, which parse fine as f64
+pub fn real(s: &str) -> Result<f64, String> {
+    parse(s).and_then(finite)
+}
+
+pub fn finite(x: f64) -> Result<f64, String> {
+    if x.is_finite() {
+        Ok(x)
+    } else {
+        Err(format!("Not a finite number: {}", x))
+    }
+}
+
+// Also keeps out NaN, which compares false with everything
+pub fn positive<T: PartialOrd + Default + Display>(x: T) -> Result<T, String> {
+    if x > T::default() {
+        Ok(x)
+    } else {
+        Err(format!("Has to be more than 0, got {}", x))
+    }
+}
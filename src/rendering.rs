@@ -1,90 +1,216 @@
 // Holds all the drawing logic, like the graph rendering and the settings display
 pub use render_backend::{
-    main::main_loop, pixels::Pixels, Pixel, PixelSlice, RenderOp, RenderOpReference, ThreadMessage,
+    main::{main_loop, render_still},
+    pixels::{Pixels, Tile},
+    CancelToken, FrameStatus, Pixel, RenderOp, RenderOpReference, RenderStats, ThreadMessage,
 };
 
 mod render_backend {
     use crate::events::SdlEvent;
     use crate::windows::Window;
-    use pixels::Pixels;
+    use pixels::{Pixels, Tile};
     use sdl2::rect::Rect;
-    use std::sync::{Arc, Mutex, RwLock};
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    };
+    use std::time::Duration;
     pub type Pixel = (u8, u8, u8, u8);
-    pub type PixelSlice<'a> = (&'a mut [Pixel], usize, usize);
     pub type RenderOpReference = Arc<RwLock<Box<dyn RenderOp + Send>>>;
 
+    // Width and height of the squares a frame is split into. Render threads
+    // take them one at a time, so no thread sits idle while others still have
+    // a big chunk of work left.
+    pub const TILE_SIZE: usize = 64;
+
     pub trait RenderOp: Sync {
-        fn get_window(&self) -> Arc<Mutex<Window>>;
+        fn get_window(&self) -> Option<Arc<Mutex<Window>>>;
         fn get_rect(&self) -> &Rect;
         fn get_present_buffer(&self) -> &Pixels;
         fn get_draw_buffer(&self) -> &Pixels;
         fn swap_buffers(&mut self);
-        fn get_slice<'a>(&self, ind: usize, max: usize) -> PixelSlice<'a> {
-            let pixels = self.get_draw_buffer();
-            let (slice, ind) = unsafe { pixels.get_slice(ind, max) };
-            let pitch = pixels.dimensions().0;
-            (slice, ind, pitch)
+        fn tile_count(&self) -> usize {
+            self.get_draw_buffer().tile_count(TILE_SIZE)
+        }
+        fn get_tile<'a>(&self, ind: usize) -> Tile<'a> {
+            unsafe { self.get_draw_buffer().get_tile(ind, TILE_SIZE) }
         }
-        fn draw(&self, pixels: &mut [Pixel], ind: usize, pitch: usize);
+        fn draw(&self, tile: &mut Tile, token: &CancelToken);
         fn modify_data(&mut self);
         fn handle_events(&mut self) -> bool;
+        // Whether the event should abort the frame that's being rendered
+        fn interrupted_by(&self, event: &SdlEvent) -> bool;
         fn push_event(&self, event: SdlEvent);
         fn set_open(&self, state: bool);
         fn get_open(&self) -> bool;
+        fn get_stats(&self) -> &RenderStats;
+        fn set_stats(&mut self, stats: RenderStats);
+        // Whether the last finished frame was the final one for the current view
+        fn is_complete(&self) -> bool;
+        // How far the view was dragged since the frame being rendered started
+        fn pending_offset(&self) -> (i32, i32);
+        // Whether the op has to see time pass even when nothing happens
+        fn wants_idle(&self) -> bool;
     }
 
     pub enum ThreadMessage {
         StartOp(RenderOpReference),
+        // Replaces the render threads with this many new ones
+        SetThreads(usize),
         Quit,
     }
 
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    pub enum FrameStatus {
+        Finished,
+        Cancelled,
+    }
+
+    // Shared between the render threads working on one frame,
+    // so the frame can be dropped part way through
+    #[derive(Clone, Default)]
+    pub struct CancelToken(Arc<AtomicBool>);
+
+    impl CancelToken {
+        pub fn cancel(&self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+        pub fn is_cancelled(&self) -> bool {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    // How the last finished frame went
+    #[derive(Clone, Debug, Default)]
+    pub struct RenderStats {
+        pub frame_time: Duration,
+        // Time each render thread spent drawing, and how many tiles it drew
+        pub busy: Vec<Duration>,
+        pub tiles: Vec<usize>,
+    }
+
+    impl std::fmt::Display for RenderStats {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let min = self.busy.iter().min().copied().unwrap_or_default();
+            let max = self.busy.iter().max().copied().unwrap_or_default();
+            let total: Duration = self.busy.iter().sum();
+            let avg = total / self.busy.len().max(1) as u32;
+            write!(
+                f,
+                "Frame: {}ms, Busy: min {}ms, avg {}ms, max {}ms",
+                self.frame_time.as_millis(),
+                min.as_millis(),
+                avg.as_millis(),
+                max.as_millis()
+            )
+        }
+    }
+
     pub mod main {
-        use super::threading::{end_threads, start_threads};
-        use super::ThreadMessage;
+        use super::threading::{end_threads, start_threads, TileQueue};
+        use super::{CancelToken, FrameStatus, RenderOpReference, RenderStats, ThreadMessage};
         use crate::events::MainEvent;
         use sdl2::event::EventSender;
-        use std::sync::mpsc::Receiver;
+        use std::collections::VecDeque;
+        use std::sync::mpsc::{Receiver, RecvTimeoutError};
+        use std::sync::Arc;
+        use std::time::{Duration, Instant};
+
+        // How often messages are checked while waiting on a frame
+        const POLL_TIME: Duration = Duration::from_millis(2);
 
         pub fn main_loop(
             sender: EventSender,
             receiver: Receiver<ThreadMessage>,
+            threads: usize,
         ) -> Result<(), String> {
             // Initialize all variables
-            let cores = num_cpus::get() * 2;
-            let (handles, senders, receivers) = start_threads(cores)?;
+            let (mut handles, mut senders, mut receivers) = start_threads(threads)?;
+            // Messages that came in while a frame was rendering
+            let mut pending = VecDeque::new();
             // Start main loop
             'main: loop {
                 // Wait for the window to give up control on the textures
-                match receiver.recv() {
+                let message = match pending.pop_front() {
+                    Some(message) => Ok(message),
+                    None => receiver.recv(),
+                };
+                match message {
                     Ok(ThreadMessage::StartOp(op)) => {
+                        let start = Instant::now();
                         // Set op as closed
-                        {
+                        let tile_count = {
                             let op_read = op.read().unwrap();
                             op_read.set_open(false);
-                        }
-                        // Start Render
+                            op_read.tile_count()
+                        };
+                        // Start Render, every thread pulls tiles
+                        // from the same queue until it's empty
+                        let token = CancelToken::default();
+                        let queue = Arc::new(TileQueue::new(tile_count));
                         for sender in &senders {
-                            sender.send(Some(op.clone())).unwrap();
+                            sender
+                                .send(Some((op.clone(), token.clone(), queue.clone())))
+                                .unwrap();
                         }
-                        // Wait for threads to finish
-                        for receiver in &receivers {
-                            receiver.recv().unwrap();
+                        // Wait for threads to finish, and keep an eye out for
+                        // new messages so the frame can be cancelled
+                        let mut stats = RenderStats::default();
+                        for thread_receiver in &receivers {
+                            loop {
+                                match thread_receiver.recv_timeout(POLL_TIME) {
+                                    Ok((busy, tiles)) => {
+                                        stats.busy.push(busy);
+                                        stats.tiles.push(tiles);
+                                        break;
+                                    }
+                                    Err(RecvTimeoutError::Timeout) => {
+                                        cancel_if_stale(
+                                            &receiver,
+                                            &op,
+                                            &mut pending,
+                                            &token,
+                                            &queue,
+                                        );
+                                    }
+                                    Err(RecvTimeoutError::Disconnected) => {
+                                        return Err("A render thread stopped!".to_string())
+                                    }
+                                }
+                            }
                         }
+                        check_messages(&receiver, &op, &mut pending);
+                        let status = if token.is_cancelled() {
+                            FrameStatus::Cancelled
+                        } else {
+                            FrameStatus::Finished
+                        };
                         // Modify data
                         {
                             let mut op_mut = op.write().unwrap();
-                            // Allow drawing logic to modify data
-                            op_mut.modify_data();
-                            // Swap the buffers
-                            op_mut.swap_buffers();
+                            // A cancelled frame is only partly drawn,
+                            // so it can't be shown
+                            if status == FrameStatus::Finished {
+                                // Allow drawing logic to modify data
+                                op_mut.modify_data();
+                                // Swap the buffers
+                                op_mut.swap_buffers();
+                                stats.frame_time = start.elapsed();
+                                op_mut.set_stats(stats);
+                            }
                             // Set op to open
                             op_mut.set_open(true);
                         }
                         // Let main thread know we're done
                         sender
-                            .push_custom_event(MainEvent::RenderOpFinish(op.clone()))
+                            .push_custom_event(MainEvent::RenderOpFinish(op.clone(), status))
                             .unwrap();
                     }
+                    // No frame is rendering here, so the threads are all idle
+                    Ok(ThreadMessage::SetThreads(count)) => {
+                        end_threads(senders, handles)?;
+                        (handles, senders, receivers) = start_threads(count)?;
+                    }
                     Ok(ThreadMessage::Quit) => {
                         break 'main;
                     }
@@ -98,19 +224,252 @@ mod render_backend {
             // We are stopping, so all sub threads need to stop too
             end_threads(senders, handles)
         }
+
+        // Renders the op frame after frame until its view is complete, for
+        // when there's no window to show it in or events to wait on
+        pub fn render_still(op: &RenderOpReference, threads: usize) -> Result<(), String> {
+            let (handles, senders, receivers) = start_threads(threads)?;
+            loop {
+                let start = Instant::now();
+                let tile_count = op.read().unwrap().tile_count();
+                let token = CancelToken::default();
+                let queue = Arc::new(TileQueue::new(tile_count));
+                for sender in &senders {
+                    sender
+                        .send(Some((op.clone(), token.clone(), queue.clone())))
+                        .unwrap();
+                }
+                let mut stats = RenderStats::default();
+                for thread_receiver in &receivers {
+                    let (busy, tiles) = thread_receiver
+                        .recv()
+                        .map_err(|_| "A render thread stopped!".to_string())?;
+                    stats.busy.push(busy);
+                    stats.tiles.push(tiles);
+                }
+                let mut op_mut = op.write().unwrap();
+                op_mut.modify_data();
+                op_mut.swap_buffers();
+                stats.frame_time = start.elapsed();
+                op_mut.set_stats(stats);
+                if op_mut.is_complete() {
+                    break;
+                }
+            }
+            end_threads(senders, handles)
+        }
+
+        // Cancels the frame if it went out of date. Once every tile is handed
+        // out the frame is as good as done though, so it's left to finish.
+        fn cancel_if_stale(
+            receiver: &Receiver<ThreadMessage>,
+            op: &RenderOpReference,
+            pending: &mut VecDeque<ThreadMessage>,
+            token: &CancelToken,
+            queue: &TileQueue,
+        ) {
+            if check_messages(receiver, op, pending) && !queue.is_empty() {
+                token.cancel();
+            }
+        }
+
+        // A new start for the op that's rendering means the frame is already
+        // out of date, and the op asks for a new frame itself once it has
+        // handled whatever changed, so the start is dropped. Returns whether
+        // there was one, or a quit, so the frame can be cancelled. Everything
+        // else waits its turn.
+        fn check_messages(
+            receiver: &Receiver<ThreadMessage>,
+            op: &RenderOpReference,
+            pending: &mut VecDeque<ThreadMessage>,
+        ) -> bool {
+            let mut stale = false;
+            while let Ok(message) = receiver.try_recv() {
+                match message {
+                    ThreadMessage::StartOp(new_op) if Arc::ptr_eq(&new_op, op) => stale = true,
+                    ThreadMessage::Quit => {
+                        stale = true;
+                        pending.push_back(ThreadMessage::Quit);
+                    }
+                    message => pending.push_back(message),
+                }
+            }
+            stale
+        }
+
+        #[cfg(test)]
+        mod tests {
+            use super::super::pixels::{Pixels, Tile};
+            use super::super::RenderOp;
+            use super::*;
+            use crate::events::SdlEvent;
+            use crate::windows::Window;
+            use sdl2::rect::Rect;
+            use std::sync::mpsc::channel;
+            use std::sync::{Mutex, RwLock};
+
+            // Only ever compared by identity, never rendered
+            struct Stub;
+
+            impl RenderOp for Stub {
+                fn get_window(&self) -> Option<Arc<Mutex<Window>>> {
+                    None
+                }
+                fn get_rect(&self) -> &Rect {
+                    unimplemented!()
+                }
+                fn get_present_buffer(&self) -> &Pixels {
+                    unimplemented!()
+                }
+                fn get_draw_buffer(&self) -> &Pixels {
+                    unimplemented!()
+                }
+                fn swap_buffers(&mut self) {}
+                fn draw(&self, _tile: &mut Tile, _token: &CancelToken) {}
+                fn modify_data(&mut self) {}
+                fn handle_events(&mut self) -> bool {
+                    false
+                }
+                fn interrupted_by(&self, _event: &SdlEvent) -> bool {
+                    false
+                }
+                fn push_event(&self, _event: SdlEvent) {}
+                fn set_open(&self, _state: bool) {}
+                fn get_open(&self) -> bool {
+                    true
+                }
+                fn get_stats(&self) -> &RenderStats {
+                    unimplemented!()
+                }
+                fn set_stats(&mut self, _stats: RenderStats) {}
+                fn is_complete(&self) -> bool {
+                    false
+                }
+                fn pending_offset(&self) -> (i32, i32) {
+                    (0, 0)
+                }
+                fn wants_idle(&self) -> bool {
+                    false
+                }
+            }
+
+            fn op() -> RenderOpReference {
+                Arc::new(RwLock::new(Box::new(Stub)))
+            }
+
+            fn kinds(pending: &VecDeque<ThreadMessage>) -> Vec<String> {
+                let kind = |message: &ThreadMessage| match message {
+                    ThreadMessage::StartOp(_) => "start".to_string(),
+                    ThreadMessage::SetThreads(count) => format!("threads {}", count),
+                    ThreadMessage::Quit => "quit".to_string(),
+                };
+                pending.iter().map(kind).collect()
+            }
+
+            #[test]
+            fn tokens_are_shared_by_their_clones() {
+                let token = CancelToken::default();
+                let other = CancelToken::default();
+                let clone = token.clone();
+                assert!(!clone.is_cancelled());
+                token.cancel();
+                assert!(clone.is_cancelled());
+                assert!(!other.is_cancelled());
+            }
+
+            #[test]
+            fn starting_the_same_op_again_is_dropped_as_stale() {
+                let (rendering, other) = (op(), op());
+                let (sender, receiver) = channel();
+                let mut pending = VecDeque::new();
+                assert!(!check_messages(&receiver, &rendering, &mut pending));
+
+                sender.send(ThreadMessage::StartOp(other)).unwrap();
+                sender.send(ThreadMessage::SetThreads(3)).unwrap();
+                assert!(!check_messages(&receiver, &rendering, &mut pending));
+                assert_eq!(kinds(&pending), vec!["start", "threads 3"]);
+
+                sender
+                    .send(ThreadMessage::StartOp(rendering.clone()))
+                    .unwrap();
+                sender
+                    .send(ThreadMessage::StartOp(rendering.clone()))
+                    .unwrap();
+                assert!(check_messages(&receiver, &rendering, &mut pending));
+                assert_eq!(kinds(&pending), vec!["start", "threads 3"]);
+
+                // A quit also ends the frame, but still has to be seen
+                sender.send(ThreadMessage::Quit).unwrap();
+                assert!(check_messages(&receiver, &rendering, &mut pending));
+                assert_eq!(kinds(&pending), vec!["start", "threads 3", "quit"]);
+            }
+
+            #[test]
+            fn only_frames_with_tiles_left_are_cancelled() {
+                let op = op();
+                let (sender, receiver) = channel();
+                let mut pending = VecDeque::new();
+                let queue = TileQueue::new(2);
+                let token = CancelToken::default();
+                // Nothing new, so the frame goes on
+                cancel_if_stale(&receiver, &op, &mut pending, &token, &queue);
+                assert!(!token.is_cancelled());
+                sender.send(ThreadMessage::StartOp(op.clone())).unwrap();
+                cancel_if_stale(&receiver, &op, &mut pending, &token, &queue);
+                assert!(token.is_cancelled());
+
+                // Every tile is being drawn, so it's left to finish
+                let token = CancelToken::default();
+                while queue.take().is_some() {}
+                sender.send(ThreadMessage::StartOp(op.clone())).unwrap();
+                cancel_if_stale(&receiver, &op, &mut pending, &token, &queue);
+                assert!(!token.is_cancelled());
+                assert!(pending.is_empty());
+            }
+        }
     }
 
     pub mod threading {
         use super::drawing::draw_loop;
-        use super::RenderOpReference;
+        use super::{CancelToken, RenderOpReference};
+        use std::sync::atomic::{AtomicUsize, Ordering};
         use std::sync::mpsc::{channel, Receiver, Sender};
+        use std::sync::Arc;
         use std::thread::{Builder, JoinHandle};
+        use std::time::Duration;
+
+        pub type Job = Option<(RenderOpReference, CancelToken, Arc<TileQueue>)>;
+        // Time spent drawing and the number of tiles drawn
+        pub type Report = (Duration, usize);
+        type Thread = (Vec<JoinHandle<()>>, Vec<Sender<Job>>, Vec<Receiver<Report>>);
+
+        // Hands out the tiles of a frame, each one exactly once
+        pub struct TileQueue {
+            next: AtomicUsize,
+            count: usize,
+        }
+
+        impl TileQueue {
+            pub fn new(count: usize) -> Self {
+                TileQueue {
+                    next: AtomicUsize::new(0),
+                    count,
+                }
+            }
+            // Whether every tile was handed out already
+            pub fn is_empty(&self) -> bool {
+                self.next.load(Ordering::Relaxed) >= self.count
+            }
+            pub fn take(&self) -> Option<usize> {
+                let ind = self.next.fetch_add(1, Ordering::Relaxed);
+                if ind < self.count {
+                    Some(ind)
+                } else {
+                    None
+                }
+            }
+        }
 
-        type Thread = (
-            Vec<JoinHandle<()>>,
-            Vec<Sender<Option<RenderOpReference>>>,
-            Vec<Receiver<()>>,
-        );
         pub fn start_threads(thread_count: usize) -> Result<Thread, String> {
             let mut handles = Vec::with_capacity(thread_count);
             let mut receivers = Vec::with_capacity(thread_count);
@@ -121,7 +480,7 @@ mod render_backend {
                 let handle = Builder::new()
                     .name(format!("Render Thread {}", id))
                     .spawn(move || {
-                        draw_loop(ttx, trx, id, thread_count);
+                        draw_loop(ttx, trx);
                     })
                     .unwrap();
                 senders.push(tx);
@@ -132,7 +491,7 @@ mod render_backend {
         }
 
         pub fn end_threads(
-            senders: Vec<Sender<Option<RenderOpReference>>>,
+            senders: Vec<Sender<Job>>,
             handles: Vec<JoinHandle<()>>,
         ) -> Result<(), String> {
             for sender in senders {
@@ -143,27 +502,73 @@ mod render_backend {
             }
             Ok(())
         }
+
+        #[cfg(test)]
+        mod tests {
+            use super::*;
+
+            #[test]
+            fn tiles_are_handed_out_in_order_until_none_are_left() {
+                let queue = TileQueue::new(3);
+                assert!(!queue.is_empty());
+                assert_eq!(queue.take(), Some(0));
+                assert_eq!(queue.take(), Some(1));
+                assert!(!queue.is_empty());
+                assert_eq!(queue.take(), Some(2));
+                // The last tile is out, even if it isn't drawn yet
+                assert!(queue.is_empty());
+                assert_eq!(queue.take(), None);
+                assert_eq!(queue.take(), None);
+                assert!(queue.is_empty());
+
+                let empty = TileQueue::new(0);
+                assert!(empty.is_empty());
+                assert_eq!(empty.take(), None);
+            }
+
+            #[test]
+            fn threads_never_get_the_same_tile() {
+                let count = 10_000;
+                let queue = TileQueue::new(count);
+                let taken: Vec<Vec<usize>> = std::thread::scope(|scope| {
+                    let handles: Vec<_> = (0..8)
+                        .map(|_| scope.spawn(|| std::iter::from_fn(|| queue.take()).collect()))
+                        .collect();
+                    handles.into_iter().map(|h| h.join().unwrap()).collect()
+                });
+                let mut all: Vec<usize> = taken.into_iter().flatten().collect();
+                all.sort_unstable();
+                assert_eq!(all, (0..count).collect::<Vec<_>>());
+                assert!(queue.is_empty());
+            }
+        }
     }
 
     pub mod drawing {
-        use super::RenderOpReference;
+        use super::threading::{Job, Report};
         use std::sync::mpsc::{Receiver, Sender};
+        use std::time::{Duration, Instant};
 
-        pub fn draw_loop(
-            sender: Sender<()>,
-            receiver: Receiver<Option<RenderOpReference>>,
-            id: usize,
-            thread_count: usize,
-        ) {
-            while let Ok(Some(op)) = receiver.recv() {
+        pub fn draw_loop(sender: Sender<Report>, receiver: Receiver<Job>) {
+            while let Ok(Some((op, token, queue))) = receiver.recv() {
                 let op = op.read().unwrap();
-                // Get slice
-                let (slice, ind, pitch) = op.get_slice(id, thread_count);
-                // Modify pixels
-                op.draw(slice, ind, pitch);
+                let mut busy = Duration::ZERO;
+                let mut tiles = 0;
+                // Keep taking tiles until there are none left,
+                // or the frame was cancelled
+                while let Some(ind) = queue.take() {
+                    if token.is_cancelled() {
+                        break;
+                    }
+                    let start = Instant::now();
+                    let mut tile = op.get_tile(ind);
+                    op.draw(&mut tile, &token);
+                    busy += start.elapsed();
+                    tiles += 1;
+                }
                 // Release read and let main thread know
                 drop(op);
-                sender.send(()).unwrap();
+                sender.send((busy, tiles)).unwrap();
             }
         }
     }
@@ -171,6 +576,8 @@ mod render_backend {
     pub mod pixels {
         use super::Pixel;
         use std::alloc::{alloc, dealloc, Layout, LayoutError};
+        use std::marker::PhantomData;
+        use std::ops::{Index, IndexMut};
         use std::slice::from_raw_parts_mut;
 
         pub struct Pixels {
@@ -207,23 +614,71 @@ mod render_backend {
                 (self.width, self.height)
             }
 
-            pub unsafe fn get_slice<'a>(&self, ind: usize, max: usize) -> (&'a mut [Pixel], usize) {
-                if self.len == 0 {
-                    return (&mut [], 0);
+            pub fn tile_count(&self, size: usize) -> usize {
+                self.width.div_ceil(size) * self.height.div_ceil(size)
+            }
+
+            // Tiles are numbered left to right, top to bottom. The ones on the
+            // right and bottom edges can be smaller than size.
+            // Unsafe because two tiles with the same ind must never be alive at once.
+            pub unsafe fn get_tile<'a>(&self, ind: usize, size: usize) -> Tile<'a> {
+                assert!(ind < self.tile_count(size));
+                let across = self.width.div_ceil(size);
+                let x = (ind % across) * size;
+                let y = (ind / across) * size;
+                Tile {
+                    ptr: self.ptr as *mut Pixel,
+                    pitch: self.width,
+                    x,
+                    y,
+                    width: size.min(self.width - x),
+                    height: size.min(self.height - y),
+                    _marker: PhantomData,
                 }
-                assert!(ind < max);
-                let offset = 4 * ((self.len / 4) / max);
-                let ptr = self.ptr.add(offset * ind);
-                let len;
-                if ind == max - 1 {
-                    len = self.len - (offset * ind);
-                } else {
-                    len = offset;
+            }
+        }
+
+        // A rectangle of pixels in a buffer that a single thread can draw to.
+        // Indexed with coordinates relative to its top left corner.
+        pub struct Tile<'a> {
+            ptr: *mut Pixel,
+            pitch: usize,
+            x: usize,
+            y: usize,
+            width: usize,
+            height: usize,
+            _marker: PhantomData<&'a mut [Pixel]>,
+        }
+
+        impl Tile<'_> {
+            // Position of the top left corner in the whole buffer
+            pub fn position(&self) -> (usize, usize) {
+                (self.x, self.y)
+            }
+            pub fn dimensions(&self) -> (usize, usize) {
+                (self.width, self.height)
+            }
+            pub fn row_mut(&mut self, y: usize) -> &mut [Pixel] {
+                assert!(y < self.height);
+                unsafe {
+                    let ptr = self.ptr.add((self.y + y) * self.pitch + self.x);
+                    from_raw_parts_mut(ptr, self.width)
                 }
-                (
-                    from_raw_parts_mut(ptr as *mut (u8, u8, u8, u8), len / 4),
-                    (offset * ind / 4),
-                )
+            }
+        }
+
+        impl Index<(usize, usize)> for Tile<'_> {
+            type Output = Pixel;
+            fn index(&self, (x, y): (usize, usize)) -> &Pixel {
+                assert!(x < self.width && y < self.height);
+                unsafe { &*self.ptr.add((self.y + y) * self.pitch + self.x + x) }
+            }
+        }
+
+        impl IndexMut<(usize, usize)> for Tile<'_> {
+            fn index_mut(&mut self, (x, y): (usize, usize)) -> &mut Pixel {
+                assert!(x < self.width && y < self.height);
+                unsafe { &mut *self.ptr.add((self.y + y) * self.pitch + self.x + x) }
             }
         }
 
@@ -258,5 +713,34 @@ mod render_backend {
                 };
             }
         }
+
+        #[cfg(test)]
+        mod tests {
+            use super::*;
+
+            #[test]
+            fn tiles_cover_every_pixel_once() {
+                let (width, height, size) = (150, 70, 64);
+                let pixels = Pixels::new(width, height).unwrap();
+                assert_eq!(pixels.tile_count(size), 3 * 2);
+                let mut covered = vec![0; width * height];
+                for ind in 0..pixels.tile_count(size) {
+                    let tile = unsafe { pixels.get_tile(ind, size) };
+                    let (x, y) = tile.position();
+                    let (w, h) = tile.dimensions();
+                    // Left to right, then top to bottom
+                    assert_eq!((x, y), (ind % 3 * size, ind / 3 * size));
+                    for py in y..y + h {
+                        for px in x..x + w {
+                            covered[py * width + px] += 1;
+                        }
+                    }
+                }
+                assert!(covered.iter().all(|&c| c == 1));
+                // The ones on the edges are cut to the buffer
+                let corner = unsafe { pixels.get_tile(5, size) };
+                assert_eq!(corner.dimensions(), (150 - 128, 70 - 64));
+            }
+        }
     }
 }
@@ -1,9 +1,16 @@
 // All Used Windows
-use sdl2::pixels::PixelFormatEnum;
+pub mod font;
+
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::Rect;
-use sdl2::render::{Canvas, RenderTarget, Texture};
+use sdl2::render::{BlendMode, Canvas, RenderTarget, Texture};
 use sdl2::video::{Window as SDL2Window, WindowPos};
 
+// Every pixel of the font is drawn as a square this big
+const TEXT_SCALE: u32 = 2;
+// Space around text, and between its lines, in font pixels
+const TEXT_MARGIN: u32 = 2;
+
 pub trait ResizeFn: Fn(usize, usize) -> (usize, usize) {}
 impl<T: Fn(usize, usize) -> (usize, usize)> ResizeFn for T {}
 
@@ -99,6 +106,14 @@ pub struct Window {
     height: usize,
     id: u32,
     resize_func: Box<dyn ResizeFn>,
+    // Where the texture is drawn, relative to the top left of the window
+    offset: (i32, i32),
+    // Outline drawn over the texture, like a box being dragged out
+    selection: Option<Rect>,
+    // Lines of text shown in the top left corner
+    hud: Vec<String>,
+    // Lines of text shown in the middle, over everything else
+    help: Vec<String>,
 }
 #[allow(dead_code)]
 impl Window {
@@ -155,6 +170,10 @@ impl Window {
             texture,
             id,
             resize_func: builder.resize_func,
+            offset: (0, 0),
+            selection: None,
+            hud: vec![],
+            help: vec![],
         })
     }
     pub fn canvas(&self) -> &Canvas<SDL2Window> {
@@ -184,15 +203,115 @@ impl Window {
         self.texture = Self::make_texture(self.canvas_mut(), w, h)?;
         Ok(())
     }
-    pub fn present<'a, T: Into<&'a [u8]>>(&mut self, pixels: T, rect: Rect) {
+    // The offset is how far the view was dragged since the pixels were drawn
+    pub fn present<'a, T: Into<&'a [u8]>>(&mut self, pixels: T, rect: Rect, offset: (i32, i32)) {
         let data = pixels.into();
         let _b = &data;
         self.texture
             .update(rect, data, rect.width() as usize * 4)
             .unwrap();
+        self.offset = offset;
+        self.redraw();
+    }
+    // Moves the last presented pixels without needing new ones
+    pub fn set_offset(&mut self, x: i32, y: i32) {
+        self.offset = (x, y);
+        self.redraw();
+    }
+    pub fn set_selection(&mut self, selection: Option<Rect>) {
+        self.selection = selection;
+        self.redraw();
+    }
+    // No lines hides the hud
+    pub fn set_hud(&mut self, lines: Vec<String>) {
+        self.hud = lines;
+        self.redraw();
+    }
+    pub fn set_help(&mut self, lines: Vec<String>) {
+        self.help = lines;
+        self.redraw();
+    }
+    fn redraw(&mut self) {
         let texture = unsafe { &*(&self.texture as *const Texture) };
-        self.canvas_mut().copy(texture, rect, rect).unwrap();
-        self.canvas_mut().present();
+        let (x, y) = self.offset;
+        let (width, height) = (self.width as u32, self.height as u32);
+        let selection = self.selection;
+        let hud = std::mem::take(&mut self.hud);
+        let help = std::mem::take(&mut self.help);
+        let canvas = self.canvas_mut();
+        // Whatever the texture doesn't cover after moving is left black
+        if (x, y) != (0, 0) {
+            canvas.set_draw_color(Color::BLACK);
+            canvas.clear();
+        }
+        canvas
+            .copy(
+                texture,
+                Rect::new(0, 0, width, height),
+                Rect::new(x, y, width, height),
+            )
+            .unwrap();
+        if let Some(rect) = selection {
+            canvas.set_draw_color(Color::WHITE);
+            canvas.draw_rect(rect).unwrap();
+        }
+        Self::draw_text(canvas, &hud, 0, 0);
+        let (help_width, help_height) = Self::text_size(&help);
+        Self::draw_text(
+            canvas,
+            &help,
+            (width as i32 - help_width as i32) / 2,
+            (height as i32 - help_height as i32) / 2,
+        );
+        canvas.present();
+        self.hud = hud;
+        self.help = help;
+    }
+    // Size of the backdrop behind lines of text
+    fn text_size(lines: &[String]) -> (u32, u32) {
+        let columns = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0) as u32;
+        (
+            (columns * (font::GLYPH_WIDTH + 1) + TEXT_MARGIN * 2) * TEXT_SCALE,
+            (lines.len() as u32 * (font::GLYPH_HEIGHT + TEXT_MARGIN) + TEXT_MARGIN) * TEXT_SCALE,
+        )
+    }
+    // Draws lines of text with the top left of their backdrop at (x, y)
+    fn draw_text(canvas: &mut Canvas<SDL2Window>, lines: &[String], x: i32, y: i32) {
+        if lines.is_empty() {
+            return;
+        }
+        let advance = font::GLYPH_WIDTH + 1;
+        let line_height = font::GLYPH_HEIGHT + TEXT_MARGIN;
+        // Darken what's behind the text so it can be read on any colors
+        let (backdrop_width, backdrop_height) = Self::text_size(lines);
+        let backdrop = Rect::new(x, y, backdrop_width, backdrop_height);
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+        canvas.fill_rect(backdrop).unwrap();
+        canvas.set_blend_mode(BlendMode::None);
+        // Every lit pixel of every glyph becomes a square
+        let mut squares = vec![];
+        for (row, line) in lines.iter().enumerate() {
+            let top = TEXT_MARGIN + row as u32 * line_height;
+            for (column, c) in line.chars().enumerate() {
+                let left = TEXT_MARGIN + column as u32 * advance;
+                for (glyph_y, bits) in font::glyph(c).iter().enumerate() {
+                    for glyph_x in 0..font::GLYPH_WIDTH {
+                        if bits & (1 << (font::GLYPH_WIDTH - 1 - glyph_x)) == 0 {
+                            continue;
+                        }
+                        squares.push(Rect::new(
+                            x + ((left + glyph_x) * TEXT_SCALE) as i32,
+                            y + ((top + glyph_y as u32) * TEXT_SCALE) as i32,
+                            TEXT_SCALE,
+                            TEXT_SCALE,
+                        ));
+                    }
+                }
+            }
+        }
+        canvas.set_draw_color(Color::WHITE);
+        canvas.fill_rects(&squares).unwrap();
     }
     fn make_texture(
         canvas: &mut Canvas<impl RenderTarget>,